@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+use crate::parsers::ExternalParser;
+
+const MB: usize = 1024 * 1024;
+
+/// Settings of the indexing of a folder, kept in its `.local_search_engine.toml`. The folder may
+/// come from anybody, so the commands it runs only come from the configuration of the user, see `Config::load_folder`:
+///
+/// ```toml
+/// timeout = 30            # seconds an external command may run
+/// max_output = 33554432   # bytes of the output of a command indexed at most
+/// parse_timeout = 60      # seconds any parser may take on a single file
+/// max_memory = 1073741824 # bytes of memory a parser may use with --isolate
+/// max_failures = 3        # crashes after which a file is not parsed again until it changes
+/// max_rejections = 5      # parse errors after which a file is not parsed again until it changes
+/// max_file_size = 268435456 # bytes beyond which files are skipped, whatever their type
+/// max_text = 67108864     # bytes of the text of a document indexed at most, the rest is cut off
+///
+/// [commands]              # only read from the configuration of the user
+/// rst = "pandoc -t plain {}"
+/// doc = "antiword {}"
+///
+/// [limits.csv]            # limits of the files with the extension
+/// max_file_size = 10485760
+/// ```
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Commands that turn the files with the extension into text, see `ExternalParser`
+    pub commands: BTreeMap<String, String>,
+    pub timeout: u64,
+    pub max_output: usize,
+    pub parse_timeout: u64,
+    pub max_memory: Option<u64>,
+    pub max_failures: u32,
+    pub max_rejections: u32,
+    /// Overrides the size limits that depend on the type of the files, see `Config::limits`
+    pub max_file_size: Option<u64>,
+    pub max_text: usize,
+    pub limits: BTreeMap<String, ExtensionLimits>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ExtensionLimits {
+    pub max_file_size: Option<u64>,
+    pub max_text: Option<usize>,
+}
+
+/// How much of a file gets indexed
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Bytes of the files that are skipped beyond
+    pub max_file_size: u64,
+    /// Bytes of text of a document beyond which the rest is left out and the document recorded as `truncated`
+    pub max_text: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            commands: BTreeMap::new(),
+            timeout: 30,
+            max_output: 32 * MB,
+            parse_timeout: 60,
+            max_memory: None,
+            max_failures: 3,
+            max_rejections: 5,
+            max_file_size: None,
+            max_text: 64 * MB,
+            limits: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// The configuration at `path`, or the default one if there is no such file
+    pub fn load(path: &Path) -> Result<Self, ()> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                eprintln!("ERROR: could not read config file {path}: {err}", path = path.display());
+                return Err(());
+            }
+        };
+        toml::from_str(&text).map_err(|err| {
+            eprintln!("ERROR: could not parse config file {path}: {err}", path = path.display());
+        })
+    }
+
+    /// The configuration of the folder, with the commands of the configuration of the user at
+    /// `user_path` and none of its own
+    pub fn load_folder(dir_path: &Path, user_path: Option<&Path>) -> Result<Self, ()> {
+        let path = dir_path.join(".local_search_engine.toml");
+        let mut config = Self::load(&path)?;
+        if !config.commands.is_empty() {
+            println!("WARNING: ignoring the commands of {path}, only the configuration of the user may set commands to run", path = path.display());
+        }
+        config.commands = match user_path {
+            Some(user_path) => Self::load(user_path)?.commands,
+            None => BTreeMap::new(),
+        };
+        Ok(config)
+    }
+
+    /// Where the configuration of the user is: `$XDG_CONFIG_HOME/local_search_engine/config.toml`,
+    /// which is `~/.config/local_search_engine/config.toml` by default
+    pub fn user_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(config_home.join("local_search_engine").join("config.toml"))
+    }
+
+    /// The limits of the file: the ones of its extension, the global ones, then the defaults of its type
+    pub fn limits(&self, file_path: &Path) -> Limits {
+        let extension = file_path.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let configured = self.limits.get(&extension);
+        Limits {
+            max_file_size: configured.and_then(|limits| limits.max_file_size)
+                .or(self.max_file_size)
+                .unwrap_or_else(|| default_max_file_size(&extension)),
+            max_text: configured.and_then(|limits| limits.max_text).unwrap_or(self.max_text),
+        }
+    }
+
+    pub fn parsers(&self) -> impl Iterator<Item = ExternalParser> + '_ {
+        self.commands.iter().map(|(extension, command)| ExternalParser::new(extension, command, self))
+    }
+}
+
+/// Plain text is read a chunk at a time and cut at `max_text`, so that no file of it is too big,
+/// and the files of the formats full of pictures are much bigger than their text
+fn default_max_file_size(extension: &str) -> u64 {
+    let mb = MB as u64;
+    match extension {
+        "txt" | "log" => u64::MAX,
+        "pdf" | "epub" | "docx" | "pptx" | "xlsx" | "odt" | "ods" | "odp" => 1024 * mb,
+        _ => 256 * mb,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(config: &str, file_name: &str) -> (u64, usize) {
+        let config: Config = toml::from_str(config).unwrap();
+        let limits = config.limits(Path::new(file_name));
+        (limits.max_file_size, limits.max_text)
+    }
+
+    #[test]
+    fn defaults_depend_on_the_type() {
+        let mb = MB as u64;
+        assert_eq!(limits("", "notes.txt"), (u64::MAX, 64 * MB));
+        assert_eq!(limits("", "server.log"), (u64::MAX, 64 * MB));
+        assert_eq!(limits("", "paper.PDF"), (1024 * mb, 64 * MB));
+        assert_eq!(limits("", "page.html"), (256 * mb, 64 * MB));
+        assert_eq!(limits("", "Makefile"), (256 * mb, 64 * MB));
+    }
+
+    #[test]
+    fn extension_limits_win_over_global_ones() {
+        let config = "max_file_size = 1000\nmax_text = 100\n[limits.csv]\nmax_file_size = 5000\n[limits.pdf]\nmax_text = 10\n";
+        assert_eq!(limits(config, "data.CSV"), (5000, 100));
+        assert_eq!(limits(config, "paper.pdf"), (1000, 10));
+        assert_eq!(limits(config, "notes.txt"), (1000, 100));
+    }
+
+    #[test]
+    fn commands_only_come_from_the_configuration_of_the_user() {
+        let dir_path = env::temp_dir().join(format!("local_search_engine-test-{}-config", std::process::id()));
+        fs::create_dir_all(&dir_path).unwrap();
+        fs::write(dir_path.join(".local_search_engine.toml"), "max_text = 100\n[commands]\nrst = \"touch planted\"\n").unwrap();
+        let user_path = dir_path.join("config.toml");
+        fs::write(&user_path, "[commands]\nrst = \"pandoc -t plain {}\"\n").unwrap();
+
+        let config = Config::load_folder(&dir_path, Some(&user_path)).unwrap();
+        assert_eq!(config.max_text, 100);
+        assert_eq!(config.commands.get("rst").map(String::as_str), Some("pandoc -t plain {}"));
+        assert!(Config::load_folder(&dir_path, None).unwrap().commands.is_empty());
+        assert!(Config::load_folder(&dir_path, Some(&dir_path.join("missing.toml"))).unwrap().commands.is_empty());
+        let _ = fs::remove_dir_all(dir_path);
+    }
+}
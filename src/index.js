@@ -12,9 +12,19 @@ async function search(prompt) {
     const json = await response.json();
     console.log(json);
     results.innerHTML = '';
-    for (let [path, rank] of json) {
+    for (let [path, rank, title] of json) {
         let item = document.createElement("span");
-        item.appendChild(document.createTextNode(path));
+        item.title = path;
+        let page = path.match(/#page=(\d+)$/);
+        let text = title ? (page ? `${title} (page ${page[1]})` : title) : path;
+        // The server serves the indexed files, members of archives included. The parts of a file, like
+        // the pages of a PDF, are in the fragment of its URL, where viewers of PDFs look for #page=N.
+        let link = document.createElement("a");
+        let fragment = path.match(/(#\w+=\d+)+$/);
+        let file = fragment ? path.slice(0, -fragment[0].length) : path;
+        link.href = "/files/" + file.split("/").map(encodeURIComponent).join("/") + (fragment ? fragment[0] : "");
+        link.appendChild(document.createTextNode(text));
+        item.appendChild(link);
         item.appendChild(document.createElement("br"));
         results.appendChild(item);
     }
@@ -0,0 +1,248 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+use crate::snowball::Stemmer;
+
+mod profiles;
+
+/// How many of the most frequent trigrams of a text are compared against the profiles
+const TEXT_PROFILE_SIZE: usize = 300;
+/// Texts with fewer trigrams than this are too short to guess their language
+const MIN_TRIGRAMS: usize = 20;
+/// How many distinct words per language the stem cache remembers, forgetting the oldest ones first
+const STEM_CACHE_CAPACITY: usize = 100_000;
+/// How many parts the stem cache is split into, each behind a lock of its own
+const STEM_CACHE_SHARDS: usize = 16;
+
+/// Shared by all the threads, as the sandbox parses every file on a thread of its own. The words
+/// are spread over shards so that the threads stemming different words do not wait for each other.
+static STEM_CACHE: LazyLock<[Mutex<StemCache>; STEM_CACHE_SHARDS]> = LazyLock::new(|| std::array::from_fn(|_| Mutex::default()));
+
+/// Bounded memo of the stems of lowercase words, since the same words come up over and over again while indexing
+#[derive(Default)]
+struct StemCache {
+    stems: HashMap<Language, Stems>,
+    buffer: String,
+}
+
+#[derive(Default)]
+struct Stems {
+    stems: HashMap<Arc<str>, Arc<str>>,
+    /// The words in the order they were stemmed, to forget the oldest one when the cache is full
+    order: VecDeque<Arc<str>>,
+}
+
+impl StemCache {
+    fn stem(&mut self, language: Language, word: &str) -> Arc<str> {
+        let Stems { stems, order } = self.stems.entry(language).or_default();
+        if let Some(stem) = stems.get(word) {
+            return Arc::clone(stem);
+        }
+        if stems.len() >= STEM_CACHE_CAPACITY / STEM_CACHE_SHARDS {
+            if let Some(oldest) = order.pop_front() {
+                stems.remove(&oldest);
+            }
+        }
+        language.stemmer().stem_into(word, &mut self.buffer);
+        let stem = Arc::<str>::from(self.buffer.as_str());
+        let word = Arc::<str>::from(word);
+        order.push_back(Arc::clone(&word));
+        stems.insert(word, Arc::clone(&stem));
+        stem
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Portuguese,
+    Dutch,
+    Russian,
+}
+
+impl Language {
+    pub const ALL: &'static [Language] = &[
+        Language::English,
+        Language::German,
+        Language::French,
+        Language::Spanish,
+        Language::Italian,
+        Language::Portuguese,
+        Language::Dutch,
+        Language::Russian,
+    ];
+
+    pub fn code(&self) -> &'static str {
+        self.stemmer().code()
+    }
+
+    pub fn stemmer(&self) -> Stemmer {
+        match self {
+            Language::English => Stemmer::English,
+            Language::German => Stemmer::German,
+            Language::French => Stemmer::French,
+            Language::Spanish => Stemmer::Spanish,
+            Language::Italian => Stemmer::Italian,
+            Language::Portuguese => Stemmer::Portuguese,
+            Language::Dutch => Stemmer::Dutch,
+            Language::Russian => Stemmer::Russian,
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|language| language.code().eq_ignore_ascii_case(code))
+    }
+
+    /// Language of a BCP 47 tag like `en-US`, as found in HTML and e-book metadata
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        Self::from_code(tag.trim().split(['-', '_']).next().unwrap_or(""))
+    }
+
+    fn profile(&self) -> &'static [&'static str] {
+        match self {
+            Language::English => profiles::ENGLISH,
+            Language::German => profiles::GERMAN,
+            Language::French => profiles::FRENCH,
+            Language::Spanish => profiles::SPANISH,
+            Language::Italian => profiles::ITALIAN,
+            Language::Portuguese => profiles::PORTUGUESE,
+            Language::Dutch => profiles::DUTCH,
+            Language::Russian => profiles::RUSSIAN,
+        }
+    }
+
+    fn stopwords(&self) -> &'static str {
+        match self {
+            Language::English => include_str!("stopwords/en.txt"),
+            Language::German => include_str!("stopwords/de.txt"),
+            Language::French => include_str!("stopwords/fr.txt"),
+            Language::Spanish => include_str!("stopwords/es.txt"),
+            Language::Italian => include_str!("stopwords/it.txt"),
+            Language::Portuguese => include_str!("stopwords/pt.txt"),
+            Language::Dutch => include_str!("stopwords/nl.txt"),
+            Language::Russian => include_str!("stopwords/ru.txt"),
+        }
+    }
+
+    /// Check if the lowercase `word` is too common in this language to be worth indexing
+    pub fn is_stopword(&self, word: &str) -> bool {
+        static STOPWORDS: LazyLock<HashMap<Language, HashSet<&'static str>>> = LazyLock::new(|| {
+            Language::ALL.iter().map(|language| (*language, language.stopwords().lines().collect())).collect()
+        });
+        STOPWORDS[self].contains(word)
+    }
+
+    /// Reduce the lowercase `word` to its stem
+    pub fn stem(&self, word: &str) -> Arc<str> {
+        let mut hasher = DefaultHasher::new();
+        word.hash(&mut hasher);
+        let shard = &STEM_CACHE[hasher.finish() as usize % STEM_CACHE_SHARDS];
+        // A parser that panicked while holding the cache leaves it with one stem less at worst
+        shard.lock().unwrap_or_else(PoisonError::into_inner).stem(*self, word)
+    }
+}
+
+/// Count the trigrams of the text the same way the profiles were built:
+/// lowercase letters only, everything else collapses into word separators.
+fn trigrams(content: &[char]) -> HashMap<[char; 3], usize> {
+    let mut result = HashMap::new();
+    let mut chars = content.iter()
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| if c.is_alphabetic() { c } else { ' ' })
+        .chain(Some(' '));
+    let mut c1 = ' ';
+    let mut c2 = chars.next().unwrap_or(' ');
+    for c3 in chars {
+        if !(c2 == ' ' && (c1 == ' ' || c3 == ' ')) {
+            *result.entry([c1, c2, c3]).or_insert(0) += 1;
+        }
+        c1 = c2;
+        c2 = c3;
+    }
+    result
+}
+
+/// Guess the dominant language of the content by comparing its most frequent
+/// trigrams with the profile of every known language (Cavnar & Trenkle "out-of-place" distance).
+/// Returns `None` when the content is too short to tell.
+pub fn detect(content: &[char]) -> Option<Language> {
+    let trigrams = trigrams(content);
+    if trigrams.len() < MIN_TRIGRAMS {
+        return None;
+    }
+    let mut ranked = trigrams.into_iter().collect::<Vec<_>>();
+    ranked.sort_by(|(t1, f1), (t2, f2)| f2.cmp(f1).then(t1.cmp(t2)));
+    ranked.truncate(TEXT_PROFILE_SIZE);
+
+    Language::ALL.iter().copied().min_by_key(|language| {
+        let profile = language.profile();
+        ranked.iter().enumerate().map(|(rank, (trigram, _))| {
+            profile.iter()
+                .position(|p| p.chars().eq(trigram.iter().copied()))
+                .map(|position| position.abs_diff(rank))
+                .unwrap_or(profile.len())
+        }).sum::<usize>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_str(text: &str) -> Option<Language> {
+        detect(&text.chars().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn detects_short_samples() {
+        let samples = [
+            (Language::English, "The quick brown fox jumps over the lazy dog while the children are watching."),
+            (Language::German, "Der schnelle braune Fuchs springt über den faulen Hund, während die Kinder zuschauen."),
+            (Language::French, "Le renard brun rapide saute par-dessus le chien paresseux pendant que les enfants regardent."),
+            (Language::Spanish, "El rápido zorro marrón salta sobre el perro perezoso mientras los niños lo miran."),
+            (Language::Italian, "La volpe marrone veloce salta sopra il cane pigro mentre i bambini guardano."),
+            (Language::Portuguese, "A rápida raposa marrom pula sobre o cão preguiçoso enquanto as crianças observam."),
+            (Language::Dutch, "De snelle bruine vos springt over de luie hond terwijl de kinderen toekijken."),
+            (Language::Russian, "Быстрая коричневая лиса прыгает через ленивую собаку, пока дети смотрят."),
+        ];
+        for (language, text) in samples {
+            assert_eq!(detect_str(text), Some(language), "{text}");
+        }
+    }
+
+    #[test]
+    fn too_short_to_tell() {
+        assert_eq!(detect_str(""), None);
+        assert_eq!(detect_str("hello world"), None);
+        assert_eq!(detect_str("42 + 17 = 59 !!!"), None);
+    }
+
+    #[test]
+    fn stem_cache_forgets_the_oldest_words_first() {
+        let mut cache = StemCache::default();
+        let capacity = STEM_CACHE_CAPACITY / STEM_CACHE_SHARDS;
+        for i in 0..capacity {
+            cache.stem(Language::English, &format!("running{i}"));
+        }
+        assert_eq!(&*cache.stem(Language::English, "running"), "run");
+        let stems = &cache.stems[&Language::English];
+        assert_eq!(stems.stems.len(), capacity);
+        assert!(!stems.stems.contains_key("running0"));
+        assert!(stems.stems.contains_key("running1"));
+        assert_eq!(stems.order.back().map(|word| &**word), Some("running"));
+    }
+
+    #[test]
+    fn languages_from_codes_and_tags() {
+        assert_eq!(Language::from_code("NL"), Some(Language::Dutch));
+        assert_eq!(Language::from_tag("pt-BR"), Some(Language::Portuguese));
+        assert_eq!(Language::from_tag("de_AT"), Some(Language::German));
+        assert_eq!(Language::from_tag("ja"), None);
+    }
+}
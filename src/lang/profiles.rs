@@ -0,0 +1,266 @@
+// Trigram profiles used by `detect`, ranked from the most to the least frequent.
+// Derived from the Latin and Cyrillic profiles of whatlang (MIT).
+
+pub static ENGLISH: &[&str] = &[
+    " th", "the", " an", "he ", "nd ", "and", "ion", " of", "of ", "tio",
+    " to", "to ", "on ", " in", "al ", "ati", "igh", "ght", "rig", " ri",
+    "or ", "ent", "as ", "ed ", "is ", "ll ", "in ", " be", "e r", "ne ",
+    "one", "ver", "all", "s t", "eve", "t t", " fr", "s a", " ha", " re",
+    "ty ", "ery", " or", "d t", " pr", "ht ", " co", " ev", "e h", "e a",
+    "ng ", "ts ", "his", "ing", "be ", "yon", " sh", "ce ", "ree", "fre",
+    "ryo", "n t", "her", "men", "nat", "sha", "pro", "nal", "y a", "has",
+    "es ", "for", " hi", "hal", "f t", "n a", "n o", "nt ", " pe", "s o",
+    " fo", "d i", "nce", "er ", "ons", "res", "e s", "ect", "ity", "ly ",
+    "l b", "ry ", "e e", "ers", "e i", "an ", "e o", " de", "cti", "dom",
+    "edo", "eed", "hts", "ter", "ona", "re ", " no", " wh", " a ", " un",
+    "d f", " as", "ny ", "l a", "e p", "ere", " en", " na", " wi", "nit",
+    "nte", "d a", "any", "ted", " di", "ns ", "sta", "th ", "per", "ith",
+    "e t", "st ", "e c", "y t", "om ", "soc", " ar", "ch ", "t o", "d o",
+    "nti", "s e", "equ", "ve ", "oci", "man", " fu", "ote", "oth", "ess",
+    " al", " ac", "wit", "ial", " ma", "uni", " se", "rea", " so", " on",
+    "lit", "int", "r t", "y o", "enc", "thi", "ual", "t a", " eq", "tat",
+    "qua", "ive", " st", "ali", "e w", "l o", "are", "f h", "con", "te ",
+    "led", " is", "und", "cia", "e f", "le ", " la", "y i", "uma", "by ",
+    " by", "hum", "f a", "ic ", " hu", "ave", "ge ", "r a", " wo", "o a",
+    "ms ", "com", " me", "eas", "s d", "tec", " li", "n e", "en ", "rat",
+    "tit", "ple", "whe", "ate", "o t", "s r", "t f", "rot", " ch", "cie",
+    "dis", "age", "ary", "o o", "anc", "eli", "no ", " fa", " su", "son",
+    "inc", "at ", "nda", "hou", "wor", "t i", "nde", "rom", "oms", " ot",
+    "g t", "eme", "tle", "iti", "gni", "s w", "itl", "duc", "d w", "whi",
+    "act", "hic", "aw ", "law", " he", "ich", "min", "imi", "ort", "o s",
+    "se ", "e b", "ntr", "tra", "edu", "oun", "tan", "e d", "nst", "l p",
+    "d n", "ld ", "nta", "s i", "ble", "n p", " pu", "n s", " at", "ily",
+    "rth", "tho", "ful", "ssi", "der", "o e", "cat", "uca", "unt", "ien",
+    " ed", "o p", "h a", "era", "ind", "pen", "sec", "n w", "omm", "r s",
+];
+
+pub static GERMAN: &[&str] = &[
+    "en ", "er ", "der", " un", "nd ", "und", "ein", "ung", "cht", " de",
+    "ich", "sch", "ng ", " ge", "ie ", "che", "ech", " di", "die", "rec",
+    "gen", "ine", "eit", " re", "ch ", " da", "n d", "ver", "hen", " zu",
+    "t d", " au", "ht ", " ha", "lic", "it ", "ten", "rei", " be", "in ",
+    " ve", " in", " ei", "nde", "auf", "den", "ede", "zu ", "n s", "uf ",
+    "fre", "ne ", "ter", "es ", " je", "jed", "n u", " an", "sei", "and",
+    " fr", "run", "at ", " se", "e u", "das", "hei", "s r", "hte", "hat",
+    "nsc", "nge", "r h", "as ", "ens", " al", "ere", "lle", "t a", " we",
+    "n g", "rde", "nte", "ese", "men", " od", "ode", "ner", "g d", "all",
+    "t u", "ers", "te ", "nen", " so", "d d", "n a", "ben", "lei", " gr",
+    " vo", "wer", "e a", "ege", "ion", " st", "ige", "le ", "cha", " me",
+    "haf", "aft", "n j", "ren", " er", "erk", "ent", "bei", " si", "eih",
+    "ihe", "kei", "erd", "tig", "n i", "on ", "lun", "r d", "len", "gem",
+    "ies", "gru", "tli", "unt", "chu", "ern", "ges", "end", "e s", "ft ",
+    "st ", "ist", "tio", "ati", " gl", "sta", "gun", "mit", "sen", "n n",
+    " na", "n z", "ite", " wi", "r g", "eic", "e e", "ei ", "lie", "r s",
+    "n w", "gle", "mei", "de ", "uch", "em ", "chl", "nat", "rch", "t w",
+    "des", "n e", "hre", "ale", "spr", "d f", "ach", "sse", "r e", " sc",
+    "urc", "r m", "nie", "e f", "fen", "e g", "e d", " ni", "dur", "dar",
+    "int", " du", "geh", "ied", "t s", " mi", "alt", "her", "hab", "f g",
+    "sic", "ste", "taa", "aat", "he ", "ang", "ruc", "hli", "tz ", "eme",
+    "abe", "h a", "n v", "nun", "geg", "arf", "rf ", "ehe", "pru", " is",
+    "erf", "e m", "ans", "ndl", "e b", "tun", "n o", "d g", "n r", "r v",
+    "wie", "ber", "r a", "arb", "bes", "t i", "h d", "r w", "r b", " ih",
+    "d s", "igk", "gke", "nsp", "dig", "ema", "ell", "eru", "n f", "ins",
+    "rbe", "ffe", "esc", "igu", "ger", "str", "ken", "e v", "gew", "han",
+    "ind", "rt ", " ar", "ieß", "n h", "rn ", "man", "r i", "hut", "utz",
+    "d a", "ls ", "ebe", "von", "lte", "r o", "rli", "etz", "tra", "aus",
+    "det", "hul", "e i", "one", "nne", "isc", "son", "sel", "et ", "ohn",
+    "t g", "sam", " fa", "rst", "rkl", "ser", "iem", "g v", "t z", "err",
+];
+
+pub static FRENCH: &[&str] = &[
+    " de", "es ", "de ", "ion", "nt ", "et ", "tio", " et", "ent", " la",
+    "la ", "e d", "on ", "ne ", "oit", "e l", "le ", " le", "s d", "e p",
+    "t d", "ati", "roi", " dr", "dro", "it ", " à ", " co", "té ", "ns ",
+    "te ", "e s", "men", "re ", " to", "con", " l’", "tou", "que", " qu",
+    "les", " so", "des", "son", " pe", "ons", " un", "s l", "s e", " pr",
+    "ue ", " pa", "e c", "t l", "ts ", "onn", " au", "e a", "eme", "e e",
+    " li", "ont", "ant", "out", "ute", "t à", "res", "ers", " sa", "ce ",
+    " a ", "tre", "per", "a d", "cti", "er ", "lib", "ité", " en", "ux ",
+    " re", "en ", "rso", "à l", " ou", " in", "lle", "un ", "nat", "ou ",
+    "nne", "n d", "une", " d’", " se", "par", "nte", "us ", "ur ", "s s",
+    "ans", "dan", "a p", "r l", "pro", "its", "és ", "t p", "ire", "e t",
+    "s p", "sa ", " dé", "ond", "é d", "a l", "nce", "ert", "aux", "omm",
+    "nal", "me ", " na", " fo", "iqu", " ce", "rté", "ect", "ale", "ber",
+    "t a", "s a", " da", "mme", "ibe", "san", "e r", " po", "com", "al ",
+    "s c", "qui", "our", "t e", " ne", "e n", "ous", "r d", "ali", "ter",
+    " di", "fon", "e o", "au ", " ch", "air", "ui ", "ell", " es", "lit",
+    "s n", "iss", "éra", "tes", "soc", "aut", "oci", "êtr", "ien", "int",
+    "du ", "est", "été", "tra", "pou", " pl", "rat", "ar ", "ran", "rai",
+    "s o", "ona", "ain", "cla", "éga", "anc", "rs ", "eur", "pri", "n c",
+    "e m", "s t", "à u", " do", "ure", "bre", "ut ", " êt", "age", " ét",
+    "nsi", "sur", "ein", "sen", "ser", "ndi", "ens", "ess", "ntr", "ir ",
+    " ma", "cia", "n p", "st ", "a c", " du", "l e", " su", "bli", "ge ",
+    "rés", " ré", "e q", "ass", "nda", "peu", "ée ", "l’a", " te", "a s",
+    "tat", "il ", "tés", "ais", "u d", "ine", "ind", "é e", "qu’", " ac",
+    "s i", "n t", "t c", "n a", "l’h", "t q", "soi", "t s", "cun", "rit",
+    " ég", "oir", "’en", "nta", "hom", " on", "n e", " mo", "ie ", "ign",
+    "rel", "nna", "t i", "l n", " tr", "ill", "ple", "s é", "l’e", "rec",
+    "a r", "ote", "sse", "uni", "idé", "ive", "s u", "t ê", "ins", "act",
+    " fa", "n s", " vi", "gal", " as", "lig", "ssa", "pré", "leu", "e f",
+    "lic", "dis", "ver", " nu", "ten", "ssi", "rot", "tec", "s m", "abl",
+];
+
+pub static SPANISH: &[&str] = &[
+    " de", "os ", "de ", " la", "la ", " y ", " a ", "es ", "ón ", "ión",
+    "rec", "ere", "der", " co", "e l", "el ", "en ", "ien", "cho", "ent",
+    "ech", "ció", "aci", "o a", "a p", " el", "a l", "al ", "as ", "e d",
+    " en", "na ", "ona", "s d", "da ", "nte", " to", "ad ", "ene", "con",
+    " pr", " su", "tod", " se", "ho ", "los", " pe", "per", "ers", " lo",
+    "o d", " ti", "cia", "n d", "cio", " es", "ida", "res", "a t", "tie",
+    "ion", "rso", "te ", "do ", " in", "son", " re", " li", "to ", "dad",
+    "tad", "e s", "est", "pro", "que", "men", " po", "a e", "oda", "nci",
+    " qu", " un", "ue ", "ne ", "n e", "s y", "lib", "su ", " na", "s e",
+    "nac", "ia ", "e e", "tra", " pa", "or ", "ado", "a d", "nes", "ra ",
+    "se ", "ual", "a c", "er ", "por", "com", "nal", "rta", "a s", "ber",
+    " o ", "one", "s p", "dos", "rá ", "sta", "les", "des", "ibe", "ser",
+    "era", "ar ", "ert", "ter", " di", "ale", "l d", "nto", "hos", "del",
+    "ica", "a a", "s n", "n c", "oci", "imi", "io ", "o e", "re ", "y l",
+    "e c", "ant", "cci", " as", "las", "par", "ame", " cu", "ici", "ara",
+    "enc", "s t", "ndi", " so", "o s", "mie", "tos", "una", "bre", "dic",
+    "cla", "s l", "e a", "l p", "pre", "ntr", "o t", "ial", "y a", "nid",
+    "n p", "a y", "man", "omo", "so ", "n l", " al", "ali", "s a", "no ",
+    " ig", "s s", "e p", "nta", "uma", "ten", "gua", "ade", "y e", "soc",
+    "mo ", " fu", "igu", "o p", "n t", "hum", "d d", "ran", "ria", "y d",
+    "ada", "tiv", "l e", "cas", " ca", "vid", "l t", "s c", "ido", "das",
+    "dis", "s i", " hu", "s o", "nad", "fun", " ma", "rac", "nda", "eli",
+    "sar", "und", " ac", "uni", "mbr", "a u", "die", "e i", "qui", "a i",
+    " ha", "lar", " tr", "odo", "ca ", "tic", "o y", "cti", "lid", "ori",
+    "ndo", "ari", " me", "ta ", "ind", "esa", "cua", "un ", "ier", "tal",
+    "esp", "seg", "ele", "ons", "ito", "ont", "iva", "s h", "d y", "nos",
+    "ist", "rse", " le", "cie", "ide", "edi", "ecc", "ios", "l m", "r e",
+    "med", "tor", "sti", "n a", "rim", "uie", "ple", "tri", "ibr", "sus",
+    "lo ", "ect", "pen", "y c", "an ", "e h", "n s", "ern", "tar", "l y",
+    "egu", "gur", "ura", "int", "ond", "mat", "l r", "r a", "isf", "ote",
+];
+
+pub static ITALIAN: &[&str] = &[
+    " di", "to ", " de", "ion", " in", "la ", "e d", "di ", "ne ", " e ",
+    "zio", "re ", "le ", "ni ", "ell", "one", "lla", "rit", "a d", "o d",
+    "del", "itt", "iri", "dir", " co", "ti ", "ess", "ent", " al", "azi",
+    "tto", "te ", "i d", "i i", "ere", "tà ", " pr", "ndi", "e l", "ale",
+    "o a", "ind", "e e", "e i", "gni", "nte", "con", "i e", "li ", "a s",
+    " un", "men", "ogn", " ne", "uo ", " og", "idu", "e a", "ivi", "duo",
+    "vid", " es", "tti", " ha", "div", " li", "a p", "no ", "all", "pro",
+    "za ", "ato", "per", "sse", "ser", " so", "i s", " la", " su", "e p",
+    " pe", "ibe", "na ", "a l", " il", "ber", "e n", "il ", "ali", "lib",
+    "ha ", "che", "in ", "o s", "e s", " qu", "o e", "ia ", "e c", " ri",
+    "nza", "ta ", "nto", "he ", "oni", "o i", " o ", "sta", "o c", "nel",
+    " a ", "o p", "naz", "e o", "so ", " po", "o h", "gli", "i u", "ond",
+    "i c", "ers", "ame", "i p", "lle", "un ", "era", "ri ", "ver", "ro ",
+    "el ", "una", "a c", " ch", "ert", "ua ", "i a", "ssi", "rtà", "a e",
+    "ei ", "dis", "ant", " l ", "tat", "a a", "ona", "ual", " le", "ità",
+    "are", "ter", " ad", "nit", " da", "pri", "dei", "à e", "cia", " st",
+    " si", "nal", "est", "tut", "ist", "com", "uni", " ed", "ono", " na",
+    "sua", "al ", "si ", "anz", " pa", " re", "raz", "gua", "ita", "res",
+    "der", "soc", "man", "o o", "ad ", "i o", "ese", "que", "enz", "ed ",
+    " se", "io ", "ett", "on ", " tu", "dic", "à d", "sia", "i r", "rso",
+    "oci", "rio", "ari", "qua", "ial", "pre", "ich", "rat", "ien", "tra",
+    "ani", "uma", "se ", "ll ", "eri", "a n", "o n", " um", "do ", "ara",
+    "a t", "zza", "er ", "tri", "att", "ico", "pos", "sci", "i l", "son",
+    "nda", "par", "e u", "fon", " fo", "nti", "uzi", "str", "utt", "ati",
+    "sen", "int", "nes", "iar", " i ", "hia", "n c", "sti", "chi", "ann",
+    "ra ", " eg", "egu", "isp", "bil", "ont", "a r", " no", "rop", " me",
+    "opr", "ost", " ma", "ues", "ica", "sso", "tal", "cie", "sun", "lit",
+    "ore", "ina", "ite", "tan", " ra", "non", "gio", "d a", "e r", "dev",
+    "i m", "l i", "ezz", "izi", " cu", "nno", "rà ", "a i", "tta", "ria",
+    "lia", "cos", "ssu", "dal", "l p", " as", "ass", "opo", "ve ", "eve",
+];
+
+pub static PORTUGUESE: &[&str] = &[
+    "os ", "de ", " de", " a ", " e ", "o d", "to ", "ão ", " di", "ent",
+    "da ", "ito", "em ", " co", "eit", "as ", "dir", "es ", "ire", "rei",
+    " se", "ção", "ade", "a p", "dad", "e d", "s d", "men", "nte", "do ",
+    "s e", " pr", " pe", "dos", " to", " da", "a a", "o e", " o ", "o a",
+    "ess", "con", "tod", "que", " qu", "te ", "e a", " do", "al ", "res",
+    "ida", "m d", " in", " ou", "er ", "sso", " na", " re", " po", "a s",
+    " li", "uma", "cia", "ar ", "pro", "e e", "a d", " te", "açã", "a t",
+    " es", " su", "ou ", "ue ", "s p", "tos", "a e", "des", "ra ", "com",
+    "no ", "ame", "ia ", "e p", "tem", "nto", " pa", "is ", "est", "tra",
+    "ões", "na ", "s o", "oda", "das", "ser", "soa", "s n", "pes", "o p",
+    "s a", "o s", "e o", " em", " as", " à ", "o o", "ais", "ber", "ado",
+    "oa ", "o t", "e s", "man", "sua", "ua ", " no", " os", "a c", "ter",
+    "çõe", "erd", "lib", "rda", "s s", "nci", "ibe", "e n", "ica", "odo",
+    "so ", "nal", "ntr", "s t", "hum", "ura", " ao", "ona", "ual", " so",
+    "or ", "ma ", "sta", "o c", "a n", "pre", "ara", "era", "ons", "e t",
+    "r a", "par", "o à", " hu", "ind", "por", "cio", "ria", "m a", "s c",
+    " um", "a l", "gua", "ran", " en", "ndi", "o i", "e c", "raç", "ion",
+    "nid", "aci", "ano", "soc", "e r", "oci", " ac", "und", "sen", "nos",
+    "nsi", "rec", "ime", "ali", "int", "um ", "per", "nac", " al", "m o",
+    "r p", " fu", "ndo", "ont", "açõ", " ig", "igu", "fun", "nta", " ma",
+    "uni", "cçã", "ere", " ex", "a i", " me", "ese", "rio", "l d", "a o",
+    "s h", "pel", "ada", "pri", "ide", "am ", "m p", "pod", "s f", "ém ",
+    "a f", "io ", "ode", "ca ", "ita", "lid", "tiv", "e f", "vid", "r e",
+    "esp", "nda", "omo", "e l", "naç", "o r", "ant", "a q", "tad", "lic",
+    "iva", " fa", "ver", "s l", "ial", "cla", "ngu", "ing", " ca", "mo ",
+    "der", " vi", "eli", "ist", "ta ", "se ", "ati", "ios", "ido", "r o",
+    "eci", "dis", " un", "e i", "r d", "ecç", "o q", "s i", "qua", "ênc",
+    "a m", "seu", "sti", "nin", "uer", "rar", "cas", "aos", "ens", "gué",
+    "ias", "sid", "uém", "tur", "dam", "sse", "ao ", "ela", "l e", "for",
+    "tec", "ote", " pl", "ena", " tr", "m c", "tro", " ni", "ico", "rot",
+];
+
+pub static DUTCH: &[&str] = &[
+    "en ", "de ", "an ", " de", "van", " va", " en", " he", "ing", "cht",
+    "der", "ng ", "n d", "n v", "et ", "een", " ge", "ech", "n e", "ver",
+    "rec", "nde", " ee", " re", " be", "ede", "er ", "e v", "gen", "den",
+    "het", "ten", " te", " in", " op", "n i", " ve", "lij", " zi", "ere",
+    "eli", "zij", "ijk", "te ", "oor", "ht ", "ens", "n o", "and", "t o",
+    "ijn", "ied", "ke ", " on", "eid", "op ", " vo", "jn ", "id ", "ond",
+    "in ", "sch", " vr", "aar", "n z", "aan", " ie", "rde", "rij", "men",
+    "ren", "ord", "hei", "hte", " we", "eft", "n g", "ft ", "n w", "or ",
+    "n h", "eef", "vri", "wor", " me", "hee", "al ", "t r", "of ", "le ",
+    " of", "ati", "g v", "e b", "eni", " aa", "lle", " wo", "n a", "e o",
+    "nd ", "r h", "voo", " al", "ege", "n t", "erk", " da", " na", "t h",
+    "sta", "jke", "at ", "nat", "nge", "e e", "end", " st", "om ", "e g",
+    "tie", "n b", "ste", "die", "e r", "erw", "wel", "e s", "r d", " om",
+    "ij ", "dig", "t e", "ige", "ter", "ie ", "gel", "re ", "jhe", "t d",
+    " za", "e m", "ers", "ijh", "nig", "zal", "nie", "d v", "ns ", "d e",
+    "e w", "e n", "est", "ele", "bes", " do", "g e", "che", "vol", "ge ",
+    "eze", "e d", "ig ", "gin", "dat", "hap", "cha", "eke", " di", "ona",
+    "e a", "lke", "nst", "ard", " gr", "tel", "min", " to", "waa", "len",
+    "elk", "lin", "eme", "jk ", "n s", "del", "str", "han", "eve", "gro",
+    "ich", "ven", "doo", " wa", "t v", "it ", "ove", "rin", "aat", "n n",
+    "wet", "uit", "ijd", "ze ", " zo", "ion", " ov", "dez", "gem", "met",
+    "tio", "bbe", "ach", " ni", "hed", "st ", "all", "ies", "per", "heb",
+    "ebb", "e i", "toe", "es ", "taa", "n m", "nte", "ien", "el ", "nin",
+    "ale", "ben", "daa", "sti", " ma", "mee", "kin", "pen", "e h", "wer",
+    "ont", "iet", "tig", "g o", "s e", " er", "igd", "ete", "ang", "lan",
+    "nsc", "ema", "man", "t g", "is ", "beg", "her", "esc", "bij", "d o",
+    "ron", "tin", "nal", "eer", "p v", "edi", "erm", "ite", "t w", "t a",
+    " hu", "rwi", "wij", "ijs", "r e", "weg", "js ", "rmi", "naa", "t b",
+    "app", "rwe", " bi", "t z", "ker", "ame", "eri", "ken", " an", "ar ",
+    " la", "tre", "ger", "rdi", "tan", "eit", "gde", "g i", "d z", "oep",
+];
+
+pub static RUSSIAN: &[&str] = &[
+    " пр", " и ", "рав", "ств", " на", "пра", "го ", "ени", "ове", "во ",
+    " ка", "ани", "ть ", " в ", " по", " об", "ия ", "сво", " св", "лов",
+    "на ", " че", "ело", "о н", " со", "ост", "чел", "ие ", "ого", "ет ",
+    "ния", "ест", "аво", "ый ", "ажд", " им", "ние", "век", " не", "льн",
+    "ли ", "ова", "име", "ать", "при", "т п", "и п", "каж", "или", "обо",
+    " ра", "ых ", "жды", " до", "дый", "воб", "ек ", "бод", "ва ", "й ч",
+    "его", "ся ", "и с", "ии ", "аци", "еет", "но ", "мее", "и и", "лен",
+    "ой ", "тва", "ных", "то ", " ил", "к и", "енн", " бы", "ию ", " за",
+    "ми ", "тво", "и н", "о п", "ван", "о с", "сто", "аль", " вс", "ом ",
+    "о в", "ьно", "их ", "ног", "и в", "нов", "ако", "про", "ий ", "сти",
+    "и о", "пол", "олж", "дол", "ое ", "бра", "я в", " ос", "ным", "жен",
+    "раз", "ти ", "нос", "я и", " во", "тор", "все", " ег", "ей ", "тел",
+    "не ", "и р", "ред", "ель", "тве", "оди", " ко", "общ", "о и", " де",
+    "има", "а и", "чес", "ним", "сно", "как", " ли", "щес", "вле", "ься",
+    "нны", "аст", "тьс", "нно", "осу", "е д", " от", "пре", "шен", "а с",
+    "бще", "осн", "одн", "быт", "сов", "ыть", "лжн", "ран", "нию", "иче",
+    "ак ", "ым ", "ват", "что", "сту", "чен", "е в", " ст", "рес", "оль",
+    " ни", "ном", "род", "ля ", "нар", "вен", "ду ", "оже", "ны ", "е и",
+    " то", "вер", "а о", "зов", "м и", "нац", "ден", "рин", "туп", "ежд",
+    "стр", " чт", "я п", "она", "дос", "х и", "й и", "тоя", "есп", "лич",
+    "бес", "обр", "ото", "о б", "ьны", "ь в", "нии", "е м", "ую ", " мо",
+    "ем ", " ме", "аро", " ре", "ава", "кот", "ав ", " вы", "ам ", "жно",
+    "ста", "ая ", "под", "и к", "ное", " к ", " та", " го", "гос", "суд",
+    "еоб", "я н", "ен ", "и д", "мож", "еск", "ели", "авн", "ве ", "ече",
+    "уще", "печ", "дно", "о д", "ход", "ка ", " дл", "для", "ово", "ате",
+    "льс", "ю и", "в к", "нен", "ции", "ной", "уда", "вов", " бе", "оро",
+    "нст", "ами", "циа", "кон", "сем", "е о", "вно", " эт", "азо", "х п",
+    "ни ", "жде", "м п", "ког", "от ", "дст", "вны", "сть", "ые ", "о о",
+    "пос", "сре", "тра", "ейс", "так", "и б", "дов", "му ", "я к", "нал",
+    "дру", " др", "кой", "тер", "ь п", "арс", "изн", "соц", "еди", "олн",
+];
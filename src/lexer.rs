@@ -1,21 +1,86 @@
+use std::sync::Arc;
+use crate::lang::Language;
+
+/// Prefix that sets the terms of the unstemmed shadow field apart from the stemmed terms
+pub const EXACT_PREFIX: char = '=';
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token<'a> {
+    /// Normalized form of the token that goes into the index, shared with the stem cache
+    pub term: Arc<str>,
+    /// Original surface text exactly as it appears in the content
+    pub text: &'a [char],
+    /// Character offset of the first char of the token in the content
+    pub start: usize,
+    /// Character offset one past the last char of the token in the content
+    pub end: usize,
+    /// Index of the token among all the tokens of the content
+    pub position: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn is_word(&self) -> bool {
+        self.text.first().map(|c| c.is_alphabetic()).unwrap_or(false)
+    }
+}
+
 pub struct Lexer<'a> {
     content: &'a [char],
+    offset: usize,
+    position: usize,
+    language: Language,
+    stemming: bool,
+    stopwords: bool,
+    lowercase: String,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(content: &'a [char]) -> Self {
-        Self { content }
+    /// Lexer that drops the stopwords of `language` and stems words with its stemmer
+    pub fn new(content: &'a [char], language: Language) -> Self {
+        Self { content, offset: 0, position: 0, language, stemming: true, stopwords: false, lowercase: String::new() }
+    }
+
+    /// Lexer for content that goes on where the content of a previous lexer stopped, whose tokens
+    /// keep counting offsets from `offset` and positions from `position`
+    pub fn continuing(mut self, offset: usize, position: usize) -> Self {
+        self.offset = offset;
+        self.position = position;
+        self
+    }
+
+    /// Keep the words in their lowercase form instead of stemming them
+    pub fn without_stemming(mut self) -> Self {
+        self.stemming = false;
+        self
+    }
+
+    /// Keep the stopwords of the language as tokens instead of dropping them
+    pub fn with_stopwords(mut self) -> Self {
+        self.stopwords = true;
+        self
+    }
+
+    /// Position of the next token, stopwords that were dropped included
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Adapter that yields only the normalized terms
+    pub fn terms(self) -> Terms<'a> {
+        Terms { lexer: self }
     }
 
     fn trim_left(&mut self) {
         while self.content.len() > 0 && self.content[0].is_whitespace() {
             self.content = &self.content[1..];
+            self.offset += 1;
         }
     }
 
     fn chop(&mut self, n: usize) -> &'a [char] {
         let token = &self.content[..n];
         self.content = &self.content[n..];
+        self.offset += n;
         return token;
     }
 
@@ -27,29 +92,131 @@ impl<'a> Lexer<'a> {
         self.chop(n)
     }
 
-    pub fn next_token(&mut self) -> Option<String> {
-        self.trim_left();
-        if self.content.len() == 0 {
-            return None;
-        }
-        if self.content[0].is_numeric() {
-            return Some(self.chop_while(|c| c.is_numeric()).iter().collect());
-        }
-        if self.content[0].is_alphabetic() {
-            let term = self.chop_while(|x| x.is_alphanumeric()).iter().map(|x| x.to_ascii_lowercase()).collect::<String>();
-            let mut env = crate::snowball::SnowballEnv::create(&term);
-            crate::snowball::algorithms::english_stemmer::stem(&mut env);
-            let stemmed_term = env.get_current().to_string();
-            return Some(stemmed_term);
+    fn emit(&mut self, term: Arc<str>, text: &'a [char]) -> Token<'a> {
+        let token = Token {
+            term,
+            text,
+            start: self.offset - text.len(),
+            end: self.offset,
+            position: self.position,
+        };
+        self.position += 1;
+        token
+    }
+
+    pub fn next_token(&mut self) -> Option<Token<'a>> {
+        loop {
+            self.trim_left();
+            if self.content.len() == 0 {
+                return None;
+            }
+            if self.content[0].is_numeric() {
+                let text = self.chop_while(|c| c.is_numeric());
+                return Some(self.emit(text.iter().collect::<String>().into(), text));
+            }
+            if self.content[0].is_alphabetic() {
+                let text = self.chop_while(|x| x.is_alphanumeric());
+                self.lowercase.clear();
+                self.lowercase.extend(text.iter().flat_map(|x| x.to_lowercase()));
+                if !self.stopwords && self.language.is_stopword(&self.lowercase) {
+                    // Stopwords still occupy a position so the positions keep reflecting the distance between words
+                    self.position += 1;
+                    continue;
+                }
+                let term = if self.stemming {
+                    self.language.stem(&self.lowercase)
+                } else {
+                    self.lowercase.as_str().into()
+                };
+                return Some(self.emit(term, text));
+            }
+            let text = self.chop(1);
+            return Some(self.emit(text.iter().collect::<String>().into(), text));
         }
-        return Some(self.chop(1).iter().collect());
     }
 }
 
 impl<'a> Iterator for Lexer<'a> {
-    type Item = String;
+    type Item = Token<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.next_token()
     }
-}
\ No newline at end of file
+}
+
+pub struct Terms<'a> {
+    lexer: Lexer<'a>,
+}
+
+impl<'a> Iterator for Terms<'a> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.lexer.next_token().map(|token| token.term.to_string())
+    }
+}
+
+/// Terms of the unstemmed shadow field: every word of the content in its exact lowercase form,
+/// stopwords included. They are what `=word` in a query matches.
+pub fn exact_terms(content: &[char], language: Language) -> impl Iterator<Item = String> + '_ {
+    Lexer::new(content, language)
+        .without_stemming()
+        .with_stopwords()
+        .filter(|token| token.is_word())
+        .map(|token| format!("{EXACT_PREFIX}{term}", term = token.term))
+}
+
+/// Terms of the field `name` of a document, which `name:word` in a query matches
+pub fn field_terms<'a>(name: &'a str, content: &'a [char], language: Language) -> impl Iterator<Item = String> + 'a {
+    Lexer::new(content, language)
+        .terms()
+        .map(move |term| format!("{name}:{term}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn tokens_have_char_offsets_and_positions() {
+        let content = chars("Süße  cats, 42");
+        let tokens = Lexer::new(&content, Language::English).collect::<Vec<_>>();
+        let spans = tokens.iter().map(|token| (&*token.term, token.start, token.end, token.position)).collect::<Vec<_>>();
+        assert_eq!(spans, [("süße", 0, 4, 0), ("cat", 6, 10, 1), (",", 10, 11, 2), ("42", 12, 14, 3)]);
+        assert_eq!(tokens[1].text.iter().collect::<String>(), "cats");
+    }
+
+    #[test]
+    fn stopwords_keep_their_position() {
+        let content = chars("the cat and the hat");
+        let positions = Lexer::new(&content, Language::English).map(|token| (token.term.to_string(), token.position)).collect::<Vec<_>>();
+        assert_eq!(positions, [("cat".to_string(), 1), ("hat".to_string(), 4)]);
+    }
+
+    #[test]
+    fn continuing_lexer_counts_on_across_chunks() {
+        let content = chars("running dogs bark at the passing cars");
+        let whole = Lexer::new(&content, Language::English).collect::<Vec<_>>();
+        let (first, second) = content.split_at(13);
+        let mut lexer = Lexer::new(first, Language::English);
+        let mut chunked = lexer.by_ref().collect::<Vec<_>>();
+        chunked.extend(Lexer::new(second, Language::English).continuing(first.len(), lexer.position()));
+        assert_eq!(chunked, whole);
+    }
+
+    #[test]
+    fn exact_terms_keep_stopwords_unstemmed() {
+        let content = chars("The running, 42");
+        assert_eq!(exact_terms(&content, Language::English).collect::<Vec<_>>(), ["=the", "=running"]);
+    }
+
+    #[test]
+    fn field_terms_are_stemmed_and_prefixed() {
+        let content = chars("Running the Tests");
+        assert_eq!(field_terms("title", &content, Language::English).collect::<Vec<_>>(), ["title:run", "title:test"]);
+    }
+}
@@ -1,88 +1,30 @@
 use std::fs;
 use std::fs::File;
-use xml::common::{TextPosition, Position};
-use xml::reader::{EventReader, XmlEvent};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::env;
 use std::process::ExitCode;
 use std::result::Result;
 use std::str;
-use std::io::{BufReader, BufWriter};
+use std::io::BufWriter;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::SystemTime;
 
 mod model;
 use model::*;
 mod server;
 mod lexer;
+mod lang;
+use lang::Language;
+mod query;
+mod parsers;
+use parsers::{CsvParser, MarkdownParser, ParsedDocument, ParserRegistry};
+mod config;
+use config::Config;
+mod sandbox;
+use sandbox::{Isolation, Quarantine, Sandbox};
 pub mod snowball;
 
-fn parse_entire_pdf_file(file_path: &Path) -> Result<String, ()> {
-    use poppler::Document;
-    use std::io::Read;
-    let mut content = Vec::new();
-    File::open(file_path)
-        .and_then(|mut file| file.read_to_end(&mut content))
-        .map_err(|err| {
-            eprintln!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
-        })?;
-    let pdf = Document::from_data(&content, None).map_err(|err| {
-        eprintln!("ERROR: could not read file {file_path}: {err}",
-                  file_path = file_path.display());
-    })?;
-    let mut result = String::new();
-    let n = pdf.n_pages();
-    for i in 0..n {
-        let page = pdf.page(i).expect(&format!("{i} is within the bounds of the range of the page"));
-        if let Some(content) = page.text() {
-            result.push_str(content.as_str());
-            result.push(' ');
-        }
-    }
-    Ok(result)
-}
-
-fn parse_entire_txt_file(file_path: &Path) -> Result<String, ()> {
-    fs::read_to_string(file_path).map_err(|err| {
-        eprintln!("ERROR: could not open file {file_path}: {err}", file_path = file_path.display());
-    })
-}
-
-fn parse_entire_xml_file(file_path: &Path) -> Result<String, ()> {
-    let file = File::open(file_path).map_err(|err| {
-        eprintln!("ERROR: could not open file {file_path}: {err}", file_path = file_path.display(), err = err);
-    })?;
-    let er = EventReader::new(BufReader::new(file));
-    let mut content = String::new();
-    for event in er.into_iter() {
-        let event = event.map_err(|err| {
-            let TextPosition {row, column} = err.position();
-            let msg = err.msg();
-            eprintln!("{file_path}:{row}:{column}: ERROR: {msg}", file_path = file_path.display(), row = row, column = column, msg = msg);
-        })?;
-        if let XmlEvent::Characters(text) = event {
-            content.push_str(&text);
-            content.push_str(" ");
-        }
-    }
-    Ok(content)
-}
-
-fn parse_entire_file_by_extension(file_path: &Path) -> Result<String, ()> {
-    let extension = file_path.extension().ok_or_else(|| {
-        eprintln!("ERROR: cannot detect file type of {file_path} without extension", file_path = file_path.display());
-    })?.to_string_lossy();
-    match extension.as_ref() {
-        "xhtml" | "xml" | "html" => parse_entire_xml_file(file_path),
-        "txt" | "md" => parse_entire_txt_file(file_path),
-        "pdf" => parse_entire_pdf_file(file_path),
-        _ => {
-            eprintln!("ERROR: cannot detect file type of {file_path}: unsupported extension {extension}", file_path = file_path.display(), extension = extension);
-            Err(())
-        }
-    }
-}
-
 fn save_model_as_json(model: &InMemoryModel, index_path: &Path) -> Result<(), ()> {
     println!("Saving {index_path}...", index_path = index_path.display());
     let index_file = File::create(index_path).map_err(|err| {
@@ -94,14 +36,49 @@ fn save_model_as_json(model: &InMemoryModel, index_path: &Path) -> Result<(), ()
     Ok(())
 }
 
-fn add_folder_to_model(dir_path: &Path, model: Arc<Mutex<Box<dyn Model + Send>>>, skipped: &mut usize, processed: &mut usize) -> Result<(), ()> {
-    let dir = fs::read_dir(dir_path).map_err(|err| {
+/// Index the document and its sub-documents, which fall back on its metadata for what they do not say themselves.
+/// The language of a document that neither says nor shows what it is written in is `fallback`.
+fn add_document_to_model(model: &mut dyn Model, file_path: PathBuf, last_modified: SystemTime, mut document: ParsedDocument, fallback: Language) -> Result<(), ()> {
+    // Parsers that stream the text count its terms themselves
+    let terms = match document.terms.take() {
+        Some(terms) => terms,
+        None => {
+            let declared = document.metadata.contains_key("lang").then(|| document_language(&document.metadata));
+            let mut counter = TermCounter::new(declared, fallback);
+            counter.push(&document.text);
+            let (terms, language) = counter.finish();
+            document.metadata.entry("lang".to_string()).or_insert_with(|| language.code().to_string());
+            terms
+        }
+    };
+    let language = document_language(&document.metadata);
+    model.add_document(file_path.clone(), last_modified, terms, &document.fields, document.metadata.clone())?;
+    for (name, mut part) in document.parts {
+        // What a part is written in and whether it is cut off is up to the part itself
+        for (key, value) in document.metadata.iter().filter(|(key, _)| !matches!(key.as_str(), "lang" | "truncated")) {
+            part.metadata.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        add_document_to_model(model, part_path(&file_path, &name), last_modified, part, language)?;
+    }
+    Ok(())
+}
+
+/// Index the files of the folder that changed since they were last indexed. The files that cannot be
+/// read or parsed count as failed and the indexing goes on, only the errors of the model stop it.
+fn add_folder_to_model(dir_path: &Path, model: Arc<Mutex<Box<dyn Model + Send>>>, sandbox: &mut Sandbox, skipped: &mut usize, processed: &mut usize, failed: &mut usize) -> Result<(), ()> {
+    let Ok(dir) = fs::read_dir(dir_path).map_err(|err| {
         eprintln!("ERROR: could not read directory {dir_path}: {err}", dir_path = dir_path.display(), err = err);
-    })?;
+    }) else {
+        *failed += 1;
+        return Ok(());
+    };
     'next_file: for file in dir {
-        let file = file.map_err(|err| {
+        let Ok(file) = file.map_err(|err| {
             eprintln!("ERROR: could not read next file in directory {dir_path} during indexing: {err}", dir_path = dir_path.display(), err = err);
-        })?;
+        }) else {
+            *failed += 1;
+            continue 'next_file;
+        };
         let file_path = file.path();
         let dot_file = file_path
             .file_name()
@@ -113,30 +90,43 @@ fn add_folder_to_model(dir_path: &Path, model: Arc<Mutex<Box<dyn Model + Send>>>
             *skipped += 1;
             continue 'next_file;
         }
-        let file_type = file.file_type().map_err(|err| {
+        let Ok(file_type) = file.file_type().map_err(|err| {
             eprintln!("ERROR: could not get file type of {file_path}: {err}", file_path = file_path.display(), err = err);
-        })?;
-        let last_modified = file.metadata().map_err(|err| {
+        }) else {
+            *failed += 1;
+            continue 'next_file;
+        };
+        let Ok(last_modified) = file.metadata().map_err(|err| {
             eprintln!("ERROR: could not get the metadata of file {file_path}: {err}", file_path = file_path.display());
-        })?.modified().map_err(|err| {
+        }).and_then(|metadata| metadata.modified().map_err(|err| {
             eprintln!("ERROR: could not get the last modification date of file {file_path}: {err}", file_path = file_path.display());
-        })?;
+        })) else {
+            *failed += 1;
+            continue 'next_file;
+        };
 
         if file_type.is_dir() {
-            add_folder_to_model(&file_path, Arc::clone(&model), skipped, processed)?;
+            add_folder_to_model(&file_path, Arc::clone(&model), sandbox, skipped, processed, failed)?;
             continue 'next_file;
         }
-        let mut model = model.lock().unwrap();
-        if model.requires_reindexing(&file_path, last_modified)? {
-            println!("Indexing {file_path:?}...", file_path = file_path);
-            let content = match parse_entire_file_by_extension(&file_path) {
-                Ok(content) => content.chars().collect::<Vec<_>>(),
-                Err(()) => {
+        let requires_reindexing = model.lock().unwrap().requires_reindexing(&file_path, last_modified)?;
+        if requires_reindexing {
+            // Parsing may take up to the whole timeout, which the searches of the server do not wait for
+            let document = match sandbox.parse(&file_path, last_modified) {
+                Ok(Some(document)) => document,
+                Ok(None) => {
                     *skipped += 1;
                     continue 'next_file;
                 }
+                Err(()) => {
+                    *failed += 1;
+                    continue 'next_file;
+                }
             };
-            model.add_document(file_path, last_modified, &content)?;
+            // The members of an archive that could not be parsed fail on their own
+            *failed += document.failures;
+            let mut model = model.lock().unwrap();
+            add_document_to_model(&mut **model, file_path, last_modified, document, Language::default())?;
             *processed += 1;
         }
         else {
@@ -147,10 +137,90 @@ fn add_folder_to_model(dir_path: &Path, model: Arc<Mutex<Box<dyn Model + Send>>>
     Ok(())
 }
 
+/// Index the folder to completion and save the index when anything changed in it.
+/// Returns how many files failed to be indexed.
+fn index_folder(dir_path: &Path, model: Arc<Mutex<Box<dyn Model + Send>>>, sandbox: &mut Sandbox) -> Result<usize, ()> {
+    let mut skipped = 0;
+    let mut processed = 0;
+    let mut failed = 0;
+    let result = add_folder_to_model(dir_path, Arc::clone(&model), sandbox, &mut skipped, &mut processed, &mut failed);
+    if result.is_err() {
+        eprintln!("ERROR: indexing of {dir_path} stopped early", dir_path = dir_path.display());
+    }
+    if processed != 0 {
+        // The SQLite database is written as the documents are added
+        let model = model.lock().unwrap();
+        if let Some(in_memory) = model.as_any().downcast_ref::<InMemoryModel>() {
+            save_model_as_json(in_memory, &dir_path.join(".local_search_engine.json"))?;
+        }
+    }
+    else {
+        println!("No new files processed; index file remains unchanged.");
+    }
+    println!("Indexing complete. Processed: {processed} files, Skipped: {skipped} files, Failed: {failed} files.");
+    result.map(|()| failed)
+}
+
+/// Open the index of the folder, which is empty when there is none yet
+fn open_model(dir_path: &Path, use_sqlite_mode: bool) -> Result<Box<dyn Model + Send>, ()> {
+    if use_sqlite_mode {
+        let index_path = dir_path.join(".local_search_engine.db");
+        let sqlite_model = SqliteModel::open(&index_path).map_err(|err| {
+            eprintln!("ERROR: could not open sqlite database {index_path}: {err:?}", index_path = index_path.display());
+        })?;
+        return Ok(Box::new(sqlite_model));
+    }
+    let index_path = dir_path.join(".local_search_engine.json");
+    let exists = index_path.try_exists().map_err(|err| {
+        eprintln!("ERROR: could not check the existence of file {index_path}: {err}", index_path = index_path.display());
+    })?;
+    if !exists {
+        return Ok(Box::new(InMemoryModel::default()));
+    }
+    let index_file = File::open(&index_path).map_err(|err| {
+        eprintln!("ERROR: could not open index file {index_path}: {err}", index_path = index_path.display());
+    })?;
+    let model = serde_json::from_reader::<_, InMemoryModel>(index_file).map_err(|err| {
+        eprintln!("ERROR: could not parse index file {index_path}: {err}", index_path = index_path.display());
+    })?;
+    Ok(Box::new(model))
+}
+
+/// Set up the parsing of the files of the folder as its `.local_search_engine.toml` and the commands of `user_config` say.
+/// With `parser_flags`, the processes of --isolate configure their parsers like `parsers` are.
+fn open_sandbox(dir_path: &str, user_config: Option<&Path>, mut parsers: ParserRegistry, parser_flags: Vec<String>, isolate: bool) -> Result<Sandbox, ()> {
+    let config = Config::load_folder(Path::new(dir_path), user_config)?;
+    for parser in config.parsers() {
+        parsers.register(Box::new(parser));
+    }
+    // Without --isolate, files are still parsed in child processes once too many parsers got stuck in threads
+    let program = env::current_exe().map_err(|err| {
+        eprintln!("ERROR: could not find the path of the program to isolate parsers: {err}");
+    })?;
+    let arguments = parser_flags.into_iter().chain(["parse".to_string(), dir_path.to_string()]).collect();
+    if !isolate && config.max_memory.is_some() {
+        println!("WARNING: max_memory only limits the parsers of --isolate");
+    }
+    let quarantine = Quarantine::load(&Path::new(dir_path).join(".local_search_engine.quarantine.json"), &config);
+    Ok(Sandbox::new(parsers, config, Isolation { program, arguments }, isolate, quarantine))
+}
+
 fn usage(program: &str) {
-    eprintln!("USAGE: {program} <subcommand> [args...]", program = program);
+    eprintln!("USAGE: {program} [flags] <subcommand> [args...]", program = program);
     eprintln!("  Subcommands:");
     eprintln!("    serve <directory> [address]         start local HTTP server with Web Interface");
+    eprintln!("    index <directory>                   index the directory and exit, failing if any file could not be indexed");
+    eprintln!("    parse <directory> <file> <output>   parse a single file into a new JSON document, as --isolate does");
+    eprintln!("  Flags:");
+    eprintln!("    --sqlite                            keep the index in a SQLite database");
+    eprintln!("    --skip-code-blocks                  do not index the code blocks of Markdown files");
+    eprintln!("    --csv-rows                          index every row of CSV files as a document of its own");
+    eprintln!("    --isolate                           parse every file in a process of its own, which a crash only costs that file");
+    eprintln!("    --config <file>                     read the commands to run from <file>");
+    eprintln!("  Commands that turn other formats into text go in $XDG_CONFIG_HOME/local_search_engine/config.toml");
+    eprintln!("  or the file of --config, never in the folder that is indexed:");
+    eprintln!("    [commands]");
+    eprintln!("    rst = \"pandoc -t plain {{}}\"");
 }
 
 fn entry() -> Result<(), ()> {
@@ -158,9 +228,31 @@ fn entry() -> Result<(), ()> {
     let program = args.next().expect("path to program is provided");
     let mut subcommand = None;
     let mut use_sqlite_mode = false;
+    let mut isolate = false;
+    let mut user_config = Config::user_path();
+    let mut parsers = ParserRegistry::default();
+    // The flags that configure the parsers, for the processes of --isolate to use them too
+    let mut parser_flags = Vec::new();
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "--sqlite" => use_sqlite_mode = true,
+            "--isolate" => isolate = true,
+            "--config" => {
+                let path = args.next().ok_or_else(|| {
+                    usage(&program);
+                    eprintln!("ERROR: no file is provided for {arg}");
+                })?;
+                user_config = Some(PathBuf::from(&path));
+                parser_flags.extend([arg, path]);
+            }
+            "--skip-code-blocks" => {
+                parsers.register(Box::new(MarkdownParser { code_blocks: false }));
+                parser_flags.push(arg);
+            }
+            "--csv-rows" => {
+                parsers.register(Box::new(CsvParser { rows: true }));
+                parser_flags.push(arg);
+            }
             _ => {
                 subcommand = Some(arg);
                 break
@@ -178,66 +270,47 @@ fn entry() -> Result<(), ()> {
                 println!("ERROR: no directory path is provided for {subcommand} subcommand");
             })?;
             let address = args.next().unwrap_or("127.0.0.1:6969".to_string());
-            if use_sqlite_mode {
-                let mut index_path = Path::new(&dir_path).to_path_buf();
-                index_path.push(".local_search_engine.db");
-                let sqlite_model = SqliteModel::open(Path::new(&index_path)).map_err(|err| {
-                    eprintln!("ERROR: could not open sqlite database {index_path}: {err:?}", index_path = index_path.display());
-                })?;
-                let model: Arc<Mutex<Box<dyn Model + Send>>> = Arc::new(Mutex::new(Box::new(sqlite_model)));
-                {
-                    let model_clone = Arc::clone(&model);
-                    thread::spawn(move || {
-                        let mut skipped = 0;
-                        let mut processed = 0;
-                        add_folder_to_model(Path::new(&dir_path), Arc::clone(&model_clone), &mut skipped, &mut processed).unwrap();
-                        if processed != 0 {
-                            println!("Indexing complete for SQLite mode. Processed: {} files, Skipped: {} files.", processed, skipped);
-                        }
-                        else {
-                            println!("No new files processed; index file remains unchanged.");
-                        }
-                    });
-                }
-                server::start(&address, Arc::clone(&model))
-            } 
-            else {
-                let mut index_path = Path::new(&dir_path).to_path_buf();
-                index_path.push(".local_search_engine.json");
-                let exists = index_path.try_exists().map_err(|err| {
-                    eprintln!("ERROR: could not check the existence of file {index_path}: {err}", index_path = index_path.display());
-                })?;                
-                let model: Box<dyn Model + Send> = if exists {
-                    let index_file = File::open(&index_path).map_err(|err| {
-                        eprintln!("ERROR: could not open index file {index_path}: {err}", index_path = index_path.display());
-                    })?;
-                    Box::new(serde_json::from_reader::<_, InMemoryModel>(index_file).map_err(|err| {
-                        eprintln!("ERROR: could not parse index file {index_path}: {err}", index_path = index_path.display());
-                    })?)
-                } 
-                else {
-                    Box::new(InMemoryModel::default())
-                };
-                let model = Arc::new(Mutex::new(model));
-                {
-                    let model_clone = Arc::clone(&model);
-                    thread::spawn(move || {
-                        let mut skipped = 0;
-                        let mut processed = 0;
-                        add_folder_to_model(Path::new(&dir_path), Arc::clone(&model_clone), &mut skipped, &mut processed).unwrap();
-                        if processed != 0 {
-                            let model_guard = model_clone.lock().unwrap();
-                            let in_memory = model_guard.as_any().downcast_ref::<InMemoryModel>().expect("Expected an InMemoryModel");
-                            save_model_as_json(in_memory, &index_path).unwrap();
-                            println!("Indexing complete. Processed: {} files, Skipped: {} files.", processed, skipped);
-                        }
-                        else {
-                            println!("No new files processed; index file remains unchanged.");
-                        }
-                    });
-                }
-                server::start(&address, Arc::clone(&model))
+            // The server extracts the members of archives within the limits they are indexed with
+            let config = Config::load(&Path::new(&dir_path).join(".local_search_engine.toml"))?;
+            let mut sandbox = open_sandbox(&dir_path, user_config.as_deref(), parsers, parser_flags, isolate)?;
+            let model = Arc::new(Mutex::new(open_model(Path::new(&dir_path), use_sqlite_mode)?));
+            {
+                let model = Arc::clone(&model);
+                thread::spawn(move || {
+                    let _ = index_folder(Path::new(&dir_path), model, &mut sandbox);
+                });
+            }
+            server::start(&address, model, config)
+        },
+        "index" => {
+            let dir_path = args.next().ok_or_else(|| {
+                usage(&program);
+                eprintln!("ERROR: no directory path is provided for {subcommand} subcommand");
+            })?;
+            let mut sandbox = open_sandbox(&dir_path, user_config.as_deref(), parsers, parser_flags, isolate)?;
+            let model = Arc::new(Mutex::new(open_model(Path::new(&dir_path), use_sqlite_mode)?));
+            let failed = index_folder(Path::new(&dir_path), model, &mut sandbox)?;
+            if failed != 0 {
+                eprintln!("ERROR: {failed} files of {dir_path} could not be indexed");
+                return Err(());
+            }
+            Ok(())
+        },
+        "parse" => {
+            let (Some(dir_path), Some(file_path), Some(output_path)) = (args.next(), args.next(), args.next()) else {
+                usage(&program);
+                eprintln!("ERROR: {subcommand} subcommand needs a directory, a file and an output path");
+                return Err(());
+            };
+            let config = Config::load_folder(Path::new(&dir_path), user_config.as_deref())?;
+            for parser in config.parsers() {
+                parsers.register(Box::new(parser));
+            }
+            if let Some(max_memory) = config.max_memory {
+                sandbox::limit_memory(max_memory);
             }
+            let document = parsers.parse(Path::new(&file_path), config.limits(Path::new(&file_path)))?;
+            sandbox::write_document(&document, Path::new(&output_path))
         },
         _ => {
             usage(&program);
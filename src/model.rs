@@ -1,18 +1,22 @@
 use std::path::{Path, PathBuf};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::time::SystemTime;
 use serde::{Deserialize, Serialize};
 use std::result::Result;
 use std::any::Any;
 
-use super::lexer::Lexer;
+use super::lang::{self, Language};
+use super::lexer::{self, Lexer};
+use super::query::{self, FilterMatch, Query};
 
 pub trait Model: Send + Any {
     fn as_any(&self) -> &dyn Any;
-    fn add_document(&mut self, path: PathBuf, last_modified: SystemTime, content: &[char]) -> Result<(), ()>;
+    fn add_document(&mut self, path: PathBuf, last_modified: SystemTime, terms: Terms, fields: &Fields, metadata: Metadata) -> Result<(), ()>;
     fn remove_document(&mut self, file_path: &std::path::Path) -> Result<(), ()>;
     fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()>;
     fn requires_reindexing(&mut self, file_path: &Path, last_modified: SystemTime) -> Result<bool, ()>;
+    /// Metadata the document was indexed with, empty for a document that is not in the model
+    fn document_metadata(&self, file_path: &Path) -> Result<Metadata, ()>;
 }
 
 pub struct SqliteModel {
@@ -40,6 +44,7 @@ impl SqliteModel {
             eprintln!("ERROR: could not open sqlite database {}: {}", path.display(), err);
         })?;
         let this = Self { connection };
+        this.register_unicode_lower(path)?;
         this.execute("
             CREATE TABLE IF NOT EXISTS Documents (
                 id INTEGER NOT NULL PRIMARY KEY,
@@ -65,9 +70,41 @@ impl SqliteModel {
                 UNIQUE(term)
             );
         ")?;
+        this.execute("
+            CREATE TABLE IF NOT EXISTS DocMeta (
+                doc_id INTEGER,
+                key TEXT,
+                value TEXT,
+                UNIQUE(doc_id, key),
+                FOREIGN KEY(doc_id) REFERENCES Documents(id)
+            );
+        ")?;
         Ok(this)
     }
     
+    /// Add the `unicode_lower` SQL function, which lowercases text like the in-memory model does
+    /// where the `lower` of SQLite only knows about ASCII
+    fn register_unicode_lower(&self, path: &Path) -> Result<(), ()> {
+        let result = unsafe {
+            sqlite3_sys::sqlite3_create_function_v2(
+                self.connection.as_raw(),
+                c"unicode_lower".as_ptr(),
+                1,
+                sqlite3_sys::SQLITE_UTF8 | sqlite3_sys::SQLITE_DETERMINISTIC,
+                std::ptr::null_mut(),
+                Some(unicode_lower),
+                None,
+                None,
+                None,
+            )
+        };
+        if result != sqlite3_sys::SQLITE_OK {
+            eprintln!("ERROR: could not add the unicode_lower function to sqlite database {}: error code {}", path.display(), result);
+            return Err(());
+        }
+        Ok(())
+    }
+
     fn execute_with_binding(&self, query: &str, bindings: &[(&str, sqlite::Value)]) -> Result<(), ()> {
         let mut stmt = self.connection.prepare(query).map_err(|err| {
             eprintln!("ERROR: could not prepare query {}: {}", query, err);
@@ -80,34 +117,8 @@ impl SqliteModel {
         })?;
         Ok(())
     }
-}
-
-impl Model for SqliteModel {
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
 
-    fn remove_document(&mut self, file_path: &std::path::Path) -> Result<(), ()> {
-        let query = "SELECT id FROM Documents WHERE path = :path";
-        let mut stmt = self.connection.prepare(query).map_err(|err| {
-            eprintln!("ERROR: Could not prepare query {}: {}", query, err);
-        })?;
-        let bindings: Vec<(&str, sqlite::Value)> = vec![
-            (":path", sqlite::Value::String(file_path.display().to_string()))
-        ];
-        stmt.bind_iter(bindings.iter().cloned()).map_err(|err| {
-            eprintln!("ERROR: Could not bind path for document removal: {}", err);
-        })?;
-        let doc_id: i64 = match stmt.next().map_err(|err| {
-            eprintln!("ERROR: Could not execute query {}: {}", query, err);
-        })? {
-            sqlite::State::Row => stmt.read("id").map_err(|err| {
-                eprintln!("ERROR: Could not read document id: {}", err);
-            })?,
-            sqlite::State::Done => {
-                return Ok(());
-            }
-        };
+    fn remove_document_by_id(&self, doc_id: i64) -> Result<(), ()> {
         let term_query = "SELECT term FROM TermFreq WHERE doc_id = :doc_id";
         let mut term_stmt = self.connection.prepare(term_query).map_err(|err| {
             eprintln!("ERROR: Could not prepare query {}: {}", term_query, err);
@@ -147,6 +158,12 @@ impl Model for SqliteModel {
                 eprintln!("ERROR: Could not execute query {}: {}", delete_termfreq, err);
             })?;
         }
+        self.execute_with_binding(
+            "DELETE FROM DocMeta WHERE doc_id = :doc_id",
+            &[
+                (":doc_id", sqlite::Value::Integer(doc_id))
+            ]
+        )?;
         let delete_doc = "DELETE FROM Documents WHERE id = :doc_id";
         {
             let mut stmt = self.connection.prepare(delete_doc).map_err(|err| {
@@ -165,10 +182,126 @@ impl Model for SqliteModel {
         Ok(())
     }
 
-    fn add_document(&mut self, path: PathBuf, last_modified: SystemTime, content: &[char]) -> Result<(), ()> {
+    /// The documents that pass the filters of a query that does nothing but filter them, unranked
+    fn filtered_documents(&self, query: &Query) -> Result<Vec<(PathBuf, f32)>, ()> {
+        let kinds = match (query.only_filters(false), query.only_filters(true)) {
+            (false, false) => return Ok(vec![]),
+            (true, true) => "1",
+            (false, true) => "IFNULL(Mime.value, '') LIKE 'message/%'",
+            (true, false) => "IFNULL(Mime.value, '') NOT LIKE 'message/%'",
+        };
+        let sql = format!(
+            "
+                SELECT Documents.path as path
+                FROM Documents
+                LEFT JOIN DocMeta AS Mime ON Mime.doc_id = Documents.id AND Mime.key = 'mime'
+                WHERE {kinds} {filters}
+            ", filters = filter_conditions(query)
+        );
+        let mut stmt = self.connection.prepare(sql.as_str()).map_err(|err| {
+            eprintln!("ERROR: Could not prepare such query: {err}");
+        })?;
+        bind_filters(&mut stmt, query)?;
+        let mut results = Vec::new();
+        while let sqlite::State::Row = stmt.next().map_err(|err| {
+            eprintln!("ERROR: Error executing filter query: {err}");
+        })? {
+            let path = stmt.read::<String, _>("path").map_err(|err| {
+                eprintln!("ERROR: Could not read document path: {err}");
+            })?;
+            results.push((PathBuf::from(path), 0.0));
+        }
+        Ok(results)
+    }
+}
+
+unsafe extern "C" fn unicode_lower(context: *mut sqlite3_sys::sqlite3_context, _argc: std::ffi::c_int, argv: *mut *mut sqlite3_sys::sqlite3_value) {
+    let value = *argv;
+    let text = sqlite3_sys::sqlite3_value_text(value);
+    if text.is_null() {
+        sqlite3_sys::sqlite3_result_null(context);
+        return;
+    }
+    let bytes = std::slice::from_raw_parts(text, sqlite3_sys::sqlite3_value_bytes(value) as usize);
+    let lower = String::from_utf8_lossy(bytes).to_lowercase();
+    // SQLITE_TRANSIENT: SQLite makes a copy of the text before `lower` is dropped
+    let transient = std::mem::transmute::<isize, unsafe extern "C" fn(*mut std::ffi::c_void)>(-1);
+    sqlite3_sys::sqlite3_result_text(context, lower.as_ptr().cast(), lower.len() as std::ffi::c_int, Some(transient));
+}
+
+/// SQL conditions on `Documents` and its `Mime` metadata for the filters of the query,
+/// whose values are bound by `bind_filters`
+fn filter_conditions(query: &Query) -> String {
+    let mut filters = String::new();
+    for (i, filter) in query.filters.iter().enumerate() {
+        let condition = match filter.how {
+            FilterMatch::Equals => format!("unicode_lower(DocMeta.value) = :filter_value{i}"),
+            FilterMatch::Contains => format!("instr(unicode_lower(DocMeta.value), :filter_value{i}) > 0"),
+            FilterMatch::StartsWith => format!("substr(unicode_lower(DocMeta.value), 1, length(:filter_value{i})) = :filter_value{i}"),
+        };
+        let exists = format!("EXISTS (SELECT 1 FROM DocMeta WHERE DocMeta.doc_id = Documents.id AND DocMeta.key = :filter_key{i} AND {condition})");
+        if filter.messages_only {
+            filters.push_str(&format!("AND (IFNULL(Mime.value, '') NOT LIKE 'message/%' OR {exists}) "));
+        } else {
+            filters.push_str(&format!("AND {exists} "));
+        }
+    }
+    filters
+}
+
+fn bind_filters(stmt: &mut sqlite::Statement, query: &Query) -> Result<(), ()> {
+    for (i, filter) in query.filters.iter().enumerate() {
+        let bindings: Vec<(String, sqlite::Value)> = vec![
+            (format!(":filter_key{i}"), sqlite::Value::String(filter.key.clone())),
+            (format!(":filter_value{i}"), sqlite::Value::String(filter.value.to_lowercase())),
+        ];
+        stmt.bind_iter(bindings.iter().map(|(name, value)| (name.as_str(), value.clone()))).map_err(|err| {
+            eprintln!("ERROR: Could not bind filter {}:{}: {err}", filter.key, filter.value);
+        })?;
+    }
+    Ok(())
+}
+
+impl Model for SqliteModel {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn remove_document(&mut self, file_path: &std::path::Path) -> Result<(), ()> {
+        // The parts are ranges of paths, which the index on the paths finds without a scan
+        let query = "SELECT id FROM Documents WHERE path = :path OR (path >= :fragment AND path < :fragment_end) OR (path >= :member AND path < :member_end)";
+        let mut stmt = self.connection.prepare(query).map_err(|err| {
+            eprintln!("ERROR: Could not prepare query {}: {}", query, err);
+        })?;
+        let [fragment, member] = PART_SEPARATORS.map(|separator| part_path(file_path, separator).display().to_string());
+        let bindings: Vec<(&str, sqlite::Value)> = vec![
+            (":path", sqlite::Value::String(file_path.display().to_string())),
+            (":fragment", sqlite::Value::String(fragment.clone())),
+            (":fragment_end", sqlite::Value::String(prefix_end(&fragment))),
+            (":member", sqlite::Value::String(member.clone())),
+            (":member_end", sqlite::Value::String(prefix_end(&member))),
+        ];
+        stmt.bind_iter(bindings.iter().cloned()).map_err(|err| {
+            eprintln!("ERROR: Could not bind path for document removal: {}", err);
+        })?;
+        let mut doc_ids = Vec::new();
+        while let sqlite::State::Row = stmt.next().map_err(|err| {
+            eprintln!("ERROR: Could not execute query {}: {}", query, err);
+        })? {
+            doc_ids.push(stmt.read::<i64, _>("id").map_err(|err| {
+                eprintln!("ERROR: Could not read document id: {}", err);
+            })?);
+        }
+        for doc_id in doc_ids {
+            self.remove_document_by_id(doc_id)?;
+        }
+        Ok(())
+    }
+
+    fn add_document(&mut self, path: PathBuf, last_modified: SystemTime, terms: Terms, fields: &Fields, metadata: Metadata) -> Result<(), ()> {
         self.begin()?;
         self.remove_document(&path)?;
-        let terms = Lexer::new(content).collect::<Vec<_>>();
+        let language = document_language(&metadata);
         let lm_ts = last_modified.duration_since(SystemTime::UNIX_EPOCH).map_err(|_| ())?.as_secs() as i64;
         let doc_id = {
             let query = "INSERT INTO Documents (path, term_count, last_modified) VALUES (:path, :count, :last_modified)";
@@ -178,7 +311,7 @@ impl Model for SqliteModel {
             let mut stmt = self.connection.prepare(query).map_err(log_err)?;
             let bindings: Vec<(&str, sqlite::Value)> = vec![
                 (":path", sqlite::Value::String(path.display().to_string())),
-                (":count", sqlite::Value::Integer(terms.len() as i64)),
+                (":count", sqlite::Value::Integer(terms.count as i64)),
                 (":last_modified", sqlite::Value::Integer(lm_ts)),
             ];
             stmt.bind_iter(bindings.iter().cloned()).map_err(log_err)?;
@@ -187,8 +320,18 @@ impl Model for SqliteModel {
                 sqlite3_sys::sqlite3_last_insert_rowid(self.connection.as_raw())
             }
         };        
-        let mut tf = TermFreq::new();
-        for term in Lexer::new(content) {
+        for (key, value) in &metadata {
+            self.execute_with_binding(
+                "INSERT INTO DocMeta(doc_id, key, value) VALUES(:doc_id, :key, :value)",
+                &[
+                    (":doc_id", sqlite::Value::Integer(doc_id)),
+                    (":key", sqlite::Value::String(key.clone())),
+                    (":value", sqlite::Value::String(value.clone())),
+                ]
+            )?;
+        }
+        let mut tf = terms.tf;
+        for term in field_terms(fields, language) {
             *tf.entry(term).or_insert(0) += 1;
         }
         for (term, freq) in &tf {
@@ -240,9 +383,16 @@ impl Model for SqliteModel {
     }
 
     fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()> {
-        let tokens = Lexer::new(query).collect::<Vec<_>>();
+        let query = Query::parse(query);
+        let tokens_by_language = Language::ALL.iter()
+            .flat_map(|language| [false, true].map(|message| ((*language, message), query.terms(*language, message))))
+            .collect::<HashMap<_, _>>();
+        let mut tokens = tokens_by_language.values().flatten().cloned().collect::<Vec<_>>();
+        tokens.sort();
+        tokens.dedup();
+        let mut results = self.filtered_documents(&query)?;
         if tokens.is_empty() {
-            return Ok(vec![]);
+            return Ok(results);
         }
         let mut param_names = Vec::new();
         for i in 0..tokens.len() {
@@ -267,14 +417,17 @@ impl Model for SqliteModel {
             };
             count
         };
+        let filters = filter_conditions(&query);
         let sql = format!(
             "
-                SELECT Documents.path as path, Documents.term_count as term_count, TermFreq.freq as tf, DocFreq.freq as df
+                SELECT Documents.path as path, Documents.term_count as term_count, TermFreq.term as term, TermFreq.freq as tf, DocFreq.freq as df, Lang.value as lang, Mime.value as mime
                 FROM TermFreq
                 JOIN Documents ON Documents.id = TermFreq.doc_id
                 JOIN DocFreq ON TermFreq.term = DocFreq.term
-                WHERE TermFreq.term IN ({})
-            ", placeholders
+                LEFT JOIN DocMeta AS Lang ON Lang.doc_id = Documents.id AND Lang.key = 'lang'
+                LEFT JOIN DocMeta AS Mime ON Mime.doc_id = Documents.id AND Mime.key = 'mime'
+                WHERE TermFreq.term IN ({}) {}
+            ", placeholders, filters
         );
         let mut stmt = self.connection.prepare(sql.as_str()).map_err(|err| {
             eprintln!("ERROR: Could not prepare such query: {err}");
@@ -285,10 +438,26 @@ impl Model for SqliteModel {
                 eprintln!("ERROR: Could not bind parameter {} for token '{}': {err}", param_name, token, err = err);
             })?;
         }
+        bind_filters(&mut stmt, &query)?;
         let mut scores = HashMap::new();
         while let sqlite::State::Row = stmt.next().map_err(|err| {
             eprintln!("ERROR: Error executing search query: {err}");
         })? {
+            let term = stmt.read::<String, _>("term").map_err(|err| {
+                eprintln!("ERROR: Could not read matched term: {err}");
+            })?;
+            let lang = stmt.read::<Option<String>, _>("lang").map_err(|err| {
+                eprintln!("ERROR: Could not read document language: {err}");
+            })?;
+            let language = lang.as_deref().and_then(Language::from_code).unwrap_or_default();
+            let mime = stmt.read::<Option<String>, _>("mime").map_err(|err| {
+                eprintln!("ERROR: Could not read document MIME type: {err}");
+            })?;
+            let message = mime.is_some_and(|mime| mime.starts_with("message/"));
+            if query.only_filters(message) || !tokens_by_language[&(language, message)].contains(&term) {
+                // The term came from the query normalized for another language or kind of document
+                continue;
+            }
             let path_str = stmt.read::<String, _>("path").map_err(|err| {
                 eprintln!("ERROR: Could not read document path: {err}");
             })?;
@@ -301,13 +470,13 @@ impl Model for SqliteModel {
             let df = stmt.read::<f64, _>("df").map_err(|err| {
                 eprintln!("ERROR: Could not read document frequncy: {err}");
             })?;
-            let tf_ratio = (tf as f64) / (term_count as f64);
+            let tf_ratio = if term_count > 0.0 { tf / term_count } else { 0.0 };
             let idf = ((total_docs as f64) / ((df as f64).max(1.0) as f64)).log10();
             let score = tf_ratio * idf;
             let path: PathBuf = PathBuf::from(path_str);
             *scores.entry(path).or_insert(0.0) += score;
         }
-        let mut results = scores.into_iter().map(|(path, score)| (path, score as f32)).collect::<Vec<(_, _)>>();
+        results.extend(scores.into_iter().map(|(path, score)| (path, score as f32)));
         results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
         Ok(results)
     }
@@ -338,19 +507,193 @@ impl Model for SqliteModel {
             }
         }
     }
+
+    fn document_metadata(&self, file_path: &Path) -> Result<Metadata, ()> {
+        let query = "SELECT key, value FROM DocMeta JOIN Documents ON Documents.id = DocMeta.doc_id WHERE Documents.path = :path";
+        let mut stmt = self.connection.prepare(query).map_err(|err| {
+            eprintln!("ERROR: Could not prepare query {}: {}", query, err);
+        })?;
+        let bindings: Vec<(&str, sqlite::Value)> = vec![
+            (":path", sqlite::Value::String(file_path.display().to_string()))
+        ];
+        stmt.bind_iter(bindings.iter().cloned()).map_err(|err| {
+            eprintln!("ERROR: Could not bind path for document_metadata: {}", err);
+        })?;
+        let mut metadata = Metadata::new();
+        while let sqlite::State::Row = stmt.next().map_err(|err| {
+            eprintln!("ERROR: Could not execute query {}: {}", query, err);
+        })? {
+            let read = |column| stmt.read::<String, _>(column).map_err(|err| {
+                eprintln!("ERROR: Could not read document metadata: {}", err);
+            });
+            metadata.insert(read("key")?, read("value")?);
+        }
+        Ok(metadata)
+    }
 }
 
 pub type TermFreq = HashMap<String, usize>;
 pub type DocFreq = HashMap<String, usize>;
+pub type Metadata = BTreeMap<String, String>;
+/// Named parts of a document (title, headings, ...) that queries can target with `name:word`
+pub type Fields = Vec<(String, String)>;
+
+/// Terms of the fields of a document, which do not count towards its length
+fn field_terms(fields: &Fields, language: Language) -> Vec<String> {
+    let mut terms = Vec::new();
+    for (name, text) in fields {
+        let text = text.chars().collect::<Vec<_>>();
+        terms.extend(lexer::field_terms(name, &text, language));
+    }
+    terms
+}
+
+/// How many characters of text are lexed at once
+const CHUNK_LEN: usize = 64 * 1024;
+/// How much of the beginning of a text its language is detected from
+const DETECTION_LEN: usize = 64 * 1024;
+
+/// Terms of the content of a document and how many there are. The words of the content in their
+/// exact form are among the terms, but do not count towards the length of the document.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Terms {
+    pub tf: TermFreq,
+    pub count: usize,
+}
+
+/// Counts the terms of a text that comes in chunks, so that the text never has to be
+/// in memory as a whole, let alone as the `Vec<char>` the lexer works on
+pub struct TermCounter {
+    /// Unknown until enough of the text came to detect it
+    language: Option<Language>,
+    /// Language of the text when it is too short to tell
+    fallback: Language,
+    /// Characters yet to be lexed: the beginning of the text until its language is known,
+    /// then the word at the end of the last chunk, which the next chunk may go on with
+    pending: Vec<char>,
+    /// Offset and position in the whole text of the first character of `pending`
+    offset: usize,
+    position: usize,
+    terms: Terms,
+    /// Bytes of text the counter still takes, the rest is left out
+    room: usize,
+    truncated: bool,
+}
+
+impl TermCounter {
+    /// Counter for a text in `language`, or in the language detected from its beginning when it is `None`
+    pub fn new(language: Option<Language>, fallback: Language) -> Self {
+        Self { language, fallback, pending: Vec::new(), offset: 0, position: 0, terms: Terms::default(), room: usize::MAX, truncated: false }
+    }
+
+    /// Leave out the text beyond `max_text` bytes
+    pub fn with_limit(mut self, max_text: usize) -> Self {
+        self.room = max_text;
+        self
+    }
+
+    /// Whether some of the text was left out, after which the counter takes no more
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn push(&mut self, mut text: &str) {
+        if text.len() > self.room {
+            text = &text[..floor_char_boundary(text, self.room)];
+            self.truncated = true;
+        }
+        self.room -= text.len();
+        let mut chars = text.chars().peekable();
+        while chars.peek().is_some() {
+            self.pending.extend(chars.by_ref().take(CHUNK_LEN));
+            if self.language.is_none() {
+                if self.pending.len() < DETECTION_LEN {
+                    continue;
+                }
+                self.language = Some(lang::detect(&self.pending).unwrap_or(self.fallback));
+            }
+            let end = match self.pending.iter().rposition(|c| !c.is_alphanumeric()) {
+                Some(last) => last + 1,
+                // A word longer than a chunk is cut rather than kept whole
+                None if self.pending.len() > CHUNK_LEN => self.pending.len(),
+                None => 0,
+            };
+            self.count(end);
+        }
+    }
+
+    /// The terms of the whole text and its language
+    pub fn finish(mut self) -> (Terms, Language) {
+        let language = *self.language.get_or_insert_with(|| lang::detect(&self.pending).unwrap_or(self.fallback));
+        self.count(self.pending.len());
+        (self.terms, language)
+    }
+
+    fn count(&mut self, end: usize) {
+        let language = self.language.unwrap_or(self.fallback);
+        let content = &self.pending[..end];
+        let mut tokens = Lexer::new(content, language).continuing(self.offset, self.position);
+        for token in tokens.by_ref() {
+            // The term is only copied the first time it comes up
+            match self.terms.tf.get_mut(&*token.term) {
+                Some(freq) => *freq += 1,
+                None => {
+                    self.terms.tf.insert(token.term.to_string(), 1);
+                }
+            }
+            self.terms.count += 1;
+        }
+        self.position = tokens.position();
+        for term in lexer::exact_terms(content, language) {
+            *self.terms.tf.entry(term).or_insert(0) += 1;
+        }
+        self.offset += end;
+        self.pending.drain(..end);
+    }
+}
+
+/// What the names of sub-documents start with: `#` for the fragments of a document like
+/// the chapters of a book, `!/` for the members of an archive
+pub const PART_SEPARATORS: [&str; 2] = ["#", "!/"];
+
+/// Path under which the sub-document `name` of the file is indexed.
+/// Removing the file from the model removes all of its sub-documents too.
+pub fn part_path(file_path: &Path, name: &str) -> PathBuf {
+    let mut path = file_path.as_os_str().to_owned();
+    path.push(name);
+    PathBuf::from(path)
+}
+
+/// The first string after all the strings that start with `prefix`, which ends in an ASCII character like the separators of parts
+fn prefix_end(prefix: &str) -> String {
+    let mut end = prefix.to_string();
+    if let Some(last) = end.pop() {
+        end.push(char::from_u32(last as u32 + 1).unwrap_or(char::MAX));
+    }
+    end
+}
+
+/// The largest index of a character boundary of the text up to `index`
+pub fn floor_char_boundary(text: &str, index: usize) -> usize {
+    (0..=index.min(text.len())).rev().find(|i| text.is_char_boundary(*i)).unwrap_or(0)
+}
+
+/// Language the document was indexed in, recorded under the `lang` key of its metadata
+pub fn document_language(metadata: &Metadata) -> Language {
+    metadata.get("lang").and_then(|code| Language::from_code(code)).unwrap_or_default()
+}
 
 #[derive(Deserialize, Serialize)]
 pub struct Doc {
     tf: TermFreq,
     count: usize,
-    last_modified: SystemTime
+    last_modified: SystemTime,
+    #[serde(default)]
+    metadata: Metadata,
 }
 
-type Docs = HashMap<PathBuf, Doc>;
+/// Ordered by path so that the parts of a document are found together
+type Docs = BTreeMap<PathBuf, Doc>;
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct InMemoryModel {
@@ -364,27 +707,35 @@ impl Model for InMemoryModel {
     }
 
     fn remove_document(&mut self, file_path: &Path) -> Result<(), ()>{
-        if let Some(doc) = self.docs.remove(file_path) {
-            for t in doc.tf.keys() {
-                if let Some(f) = self.df.get_mut(t) {
-                    *f -= 1;
+        let mut parts = Vec::new();
+        for prefix in PART_SEPARATORS.map(|separator| part_path(file_path, separator)) {
+            let starts_with_prefix = |path: &Path| path.as_os_str().as_encoded_bytes().starts_with(prefix.as_os_str().as_encoded_bytes());
+            // Paths are ordered by their components, so the ones that start with `file.pdf#` follow it, and the ones
+            // that start with `bundle.zip!/` follow `bundle.zip!`, which is the same path as the prefix
+            parts.extend(self.docs.range(prefix.clone()..)
+                .map(|(path, _)| path)
+                .skip_while(|path| **path == prefix && !starts_with_prefix(path))
+                .take_while(|path| starts_with_prefix(path))
+                .cloned());
+        }
+        for path in parts.iter().map(|path| path.as_path()).chain(Some(file_path)) {
+            if let Some(doc) = self.docs.remove(path) {
+                for t in doc.tf.keys() {
+                    if let Some(f) = self.df.get_mut(t) {
+                        *f -= 1;
+                    }
                 }
             }
         }
         Ok(())
     }
 
-    fn add_document(&mut self, file_path: PathBuf, last_modified: SystemTime, content: &[char]) -> Result<(), ()> {
+    fn add_document(&mut self, file_path: PathBuf, last_modified: SystemTime, terms: Terms, fields: &Fields, metadata: Metadata) -> Result<(), ()> {
         self.remove_document(&file_path)?;
-        let mut tf = TermFreq::new();
-        let mut count = 0;
-        for t in Lexer::new(&content) {
-            if let Some(f) = tf.get_mut(&t) {
-                *f += 1;
-            } else {
-                tf.insert(t, 1);
-            }
-            count += 1;
+        let language = document_language(&metadata);
+        let Terms { mut tf, count } = terms;
+        for t in field_terms(fields, language) {
+            *tf.entry(t).or_insert(0) += 1;
         }
         for t in tf.keys() {
             if let Some(f) = self.df.get_mut(t) {
@@ -393,16 +744,30 @@ impl Model for InMemoryModel {
                 self.df.insert(t.to_string(), 1);
             }
         }
-        self.docs.insert(file_path, Doc {count, tf, last_modified});
+        self.docs.insert(file_path, Doc {count, tf, last_modified, metadata});
         Ok(())
     }
 
     fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()> {
         let mut result = Vec::new();
-        let tokens = Lexer::new(&query).collect::<Vec<_>>();
+        let query = Query::parse(query);
+        let mut tokens_by_language = HashMap::new();
         for (path, doc) in &self.docs {
+            if !query.matches(&doc.metadata) {
+                continue;
+            }
+            let language = document_language(&doc.metadata);
+            let message = query::is_message(&doc.metadata);
+            if query.only_filters(message) {
+                result.push((path.clone(), 0f32));
+                continue;
+            }
+            let tokens = tokens_by_language.entry((language, message)).or_insert_with(|| query.terms(language, message));
+            if !tokens.iter().any(|token| doc.tf.contains_key(token)) {
+                continue;
+            }
             let mut rank = 0f32;
-            for token in &tokens {
+            for token in tokens.iter() {
                 rank += compute_tf(&token, doc) * compute_idf(&token, self.docs.len(), &self.df);
             }
             if !rank.is_nan() {
@@ -420,9 +785,18 @@ impl Model for InMemoryModel {
         }
         return Ok(true);
     }
+
+    fn document_metadata(&self, file_path: &Path) -> Result<Metadata, ()> {
+        Ok(self.docs.get(file_path).map(|doc| doc.metadata.clone()).unwrap_or_default())
+    }
 }
 
+/// Frequency of the term relative to the length of the document. A document whose text has no
+/// terms can still match in its fields, which do not count towards the length.
 fn compute_tf(t: &str, doc: &Doc) -> f32 {
+    if doc.count == 0 {
+        return 0.0;
+    }
     let n = doc.count as f32;
     let m = doc.tf.get(t).cloned().unwrap_or(0) as f32;
     m / n
@@ -432,4 +806,114 @@ fn compute_idf(t: &str, n: usize, df: &DocFreq) -> f32 {
     let n = n as f32;
     let m = df.get(t).cloned().unwrap_or(1) as f32;
     (n / m).log10()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(model: &mut dyn Model, path: &str, text: &str, fields: &[(&str, &str)], metadata: &[(&str, &str)]) {
+        let metadata = metadata.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect::<Metadata>();
+        let mut counter = TermCounter::new(Some(document_language(&metadata)), Language::English);
+        counter.push(text);
+        let fields = fields.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect::<Fields>();
+        model.add_document(PathBuf::from(path), SystemTime::UNIX_EPOCH, counter.finish().0, &fields, metadata).unwrap();
+    }
+
+    fn search(model: &dyn Model, query: &str) -> Vec<(String, f32)> {
+        let mut results = model.search_query(&query.chars().collect::<Vec<_>>()).unwrap().into_iter()
+            .map(|(path, rank)| (path.display().to_string(), rank))
+            .collect::<Vec<_>>();
+        results.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
+        results
+    }
+
+    #[test]
+    fn in_memory_and_sqlite_models_agree() {
+        let mut in_memory = InMemoryModel::default();
+        let mut sqlite = SqliteModel::open(Path::new(":memory:")).unwrap();
+        for model in [&mut in_memory as &mut dyn Model, &mut sqlite] {
+            add(model, "report.txt", "The quarterly report on invoices", &[], &[("lang", "en"), ("mime", "text/plain")]);
+            add(model, "bericht.txt", "Der Bericht über die Rechnungen", &[], &[("lang", "de"), ("mime", "text/plain")]);
+            add(model, "mail.eml", "Please pay the invoice", &[], &[("lang", "en"), ("mime", "message/rfc822"), ("from", "Alice <alice@example.com>")]);
+            add(model, "tickets.csv", "", &[("subject", "invoice"), ("from", "alice")], &[("lang", "en"), ("mime", "text/csv")]);
+        }
+        for query in ["invoice", "lang:de", "from:alice", "subject:invoice", "invoice lang:en", "=the", "the", "", "nothing"] {
+            let expected = search(&in_memory, query);
+            let actual = search(&sqlite, query);
+            assert_eq!(expected.len(), actual.len(), "{query:?}: {expected:?} and {actual:?}");
+            for ((path1, rank1), (path2, rank2)) in expected.iter().zip(&actual) {
+                assert!(path1 == path2 && rank1.is_finite() && (rank1 - rank2).abs() < 1e-6, "{query:?}: {expected:?} and {actual:?}");
+            }
+        }
+        let paths = |query| search(&in_memory, query).into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+        assert_eq!(paths("lang:de"), ["bericht.txt"]);
+        assert_eq!(paths("from:alice"), ["mail.eml", "tickets.csv"]);
+        assert_eq!(paths("invoice"), ["mail.eml", "report.txt"]);
+        assert!(paths("the").is_empty());
+        assert!(paths("").is_empty());
+    }
+
+    #[test]
+    fn filters_fold_case_beyond_ascii_in_both_models() {
+        let mut in_memory = InMemoryModel::default();
+        let mut sqlite = SqliteModel::open(Path::new(":memory:")).unwrap();
+        for model in [&mut in_memory as &mut dyn Model, &mut sqlite] {
+            add(model, "mail.eml", "Bitte zahlen", &[], &[("lang", "de"), ("mime", "message/rfc822"), ("from", "ÜNAL Öztürk <unal@example.com>"), ("subject", "ÉTÉ")]);
+            add(model, "other.eml", "Bitte zahlen", &[], &[("lang", "de"), ("mime", "message/rfc822"), ("from", "Bob <bob@example.com>")]);
+        }
+        for query in ["from:ünal", "from:ÖZTÜRK", "subject:été", "zahlen from:üna"] {
+            let paths = |model: &dyn Model| search(model, query).into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+            assert_eq!(paths(&in_memory), ["mail.eml"], "{query:?}");
+            assert_eq!(paths(&sqlite), ["mail.eml"], "{query:?}");
+        }
+    }
+
+    #[test]
+    fn documents_are_removed_with_their_parts_only() {
+        let paths = [
+            "a.pdf", "a.pdf#page=1", "a.pdf#page=2", "b.zip", "b.zip!/docs/intro.md", "b.zip!/inner.tar!/deep.txt",
+            "a.pdfx", "a.pdf.txt", "b.zip!", "b.zip.txt", "b.zipper/notes.txt", "c.pdf#page=1",
+        ];
+        let mut in_memory = InMemoryModel::default();
+        let mut sqlite = SqliteModel::open(Path::new(":memory:")).unwrap();
+        for model in [&mut in_memory as &mut dyn Model, &mut sqlite] {
+            for path in paths {
+                add(model, path, "text", &[], &[("mime", "text/plain")]);
+            }
+            model.remove_document(Path::new("a.pdf")).unwrap();
+            model.remove_document(Path::new("b.zip")).unwrap();
+            let left = paths.into_iter().filter(|path| !model.document_metadata(Path::new(path)).unwrap().is_empty()).collect::<Vec<_>>();
+            assert_eq!(left, ["a.pdfx", "a.pdf.txt", "b.zip!", "b.zip.txt", "b.zipper/notes.txt", "c.pdf#page=1"]);
+        }
+    }
+
+    #[test]
+    fn short_text_falls_back_on_the_given_language() {
+        let mut counter = TermCounter::new(None, Language::German);
+        counter.push("Hallo");
+        let (terms, language) = counter.finish();
+        assert_eq!(language, Language::German);
+        assert_eq!(terms.tf.get("hallo"), Some(&1));
+    }
+
+    #[test]
+    fn declared_language_is_not_detected_again() {
+        let mut counter = TermCounter::new(Some(Language::French), Language::English);
+        counter.push("The quick brown fox jumps over the lazy dog while the children are watching.");
+        assert_eq!(counter.finish().1, Language::French);
+    }
+
+    #[test]
+    fn long_text_is_detected_and_counted_across_chunks() {
+        let sentence = "Der schnelle braune Fuchs springt über den faulen Hund. ";
+        let mut counter = TermCounter::new(None, Language::English);
+        let repeats = 3 * CHUNK_LEN / sentence.len();
+        for _ in 0..repeats {
+            counter.push(sentence);
+        }
+        let (terms, language) = counter.finish();
+        assert_eq!(language, Language::German);
+        assert_eq!(terms.tf.get("fuch"), Some(&repeats));
+        assert_eq!(terms.tf.get("=über"), Some(&repeats));
+    }
+}
@@ -0,0 +1,325 @@
+use std::io::{Cursor, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use flate2::read::MultiGzDecoder;
+use zip::ZipArchive;
+
+use crate::config::Limits;
+use super::sniff::{self, Sniffed};
+use super::{ParsedDocument, ParserRegistry};
+
+/// Archive formats whose members are indexed as documents of their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Archive {
+    Zip,
+    Tar,
+    /// A single compressed file, which is often a tar archive itself
+    Gzip,
+}
+
+impl Archive {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Archive::Zip => "application/zip",
+            Archive::Tar => "application/x-tar",
+            Archive::Gzip => "application/gzip",
+        }
+    }
+}
+
+/// Virtual path of a member of an archive, like `bundle.zip!/docs/intro.md`
+pub fn member_path(archive_path: &Path, member: &str) -> PathBuf {
+    let mut path = archive_path.as_os_str().to_owned();
+    path.push(member_name(member));
+    PathBuf::from(path)
+}
+
+fn member_name(member: &str) -> String {
+    format!("!/{member}", member = member.trim_start_matches('/'))
+}
+
+/// Members that are not worth looking at, like the resource forks macOS leaves in ZIP archives
+fn is_ignored(member: &str) -> bool {
+    member.split('/').any(|segment| segment.starts_with('.') && segment != "." && segment != "..")
+        || member.starts_with("__MACOSX/")
+}
+
+/// Parse every member of the archive with the registry. The members become the parts of the
+/// returned document. The ones that cannot be read or parsed are counted in its `failures`, the
+/// ones that no parser supports are skipped. Archives can say anything about the size of their
+/// members, so it is what is decompressed that is kept within `max_file_size`, nested archives included.
+pub fn parse(registry: &ParserRegistry, file_path: &Path, bytes: &[u8], archive: Archive, limits: Limits) -> Result<Option<ParsedDocument>, ()> {
+    let mut budget = limits.max_file_size;
+    parse_within(registry, file_path, bytes, archive, limits, &mut budget)
+}
+
+/// Parse the archive with `budget` bytes left to decompress
+fn parse_within(registry: &ParserRegistry, file_path: &Path, bytes: &[u8], archive: Archive, limits: Limits, budget: &mut u64) -> Result<Option<ParsedDocument>, ()> {
+    println!("Indexing {file_path:?} as {mime_type} archive...", mime_type = archive.mime_type());
+    let mut document = ParsedDocument::default();
+    match archive {
+        Archive::Zip => {
+            let mut zip = ZipArchive::new(Cursor::new(bytes)).map_err(|err| {
+                eprintln!("ERROR: could not read ZIP archive {file_path}: {err}", file_path = file_path.display());
+            })?;
+            for i in 0..zip.len() {
+                let mut file = match zip.by_index(i) {
+                    Ok(file) => file,
+                    Err(err) => {
+                        eprintln!("ERROR: could not read member {i} of ZIP archive {file_path}: {err}", file_path = file_path.display());
+                        document.failures += 1;
+                        continue;
+                    }
+                };
+                let member = file.name().to_string();
+                if !file.is_file() || is_ignored(&member) {
+                    continue;
+                }
+                let Some(content) = read_member(&mut file, &member_path(file_path, &member), limits, budget, &mut document) else {
+                    if *budget == 0 {
+                        break;
+                    }
+                    continue;
+                };
+                add_member(registry, &mut document, file_path, &member, &content, limits, budget);
+            }
+        }
+        Archive::Tar => {
+            let mut tar = tar::Archive::new(Cursor::new(bytes));
+            let entries = tar.entries().map_err(|err| {
+                eprintln!("ERROR: could not read tar archive {file_path}: {err}", file_path = file_path.display());
+            })?;
+            for entry in entries {
+                // A corrupt header ends the entries, the members before it are kept
+                let mut entry = match entry {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        eprintln!("ERROR: could not read a member of tar archive {file_path}: {err}", file_path = file_path.display());
+                        document.failures += 1;
+                        continue;
+                    }
+                };
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let member = match entry.path() {
+                    Ok(path) => path.to_string_lossy().into_owned(),
+                    Err(err) => {
+                        eprintln!("ERROR: could not read the path of a member of tar archive {file_path}: {err}", file_path = file_path.display());
+                        document.failures += 1;
+                        continue;
+                    }
+                };
+                if is_ignored(&member) {
+                    continue;
+                }
+                let Some(content) = read_member(&mut entry, &member_path(file_path, &member), limits, budget, &mut document) else {
+                    if *budget == 0 {
+                        break;
+                    }
+                    continue;
+                };
+                add_member(registry, &mut document, file_path, &member, &content, limits, budget);
+            }
+        }
+        Archive::Gzip => {
+            // The compressed file is parsed in place of the archive, as what its name says without `.gz`
+            let path = decompressed_path(file_path);
+            let mut decoder = MultiGzDecoder::new(bytes);
+            let Some(content) = read_member(&mut decoder, &path, limits, budget, &mut document) else {
+                return if document.failures > 0 { Err(()) } else { Ok(None) };
+            };
+            return parse_content(registry, &path, &content, limits, budget);
+        }
+    }
+    document.metadata.insert("mime".to_string(), archive.mime_type().to_string());
+    Ok(Some(document))
+}
+
+/// Parse the content of a member like `ParserRegistry::parse_bytes` does, but a nested archive
+/// only gets what is left of the `budget` of the outer one
+fn parse_content(registry: &ParserRegistry, path: &Path, content: &[u8], limits: Limits, budget: &mut u64) -> Result<Option<ParsedDocument>, ()> {
+    match registry.archive(path, sniff::sniff(content)) {
+        Some(archive) => parse_within(registry, path, content, archive, limits, budget),
+        None => registry.parse_bytes(path, content, limits),
+    }
+}
+
+/// The content of the member of the archive at `member`, which is the part of the virtual path of
+/// the member after `!/`, like `docs/intro.md` or `inner.tar!/notes.txt` for a nested archive.
+/// Members bigger than `max_file_size` once decompressed are not extracted, like they are not indexed.
+pub fn extract_member(bytes: &[u8], member: &str, max_file_size: u64) -> Option<Vec<u8>> {
+    let read = |reader: &mut dyn Read| -> Option<Vec<u8>> {
+        let mut content = Vec::new();
+        if let Err(err) = reader.take(max_file_size.saturating_add(1)).read_to_end(&mut content) {
+            eprintln!("ERROR: could not read archive member {member}: {err}");
+            return None;
+        }
+        (content.len() as u64 <= max_file_size).then_some(content)
+    };
+    let (name, rest) = member.split_once("!/").unwrap_or((member, ""));
+    let content = match sniff::sniff(bytes) {
+        Sniffed::Magic("application/gzip") => {
+            // The compressed file stands in for the archive, so the member is looked for inside of it
+            let content = read(&mut MultiGzDecoder::new(bytes))?;
+            return extract_member(&content, member, max_file_size);
+        }
+        Sniffed::Magic("application/zip") => {
+            let mut zip = ZipArchive::new(Cursor::new(bytes)).ok()?;
+            let mut file = zip.by_name(name).ok()?;
+            read(&mut file)?
+        }
+        Sniffed::Magic("application/x-tar") => {
+            let mut tar = tar::Archive::new(Cursor::new(bytes));
+            let mut entry = tar.entries().ok()?
+                .filter_map(Result::ok)
+                .find(|entry| entry.path().is_ok_and(|path| path.to_string_lossy().trim_start_matches('/') == name))?;
+            read(&mut entry)?
+        }
+        _ => return None,
+    };
+    if rest.is_empty() {
+        Some(content)
+    } else {
+        extract_member(&content, rest, max_file_size)
+    }
+}
+
+/// Decompress a member of an archive out of the `budget` of bytes left to decompress. Once a
+/// member does not fit, the budget is used up and the rest of the archive is skipped with it.
+fn read_member(reader: &mut dyn Read, path: &Path, limits: Limits, budget: &mut u64, document: &mut ParsedDocument) -> Option<Vec<u8>> {
+    let available = *budget;
+    let mut content = Vec::new();
+    let result = reader.take(available.saturating_add(1)).read_to_end(&mut content);
+    *budget = available.saturating_sub(content.len() as u64);
+    if let Err(err) = result {
+        eprintln!("ERROR: could not read {path}: {err}", path = path.display());
+        document.failures += 1;
+        return None;
+    }
+    if content.len() as u64 > available {
+        println!("Skipping {path:?} and the rest of its archive because it decompresses to more than the limit of {max} bytes.", max = limits.max_file_size);
+        return None;
+    }
+    Some(content)
+}
+
+fn add_member(registry: &ParserRegistry, document: &mut ParsedDocument, file_path: &Path, member: &str, content: &[u8], limits: Limits, budget: &mut u64) {
+    let path = member_path(file_path, member);
+    match parse_content(registry, &path, content, limits, budget) {
+        Ok(Some(mut parsed)) => {
+            // The failures inside a nested archive are the failures of this one
+            document.failures += mem::take(&mut parsed.failures);
+            document.parts.push((member_name(member), parsed));
+        }
+        Ok(None) => {}
+        Err(()) => {
+            eprintln!("ERROR: could not parse {path}", path = path.display());
+            document.failures += 1;
+        }
+    }
+}
+
+/// `notes.txt.gz` holds `notes.txt`, and `bundle.tgz` holds `bundle.tar`
+fn decompressed_path(file_path: &Path) -> PathBuf {
+    match file_path.extension().map(|extension| extension.to_string_lossy().to_lowercase()) {
+        Some(extension) if extension == "gz" => file_path.with_extension(""),
+        Some(extension) if extension == "tgz" => file_path.with_extension("tar"),
+        _ => file_path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use super::super::package::tests::{zip, NO_LIMITS};
+    use super::*;
+
+    fn tar(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, content) in members {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn parse(file_name: &str, bytes: &[u8]) -> ParsedDocument {
+        ParserRegistry::default().parse_bytes(Path::new(file_name), bytes, NO_LIMITS).unwrap().unwrap()
+    }
+
+    /// Virtual path and text of every member, nested ones included
+    fn members(prefix: &str, document: &ParsedDocument) -> Vec<(String, String)> {
+        document.parts.iter()
+            .flat_map(|(name, part)| {
+                let path = format!("{prefix}{name}");
+                let nested = members(&path, part);
+                std::iter::once((path, part.text.trim().to_string())).chain(nested)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn members_are_parts_named_after_their_path() {
+        let bytes = zip(&[("docs/intro.txt", b"hello"), ("__MACOSX/._intro.txt", b"fork"), (".git/HEAD", b"ref"), ("image.bin", &[0, 159, 146, 150])]);
+        let document = parse("bundle.zip", &bytes);
+        assert_eq!(members("", &document), [("!/docs/intro.txt".to_string(), "hello".to_string())]);
+        assert_eq!(document.metadata.get("mime").map(String::as_str), Some("application/zip"));
+        assert_eq!(document.failures, 0);
+
+        let document = parse("bundle.tar", &tar(&[("notes.txt", b"one"), ("src/lib.txt", b"two")]));
+        let paths = members("", &document).into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+        assert_eq!(paths, ["!/notes.txt", "!/src/lib.txt"]);
+        assert_eq!(member_path(Path::new("/data/bundle.tar"), "/src/lib.txt"), Path::new("/data/bundle.tar!/src/lib.txt"));
+    }
+
+    #[test]
+    fn compressed_tar_archives_are_parsed_as_the_archive_they_hold() {
+        let document = parse("bundle.tar.gz", &gzip(&tar(&[("notes.txt", b"inside")])));
+        assert_eq!(members("", &document), [("!/notes.txt".to_string(), "inside".to_string())]);
+        assert_eq!(document.metadata.get("mime").map(String::as_str), Some("application/x-tar"));
+
+        let document = parse("notes.txt.gz", &gzip(b"just text"));
+        assert!(document.parts.is_empty());
+        assert_eq!(document.text.trim(), "just text");
+    }
+
+    #[test]
+    fn nested_archives_are_parts_of_their_member() {
+        let inner = tar(&[("deep.txt", b"deep")]);
+        let bytes = zip(&[("inner.tar", &inner), ("top.txt", b"top")]);
+        let document = parse("outer.zip", &bytes);
+        assert_eq!(members("", &document), [
+            ("!/inner.tar".to_string(), String::new()),
+            ("!/inner.tar!/deep.txt".to_string(), "deep".to_string()),
+            ("!/top.txt".to_string(), "top".to_string()),
+        ]);
+
+        assert_eq!(extract_member(&bytes, "top.txt", u64::MAX), Some(b"top".to_vec()));
+        assert_eq!(extract_member(&bytes, "inner.tar!/deep.txt", u64::MAX), Some(b"deep".to_vec()));
+        assert_eq!(extract_member(&gzip(&inner), "deep.txt", u64::MAX), Some(b"deep".to_vec()));
+        assert_eq!(extract_member(&bytes, "missing.txt", u64::MAX), None);
+        assert_eq!(extract_member(&bytes, "top.txt", 2), None);
+    }
+
+    #[test]
+    fn a_corrupt_tar_entry_keeps_the_members_before_it() {
+        let mut bytes = tar(&[("first.txt", b"kept"), ("second.txt", b"lost")]);
+        // The checksum of the second header no longer matches
+        bytes[1024 + 148..1024 + 156].copy_from_slice(b"0000000\0");
+        let document = parse("broken.tar", &bytes);
+        assert_eq!(members("", &document), [("!/first.txt".to_string(), "kept".to_string())]);
+        assert_eq!(document.failures, 1);
+    }
+}
@@ -0,0 +1,427 @@
+use std::path::Path;
+
+use crate::config::Limits;
+use super::encoding;
+use super::{DocumentParser, ParsedDocument};
+
+/// How the comments and string literals of a programming language are written
+struct Syntax {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    mime_types: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    block_comments: &'static [(&'static str, &'static str)],
+    /// Whether block comments nest like in Rust
+    nested_comments: bool,
+    /// Longest first so that `"""` is not taken for an empty string
+    quotes: &'static [&'static str],
+    /// Reserved words, which are not symbols of the code
+    keywords: &'static [&'static str],
+}
+
+const C_QUOTES: &[&str] = &["\"", "'"];
+const C_COMMENTS: &[(&str, &str)] = &[("/*", "*/")];
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "Self", "self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while",
+];
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is",
+    "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True", "try", "while",
+    "with", "yield",
+];
+
+const JS_KEYWORDS: &[&str] = &[
+    "async", "await", "break", "case", "catch", "class", "const", "continue", "debugger",
+    "default", "delete", "do", "else", "export", "extends", "false", "finally", "for", "from",
+    "function", "if", "import", "in", "instanceof", "let", "new", "null", "of", "return", "static",
+    "super", "switch", "this", "throw", "true", "try", "typeof", "undefined", "var", "void",
+    "while", "with", "yield",
+];
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while",
+];
+
+const CPP_KEYWORDS: &[&str] = &[
+    "auto", "bool", "break", "case", "catch", "char", "class", "const", "constexpr", "continue",
+    "default", "delete", "do", "double", "else", "enum", "explicit", "extern", "false", "float",
+    "for", "friend", "goto", "if", "inline", "int", "long", "mutable", "namespace", "new",
+    "noexcept", "nullptr", "operator", "private", "protected", "public", "return", "short",
+    "signed", "sizeof", "static", "struct", "switch", "template", "this", "throw", "true", "try",
+    "typedef", "typename", "union", "unsigned", "using", "virtual", "void", "volatile", "while",
+];
+
+const CSHARP_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "base", "bool", "break", "case", "catch", "char", "class",
+    "const", "continue", "decimal", "default", "delegate", "do", "double", "else", "enum", "event",
+    "false", "finally", "float", "for", "foreach", "if", "in", "int", "interface", "internal",
+    "is", "lock", "long", "namespace", "new", "null", "object", "out", "override", "params",
+    "private", "protected", "public", "readonly", "ref", "return", "sealed", "short", "static",
+    "string", "struct", "switch", "this", "throw", "true", "try", "typeof", "uint", "ulong",
+    "using", "var", "virtual", "void", "while",
+];
+
+const JAVA_KEYWORDS: &[&str] = &[
+    "abstract", "boolean", "break", "byte", "case", "catch", "char", "class", "const", "continue",
+    "default", "do", "double", "else", "enum", "extends", "false", "final", "finally", "float",
+    "for", "if", "implements", "import", "instanceof", "int", "interface", "long", "native", "new",
+    "null", "package", "private", "protected", "public", "return", "short", "static", "super",
+    "switch", "synchronized", "this", "throw", "throws", "true", "try", "void", "volatile",
+    "while",
+];
+
+const KOTLIN_KEYWORDS: &[&str] = &[
+    "as", "break", "class", "continue", "do", "else", "false", "for", "fun", "if", "import", "in",
+    "interface", "is", "null", "object", "override", "package", "private", "public", "return",
+    "super", "this", "throw", "true", "try", "typealias", "val", "var", "when", "while",
+];
+
+const SWIFT_KEYWORDS: &[&str] = &[
+    "as", "break", "case", "catch", "class", "continue", "default", "defer", "do", "else", "enum",
+    "extension", "false", "fileprivate", "for", "func", "guard", "if", "import", "in", "init",
+    "internal", "is", "let", "nil", "private", "protocol", "public", "repeat", "return", "self",
+    "static", "struct", "super", "switch", "throw", "throws", "true", "try", "var", "where",
+    "while",
+];
+
+const GO_KEYWORDS: &[&str] = &[
+    "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough",
+    "false", "for", "func", "go", "goto", "if", "import", "interface", "map", "nil", "package",
+    "range", "return", "select", "struct", "switch", "true", "type", "var",
+];
+
+const PHP_KEYWORDS: &[&str] = &[
+    "abstract", "and", "array", "as", "break", "case", "catch", "class", "clone", "const",
+    "continue", "declare", "default", "do", "echo", "else", "elseif", "empty", "extends", "false",
+    "final", "finally", "fn", "for", "foreach", "function", "global", "if", "implements",
+    "include", "instanceof", "interface", "isset", "namespace", "new", "null", "or", "print",
+    "private", "protected", "public", "require", "return", "static", "switch", "this", "throw",
+    "trait", "true", "try", "unset", "use", "var", "while",
+];
+
+const RUBY_KEYWORDS: &[&str] = &[
+    "alias", "and", "begin", "break", "case", "class", "def", "defined", "do", "else", "elsif",
+    "end", "ensure", "false", "for", "if", "in", "module", "next", "nil", "not", "or", "redo",
+    "rescue", "retry", "return", "self", "super", "then", "true", "undef", "unless", "until",
+    "when", "while", "yield",
+];
+
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "fi", "for", "function", "if", "in", "local",
+    "return", "select", "then", "until", "while",
+];
+
+const LUA_KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+const SQL_KEYWORDS: &[&str] = &[
+    "add", "all", "alter", "and", "as", "asc", "between", "by", "case", "create", "delete", "desc",
+    "distinct", "drop", "else", "end", "exists", "from", "group", "having", "in", "index", "inner",
+    "insert", "into", "is", "join", "key", "left", "like", "limit", "not", "null", "on", "or",
+    "order", "outer", "primary", "references", "right", "select", "set", "table", "then", "union",
+    "update", "values", "view", "when", "where",
+];
+
+const SYNTAXES: &[Syntax] = &[
+    Syntax { name: "rust", extensions: &["rs"], mime_types: &["text/x-rust"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: true, quotes: &["\""], keywords: RUST_KEYWORDS },
+    Syntax { name: "python", extensions: &["py", "pyw", "pyi"], mime_types: &["text/x-python"], line_comments: &["#"], block_comments: &[], nested_comments: false, quotes: &["\"\"\"", "'''", "\"", "'"], keywords: PYTHON_KEYWORDS },
+    Syntax { name: "javascript", extensions: &["js", "mjs", "cjs", "jsx"], mime_types: &["text/javascript"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: false, quotes: &["\"", "'", "`"], keywords: JS_KEYWORDS },
+    Syntax { name: "typescript", extensions: &["ts", "tsx"], mime_types: &["text/x-typescript"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: false, quotes: &["\"", "'", "`"], keywords: JS_KEYWORDS },
+    Syntax { name: "c", extensions: &["c", "h"], mime_types: &["text/x-c"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: false, quotes: C_QUOTES, keywords: C_KEYWORDS },
+    Syntax { name: "c++", extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"], mime_types: &["text/x-c++"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: false, quotes: C_QUOTES, keywords: CPP_KEYWORDS },
+    Syntax { name: "c#", extensions: &["cs"], mime_types: &["text/x-csharp"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: false, quotes: C_QUOTES, keywords: CSHARP_KEYWORDS },
+    Syntax { name: "java", extensions: &["java"], mime_types: &["text/x-java"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: false, quotes: C_QUOTES, keywords: JAVA_KEYWORDS },
+    Syntax { name: "kotlin", extensions: &["kt", "kts"], mime_types: &["text/x-kotlin"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: true, quotes: &["\"\"\"", "\"", "'"], keywords: KOTLIN_KEYWORDS },
+    Syntax { name: "swift", extensions: &["swift"], mime_types: &["text/x-swift"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: true, quotes: &["\"\"\"", "\""], keywords: SWIFT_KEYWORDS },
+    Syntax { name: "go", extensions: &["go"], mime_types: &["text/x-go"], line_comments: &["//"], block_comments: C_COMMENTS, nested_comments: false, quotes: &["\"", "'", "`"], keywords: GO_KEYWORDS },
+    Syntax { name: "php", extensions: &["php"], mime_types: &["text/x-php"], line_comments: &["//", "#"], block_comments: C_COMMENTS, nested_comments: false, quotes: C_QUOTES, keywords: PHP_KEYWORDS },
+    Syntax { name: "ruby", extensions: &["rb"], mime_types: &["text/x-ruby"], line_comments: &["#"], block_comments: &[("=begin", "=end")], nested_comments: false, quotes: C_QUOTES, keywords: RUBY_KEYWORDS },
+    Syntax { name: "shell", extensions: &["sh", "bash", "zsh"], mime_types: &["text/x-shellscript"], line_comments: &["#"], block_comments: &[], nested_comments: false, quotes: C_QUOTES, keywords: SHELL_KEYWORDS },
+    Syntax { name: "lua", extensions: &["lua"], mime_types: &["text/x-lua"], line_comments: &["--"], block_comments: &[("--[[", "]]")], nested_comments: false, quotes: C_QUOTES, keywords: LUA_KEYWORDS },
+    Syntax { name: "sql", extensions: &["sql"], mime_types: &["text/x-sql"], line_comments: &["--"], block_comments: C_COMMENTS, nested_comments: false, quotes: &["'", "\""], keywords: SQL_KEYWORDS },
+];
+
+/// Parser for the source code of one programming language. Comments (docstrings included),
+/// string literals and identifiers go to the `comment`, `string` and `symbol` fields on top of the text.
+pub struct CodeParser {
+    syntax: &'static Syntax,
+}
+
+impl CodeParser {
+    /// One parser for every programming language that is recognized
+    pub fn all() -> impl Iterator<Item = CodeParser> {
+        SYNTAXES.iter().map(|syntax| CodeParser { syntax })
+    }
+}
+
+impl DocumentParser for CodeParser {
+    fn name(&self) -> &'static str {
+        self.syntax.name
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        self.syntax.extensions
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        self.syntax.mime_types
+    }
+
+    fn parse(&self, _file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let (source, encoding) = encoding::decode(bytes, None);
+        let mut document = extract(&source, self.syntax);
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        Ok(document)
+    }
+}
+
+enum Token<'a> {
+    Comment(&'a str),
+    String(&'a str),
+    Identifier(&'a str),
+}
+
+fn extract(source: &str, syntax: &Syntax) -> ParsedDocument {
+    let mut document = ParsedDocument::default();
+    let mut symbols = String::new();
+    let scanner = Scanner { source, syntax, offset: 0 };
+    for token in scanner {
+        let (field, text) = match token {
+            Token::Comment(text) => ("comment", text),
+            Token::String(text) => ("string", text),
+            Token::Identifier(identifier) if is_keyword(identifier, syntax) => continue,
+            Token::Identifier(identifier) => {
+                symbols.push_str(identifier);
+                symbols.push(' ');
+                // `parseBytes` is also found as `parse` and `bytes`
+                let parts = camel_case_parts(identifier);
+                if parts.len() > 1 {
+                    for part in parts {
+                        symbols.push_str(part);
+                        symbols.push(' ');
+                    }
+                }
+                document.text.push_str(identifier);
+                document.text.push(' ');
+                continue;
+            }
+        };
+        if text.chars().any(|c| c.is_alphanumeric()) {
+            document.fields.push((field.to_string(), text.to_string()));
+            document.text.push('\n');
+            document.text.push_str(text);
+            document.text.push('\n');
+        }
+    }
+    if !symbols.is_empty() {
+        document.fields.push(("symbol".to_string(), symbols));
+    }
+    document
+}
+
+/// SQL is the one language whose keywords are written in any case
+fn is_keyword(identifier: &str, syntax: &Syntax) -> bool {
+    if syntax.name == "sql" {
+        syntax.keywords.iter().any(|keyword| keyword.eq_ignore_ascii_case(identifier))
+    } else {
+        syntax.keywords.contains(&identifier)
+    }
+}
+
+fn camel_case_parts(identifier: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let chars = identifier.char_indices().collect::<Vec<_>>();
+    for window in chars.windows(2) {
+        let ((_, previous), (i, current)) = (window[0], window[1]);
+        if previous.is_lowercase() && current.is_uppercase() {
+            parts.push(&identifier[start..i]);
+            start = i;
+        }
+    }
+    parts.push(&identifier[start..]);
+    parts
+}
+
+struct Scanner<'a> {
+    source: &'a str,
+    syntax: &'a Syntax,
+    offset: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.offset..]
+    }
+
+    /// Whether only indentation comes before the current position on its line
+    fn at_line_start(&self) -> bool {
+        self.source[..self.offset].rsplit('\n').next().unwrap_or("").trim().is_empty()
+    }
+
+    fn block_comment(&mut self, open: &str, close: &str) -> &'a str {
+        let rest = self.rest();
+        let mut depth = 0;
+        let mut i = 0;
+        while i < rest.len() {
+            if rest[i..].starts_with(open) && (depth == 0 || self.syntax.nested_comments) {
+                depth += 1;
+                i += open.len();
+            } else if rest[i..].starts_with(close) {
+                depth -= 1;
+                i += close.len();
+                if depth == 0 {
+                    break;
+                }
+            } else {
+                i += rest[i..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        let end = i.min(rest.len());
+        self.offset += end;
+        let comment = &rest[..end];
+        comment.strip_prefix(open).unwrap_or(comment).strip_suffix(close).unwrap_or(comment)
+    }
+
+    fn string(&mut self, quote: &str) -> &'a str {
+        let rest = &self.rest()[quote.len()..];
+        let mut chars = rest.char_indices();
+        let mut end = rest.len();
+        while let Some((i, c)) = chars.next() {
+            if c == '\\' {
+                chars.next();
+            } else if rest[i..].starts_with(quote) {
+                end = i;
+                break;
+            } else if c == '\n' && quote.len() == 1 && quote != "`" && self.syntax.name != "rust" {
+                // An unterminated string does not swallow the rest of the file
+                end = i;
+                break;
+            }
+        }
+        self.offset += quote.len() + (end + quote.len()).min(rest.len());
+        &rest[..end]
+    }
+
+    /// Skip a character literal of Rust like `'"'` or `'\''`, which holds no words, or else the `'` of a lifetime
+    fn char_literal(&mut self) {
+        let rest = &self.rest()[1..];
+        let mut chars = rest.char_indices();
+        let end = match chars.next() {
+            // The escape is followed by a character or by `u{...}`
+            Some((_, '\\')) => rest[1..].char_indices().skip(1).take(9).find(|(_, c)| *c == '\'').map(|(i, _)| 1 + i),
+            Some(_) => chars.next().filter(|(_, c)| *c == '\'').map(|(i, _)| i),
+            None => None,
+        };
+        self.offset += 1 + end.map_or(0, |end| end + 1);
+    }
+
+    /// `r"..."` and `r#"..."#` in Rust, whose content has no escapes
+    fn raw_string(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let hashes = rest.strip_prefix(['r', 'R'])?.chars().take_while(|c| *c == '#').count();
+        if !rest[1 + hashes..].starts_with('"') {
+            return None;
+        }
+        let content = &rest[2 + hashes..];
+        let close = format!("\"{}", "#".repeat(hashes));
+        let end = content.find(&close).unwrap_or(content.len());
+        self.offset += 2 + hashes + (end + close.len()).min(content.len());
+        Some(&content[..end])
+    }
+}
+
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let rest = self.rest();
+            let c = rest.chars().next()?;
+            // Block comments first, as `--[[` of Lua starts with the line comment `--`
+            if let Some((open, close)) = self.syntax.block_comments.iter().find(|(open, _)| rest.starts_with(*open)) {
+                return Some(Token::Comment(self.block_comment(open, close)));
+            }
+            if let Some(marker) = self.syntax.line_comments.iter().find(|marker| rest.starts_with(**marker)) {
+                let end = rest.find('\n').unwrap_or(rest.len());
+                self.offset += end;
+                return Some(Token::Comment(&rest[marker.len()..end]));
+            }
+            if self.syntax.name == "rust" && matches!(c, 'r' | 'R') {
+                if let Some(string) = self.raw_string() {
+                    return Some(Token::String(string));
+                }
+            }
+            if self.syntax.name == "rust" && c == '\'' {
+                self.char_literal();
+                continue;
+            }
+            if let Some(quote) = self.syntax.quotes.iter().find(|quote| rest.starts_with(**quote)) {
+                // A string standing on its own line in Python is a docstring
+                let docstring = self.syntax.name == "python" && quote.len() == 3 && self.at_line_start();
+                let string = self.string(quote);
+                return Some(if docstring { Token::Comment(string) } else { Token::String(string) });
+            }
+            if c.is_alphabetic() || c == '_' {
+                let end = rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+                self.offset += end;
+                return Some(Token::Identifier(&rest[..end]));
+            }
+            if c.is_numeric() {
+                self.offset += rest.find(|c: char| !c.is_alphanumeric() && c != '_' && c != '.').unwrap_or(rest.len());
+                continue;
+            }
+            self.offset += c.len_utf8();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn syntax(name: &str) -> &'static Syntax {
+        SYNTAXES.iter().find(|syntax| syntax.name == name).unwrap()
+    }
+
+    fn field<'a>(document: &'a ParsedDocument, name: &str) -> Vec<&'a str> {
+        document.fields.iter().filter(|(field, _)| field == name).map(|(_, text)| text.as_str()).collect()
+    }
+
+    #[test]
+    fn keywords_are_not_symbols() {
+        let document = extract("pub fn parseBytes(self) -> Result<u8> { let x = 1; return x }", syntax("rust"));
+        assert_eq!(field(&document, "symbol"), ["parseBytes parse Bytes Result u8 x x "]);
+        let document = extract("SELECT name FROM Users where id IN (1)", syntax("sql"));
+        assert_eq!(field(&document, "symbol"), ["name Users id "]);
+    }
+
+    #[test]
+    fn rust_char_literals_are_not_strings() {
+        let source = "let quote = '\"'; let tick = '\\''; let smile = '\\u{1F600}'; let s = \"text\";\nfn f<'a>(x: &'a str) {}";
+        let document = extract(source, syntax("rust"));
+        assert_eq!(field(&document, "string"), ["text"]);
+        assert_eq!(field(&document, "symbol"), ["quote tick smile s f a x a str "]);
+    }
+
+    #[test]
+    fn comments_strings_and_docstrings() {
+        let document = extract("# note\ndef f():\n    \"\"\"Docs here\"\"\"\n    return 'value'\n", syntax("python"));
+        assert_eq!(field(&document, "comment"), [" note", "Docs here"]);
+        assert_eq!(field(&document, "string"), ["value"]);
+        let document = extract("/* outer /* inner */ still */ x", syntax("rust"));
+        assert_eq!(field(&document, "comment"), [" outer /* inner */ still "]);
+        assert_eq!(field(&document, "symbol"), ["x "]);
+    }
+}
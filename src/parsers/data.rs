@@ -0,0 +1,281 @@
+use std::path::Path;
+use serde_json::Value;
+
+use crate::config::Limits;
+use super::encoding;
+use super::{DocumentParser, ParsedDocument};
+
+/// Parser for comma and tab separated values whose first row names the columns
+#[derive(Default)]
+pub struct CsvParser {
+    /// Whether every row is a sub-document `#row=N` with a field per column,
+    /// rather than the whole table being one document
+    pub rows: bool,
+}
+
+impl DocumentParser for CsvParser {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["csv", "tsv"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/csv", "text/tab-separated-values"]
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let tabs = file_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("tsv"));
+        let (text, encoding) = encoding::decode(bytes, None);
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(if tabs { b'\t' } else { b',' })
+            .flexible(true)
+            .from_reader(text.as_bytes());
+        let columns = reader.headers().map_err(|err| {
+            eprintln!("ERROR: could not read the header of {file_path}: {err}", file_path = file_path.display());
+        })?.iter().map(field_name).collect::<Vec<_>>();
+
+        let mut document = ParsedDocument::default();
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        document.metadata.insert("mime".to_string(), self.mime_types()[usize::from(tabs)].to_string());
+        document.text.push_str(&columns.join(" "));
+        document.text.push('\n');
+        for (number, record) in reader.records().enumerate() {
+            let record = record.map_err(|err| {
+                eprintln!("ERROR: could not read {file_path}: {err}", file_path = file_path.display());
+            })?;
+            let mut row = ParsedDocument::default();
+            for (i, value) in record.iter().enumerate() {
+                let value = value.trim();
+                if value.is_empty() {
+                    continue;
+                }
+                if let Some(column) = columns.get(i).filter(|column| !column.is_empty()) {
+                    row.fields.push((column.clone(), value.to_string()));
+                }
+                row.text.push_str(value);
+                row.text.push('\n');
+            }
+            if self.rows {
+                // The first row is the header, so the data starts on line 2
+                row.metadata.insert("row".to_string(), (number + 2).to_string());
+                document.parts.push((format!("#row={}", number + 2), row));
+            } else {
+                document.text.push_str(&row.text);
+                document.fields.extend(row.fields);
+            }
+        }
+        Ok(document)
+    }
+}
+
+/// Parser for JSON, YAML and TOML data. Every value is indexed in the field named
+/// after the path of keys that leads to it, like `server.host: example.com`.
+pub struct StructuredDataParser;
+
+impl DocumentParser for StructuredDataParser {
+    fn name(&self) -> &'static str {
+        "structured data"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["json", "yaml", "yml", "toml"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/json", "application/yaml", "application/toml"]
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let (text, encoding) = encoding::decode(bytes, None);
+        let extension = file_path.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        let values = match extension.as_str() {
+            "yaml" | "yml" => {
+                // A YAML file may hold several documents separated by `---`
+                let mut values = Vec::new();
+                for document in serde_yaml::Deserializer::from_str(&text) {
+                    let value = serde::Deserialize::deserialize(document).map_err(|err: serde_yaml::Error| {
+                        eprintln!("ERROR: could not parse YAML file {file_path}: {err}", file_path = file_path.display());
+                    })?;
+                    values.push(from_yaml(value));
+                }
+                values
+            }
+            "toml" => vec![toml::from_str::<Value>(&text).map_err(|err| {
+                eprintln!("ERROR: could not parse TOML file {file_path}: {err}", file_path = file_path.display());
+            })?],
+            _ => vec![serde_json::from_str::<Value>(&text).map_err(|err| {
+                eprintln!("ERROR: could not parse JSON file {file_path}: {err}", file_path = file_path.display());
+            })?],
+        };
+        let mut document = ParsedDocument::default();
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        let mut entries = Vec::new();
+        for value in &values {
+            flatten(value, String::new(), &mut entries);
+        }
+        for (path, value) in entries {
+            document.text.push_str(&format!("{path}: {value}\n"));
+            if !path.is_empty() {
+                document.fields.push((path, value));
+            }
+        }
+        Ok(document)
+    }
+}
+
+/// Lowercase name of a field as queries can spell it, made of letters, digits, `.`, `_` and `-`
+fn field_name(key: &str) -> String {
+    key.trim()
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect()
+}
+
+/// Value of YAML as JSON. Unlike JSON, YAML allows keys that are numbers, booleans or null,
+/// which become strings, and tags such as `!Ref`, which are dropped for the value they tag.
+/// Keys that are themselves mappings or sequences name no field and are left out.
+fn from_yaml(value: serde_yaml::Value) -> Value {
+    match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(flag) => Value::Bool(flag),
+        serde_yaml::Value::Number(number) => match (number.as_u64(), number.as_i64(), number.as_f64()) {
+            (Some(number), _, _) => Value::from(number),
+            (_, Some(number), _) => Value::from(number),
+            // `.nan` and `.inf` have no JSON number
+            (_, _, Some(number)) => serde_json::Number::from_f64(number).map_or_else(|| Value::String(number.to_string()), Value::Number),
+            _ => Value::Null,
+        },
+        serde_yaml::Value::String(string) => Value::String(string),
+        serde_yaml::Value::Sequence(items) => Value::Array(items.into_iter().map(from_yaml).collect()),
+        serde_yaml::Value::Mapping(mapping) => Value::Object(
+            mapping
+                .into_iter()
+                .filter_map(|(key, value)| {
+                    let key = match key {
+                        serde_yaml::Value::Tagged(tagged) => tagged.value,
+                        key => key,
+                    };
+                    let key = match key {
+                        serde_yaml::Value::Null => "null".to_string(),
+                        serde_yaml::Value::Bool(flag) => flag.to_string(),
+                        serde_yaml::Value::Number(number) => number.to_string(),
+                        serde_yaml::Value::String(string) => string,
+                        _ => return None,
+                    };
+                    Some((key, from_yaml(value)))
+                })
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => from_yaml(tagged.value),
+    }
+}
+
+/// Collect the scalars of the value with the path of keys that leads to them. The items of
+/// arrays share the path of the array so that `servers.host:` finds the host of any server.
+fn flatten(value: &Value, path: String, entries: &mut Vec<(String, String)>) {
+    match value {
+        Value::Null => {}
+        Value::Bool(flag) => entries.push((path, flag.to_string())),
+        Value::Number(number) => entries.push((path, number.to_string())),
+        Value::String(string) => entries.push((path, string.clone())),
+        Value::Array(items) => {
+            for item in items {
+                flatten(item, path.clone(), entries);
+            }
+        }
+        Value::Object(object) => {
+            // Dates and times of TOML come out as objects with a single magic key
+            if let (1, Some(Value::String(datetime))) = (object.len(), object.get("$__toml_private_datetime")) {
+                entries.push((path, datetime.clone()));
+                return;
+            }
+            for (key, value) in object {
+                let key = field_name(key);
+                let path = if path.is_empty() { key } else { format!("{path}.{key}") };
+                flatten(value, path, entries);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::package::tests::NO_LIMITS;
+    use super::*;
+
+    fn fields(document: &ParsedDocument) -> Vec<(&str, &str)> {
+        let mut fields = document.fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<Vec<_>>();
+        fields.sort();
+        fields
+    }
+
+    const CSV: &[u8] = b"Name,E-mail Address,\nAda,ada@example.com,x\n,bob@example.com\n";
+
+    #[test]
+    fn csv_columns_become_fields() {
+        let document = CsvParser::default().parse(Path::new("people.csv"), CSV, NO_LIMITS).unwrap();
+        assert_eq!(document.text, "name e-mail_address \nAda\nada@example.com\nx\nbob@example.com\n");
+        assert_eq!(fields(&document), [
+            ("e-mail_address", "ada@example.com"), ("e-mail_address", "bob@example.com"), ("name", "Ada"),
+        ]);
+        assert!(document.parts.is_empty());
+        assert_eq!(document.metadata.get("mime").map(String::as_str), Some("text/csv"));
+    }
+
+    #[test]
+    fn csv_rows_can_be_parts_of_their_own() {
+        let document = CsvParser { rows: true }.parse(Path::new("people.csv"), CSV, NO_LIMITS).unwrap();
+        let names = document.parts.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["#row=2", "#row=3"]);
+        assert_eq!(fields(&document.parts[0].1), [("e-mail_address", "ada@example.com"), ("name", "Ada")]);
+        assert_eq!(document.parts[1].1.metadata.get("row").map(String::as_str), Some("3"));
+        assert!(document.fields.is_empty());
+
+        let document = CsvParser::default().parse(Path::new("people.tsv"), b"id\tcity\n1\tParis\n", NO_LIMITS).unwrap();
+        assert_eq!(fields(&document), [("city", "Paris"), ("id", "1")]);
+        assert_eq!(document.metadata.get("mime").map(String::as_str), Some("text/tab-separated-values"));
+    }
+
+    fn parse(file_name: &str, data: &str) -> ParsedDocument {
+        StructuredDataParser.parse(Path::new(file_name), data.as_bytes(), NO_LIMITS).unwrap()
+    }
+
+    #[test]
+    fn values_are_fields_named_after_their_key_path() {
+        let json = parse("config.json", r#"{"server": {"Host Name": "example.com", "port": 8080, "tls": true, "proxy": null},
+            "servers": [{"host": "a"}, {"host": "b"}], "tags": ["x", "y"]}"#);
+        let expected = [
+            ("server.host_name", "example.com"), ("server.port", "8080"), ("server.tls", "true"),
+            ("servers.host", "a"), ("servers.host", "b"), ("tags", "x"), ("tags", "y"),
+        ];
+        assert_eq!(fields(&json), expected);
+        assert!(json.text.contains("server.host_name: example.com\n"));
+
+        let yaml = parse("config.yaml", "server:\n  Host Name: example.com\n  port: 8080\n  tls: true\n  proxy: ~\nservers:\n  - host: a\n  - host: b\ntags: [x, y]\n");
+        assert_eq!(fields(&yaml), expected);
+        let toml = parse("config.toml", "tags = [\"x\", \"y\"]\n[server]\n\"Host Name\" = \"example.com\"\nport = 8080\ntls = true\n[[servers]]\nhost = \"a\"\n[[servers]]\nhost = \"b\"\n");
+        assert_eq!(fields(&toml), expected);
+    }
+
+    #[test]
+    fn every_yaml_document_and_key_and_toml_datetime_is_kept() {
+        let yaml = parse("stream.yml", "name: first\n---\nname: second\n");
+        assert_eq!(fields(&yaml), [("name", "first"), ("name", "second")]);
+
+        let yaml = parse("template.yaml", "codes:\n  404: Not Found\n  true: yes\nbucket: !Ref Logs\nports: !!seq [80, 443]\n");
+        assert_eq!(fields(&yaml), [("bucket", "Logs"), ("codes.404", "Not Found"), ("codes.true", "yes"), ("ports", "443"), ("ports", "80")]);
+
+        let toml = parse("release.toml", "[release]\ndate = 2024-05-13T10:22:01Z\nday = 2024-05-13\n");
+        assert_eq!(fields(&toml), [("release.date", "2024-05-13T10:22:01Z"), ("release.day", "2024-05-13")]);
+    }
+
+    #[test]
+    fn invalid_data_is_rejected() {
+        assert!(StructuredDataParser.parse(Path::new("broken.json"), b"{\"a\": ", NO_LIMITS).is_err());
+        assert!(StructuredDataParser.parse(Path::new("broken.toml"), b"a = ", NO_LIMITS).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+use std::path::Path;
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::config::Limits;
+use super::html;
+use super::{DocumentParser, ParsedDocument};
+
+pub const EML: &str = "message/rfc822";
+pub const MBOX: &str = "application/mbox";
+
+/// Headers that make it into the metadata of a message, so queries can filter on them
+const METADATA_HEADERS: &[(&str, &str)] = &[
+    ("from", "from"),
+    ("to", "to"),
+    ("cc", "cc"),
+    ("subject", "subject"),
+    ("date", "date"),
+    ("message-id", "message-id"),
+];
+/// How deep multipart bodies and attached messages are walked down, as every level takes
+/// some of the stack and a crafted message could nest enough of them to overflow it
+const MAX_DEPTH: usize = 16;
+
+const MONTHS: &[&str] = &["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// Parser for single email messages (.eml) and mailboxes (.mbox), where every
+/// message of a mailbox becomes a sub-document of its own
+pub struct EmailParser;
+
+impl DocumentParser for EmailParser {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["eml", "mbox"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &[EML, MBOX]
+    }
+
+    fn parse(&self, _file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        // What comes before the first `From ` line does not keep a mailbox from being sniffed as one
+        let mailbox_bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes).trim_ascii_start();
+        if !mailbox_bytes.starts_with(b"From ") {
+            return Ok(parse_message(bytes, 0));
+        }
+        let mut mailbox = ParsedDocument::default();
+        for (number, message) in split_mbox(mailbox_bytes).into_iter().enumerate() {
+            let mut message = parse_message(&message, 0);
+            message.metadata.insert("mime".to_string(), EML.to_string());
+            mailbox.parts.push((format!("#message={}", number + 1), message));
+        }
+        Ok(mailbox)
+    }
+}
+
+/// Messages of a mailbox, which start with a `From ` line each. Lines of the body
+/// that start with `From ` are escaped as `>From `, which is undone here.
+fn split_mbox(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages: Vec<Vec<u8>> = Vec::new();
+    for line in bytes.split_inclusive(|b| *b == b'\n') {
+        if line.starts_with(b"From ") {
+            messages.push(Vec::new());
+            continue;
+        }
+        let Some(message) = messages.last_mut() else {
+            continue;
+        };
+        let unquoted = line.iter().position(|b| *b != b'>')
+            .filter(|quotes| *quotes > 0 && line[*quotes..].starts_with(b"From "))
+            .map(|_| &line[1..])
+            .unwrap_or(line);
+        message.extend_from_slice(unquoted);
+    }
+    messages
+}
+
+type Headers = Vec<(String, String)>;
+
+fn header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// Split a message or a MIME part into its unfolded headers (with lowercase names) and its body
+fn split_headers(raw: &[u8]) -> (Headers, &[u8]) {
+    let mut headers: Headers = Vec::new();
+    let mut rest = raw;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|b| *b == b'\n').map(|end| end + 1).unwrap_or(rest.len());
+        let line = String::from_utf8_lossy(&rest[..end]);
+        let line = line.trim_end_matches(['\r', '\n']);
+        rest = &rest[end..];
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    (headers, rest)
+}
+
+/// The message at `depth` levels down the message it is attached to, if any
+fn parse_message(raw: &[u8], depth: usize) -> ParsedDocument {
+    let (headers, body) = split_headers(raw);
+    let mut document = ParsedDocument::default();
+    for (name, key) in METADATA_HEADERS {
+        if let Some(value) = header(&headers, name) {
+            let value = decode_words(value);
+            let value = if *key == "date" { iso_date(&value).unwrap_or(value) } else { value };
+            document.metadata.insert(key.to_string(), value);
+        }
+    }
+    if let Some(subject) = document.metadata.get("subject") {
+        document.text.push_str(subject);
+        document.text.push_str("\n\n");
+        document.metadata.insert("title".to_string(), subject.clone());
+    }
+    document.text.push_str(&body_text(&headers, body, depth));
+    document
+}
+
+/// MIME type of a `Content-Type` header in lowercase along with its parameters
+fn content_type(value: &str) -> (String, Vec<(String, String)>) {
+    let mut params = value.split(';');
+    let mime_type = params.next().unwrap_or("").trim().to_ascii_lowercase();
+    let params = params
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim().trim_matches('"').to_string()))
+        .collect();
+    (mime_type, params)
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// Readable text of a body at `depth` levels down the message, walking down multipart bodies.
+/// Attachments other than text are left out, and so is what is nested beyond `MAX_DEPTH` levels.
+fn body_text(headers: &Headers, body: &[u8], depth: usize) -> String {
+    let (mime_type, params) = content_type(header(headers, "content-type").unwrap_or("text/plain"));
+    let nested = mime_type.starts_with("multipart/") || mime_type == "message/rfc822";
+    if nested && depth >= MAX_DEPTH {
+        return String::new();
+    }
+    let attachment = header(headers, "content-disposition")
+        .map(|disposition| disposition.to_ascii_lowercase().starts_with("attachment"))
+        .unwrap_or(false);
+    if let Some(boundary) = param(&params, "boundary").filter(|_| mime_type.starts_with("multipart/")) {
+        let parts = split_multipart(body, boundary);
+        if mime_type == "multipart/alternative" {
+            // The same content in several formats, plain text is the easiest to index
+            let plain = parts.iter().find(|(headers, _)| {
+                content_type(header(headers, "content-type").unwrap_or("text/plain")).0 == "text/plain"
+            });
+            return plain.or(parts.first()).map(|(headers, body)| body_text(headers, body, depth + 1)).unwrap_or_default();
+        }
+        return parts.iter()
+            .map(|(headers, body)| body_text(headers, body, depth + 1))
+            .filter(|text| !text.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+    }
+    let decoded = match header(headers, "content-transfer-encoding").map(|encoding| encoding.to_ascii_lowercase()) {
+        Some(encoding) if encoding == "base64" => decode_base64(body),
+        Some(encoding) if encoding == "quoted-printable" => decode_quoted_printable(body, false),
+        _ => body.to_vec(),
+    };
+    match mime_type.as_str() {
+        "message/rfc822" => parse_message(&decoded, depth + 1).text,
+        "text/html" if !attachment => html::extract(&decode_charset(&decoded, param(&params, "charset"))).text,
+        mime_type if mime_type.starts_with("text/") && !attachment => decode_charset(&decoded, param(&params, "charset")),
+        _ => String::new(),
+    }
+}
+
+/// Parts of a multipart body delimited by `--boundary` lines
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<(Headers, &'a [u8])> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut offset = 0;
+    for line in body.split_inclusive(|b| *b == b'\n') {
+        // Only whole lines are delimiters, as the boundary of a nested part may start with this one
+        let rest = line.trim_ascii_end().strip_prefix(delimiter.as_bytes());
+        if let Some(rest @ (b"" | b"--")) = rest {
+            if let Some(start) = start {
+                parts.push(split_headers(&body[start..offset]));
+            }
+            if rest == b"--" {
+                break;
+            }
+            start = Some(offset + line.len());
+        }
+        offset += line.len();
+    }
+    parts
+}
+
+fn decode_charset(bytes: &[u8], charset: Option<&str>) -> String {
+    let encoding = charset.and_then(|charset| Encoding::for_label(charset.as_bytes())).unwrap_or(UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
+
+fn decode_base64(bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in bytes {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => continue,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    decoded
+}
+
+/// Decode quoted-printable text, or the Q encoding of encoded words in headers where `_` stands for a space
+fn decode_quoted_printable(bytes: &[u8], header: bool) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'=' if bytes[i + 1..].starts_with(b"\r\n") => i += 3,
+            b'=' if bytes[i + 1..].starts_with(b"\n") => i += 2,
+            b'=' => {
+                let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+                match hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                    }
+                    None => {
+                        decoded.push(b'=');
+                        i += 1;
+                    }
+                }
+            }
+            b'_' if header => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Decode the RFC 2047 encoded words (`=?charset?Q?...?=`) of a header
+fn decode_words(value: &str) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let word = rest[start + 2..].splitn(3, '?').collect::<Vec<_>>();
+        let decoded = match word.as_slice() {
+            [charset, encoding, text] => text.find("?=").map(|end| {
+                let bytes = match encoding.to_ascii_lowercase().as_str() {
+                    "b" => decode_base64(&text.as_bytes()[..end]),
+                    _ => decode_quoted_printable(&text.as_bytes()[..end], true),
+                };
+                // Drop the RFC 2231 language of the charset, as in `utf-8*en`
+                let label = charset.split('*').next().unwrap_or(charset);
+                (decode_charset(&bytes, Some(label)), start + 2 + charset.len() + encoding.len() + 2 + end + 2)
+            }),
+            _ => None,
+        };
+        let Some((decoded, end)) = decoded else {
+            result.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+        // Whitespace between two encoded words is not part of the text
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            result.push_str(between);
+        }
+        result.push_str(&decoded);
+        rest = &rest[end..];
+        after_word = true;
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Turn an RFC 5322 date like `Tue, 13 May 2024 10:22:01 +0200` into `2024-05-13T10:22:01+02:00`,
+/// so that `date:2024-05` filters can match it by prefix. The time of an unknown zone gets the `-00:00`
+/// that RFC 5322 gives a local time whose offset is not known.
+fn iso_date(value: &str) -> Option<String> {
+    let value = value.split_once(',').map(|(_, date)| date).unwrap_or(value);
+    let mut words = value.split_whitespace();
+    let day = words.next()?.parse::<u32>().ok()?;
+    let month = words.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|name| month.starts_with(name))? + 1;
+    let year = match words.next()?.parse::<u32>().ok()? {
+        year @ 0..=49 => year + 2000,
+        year @ 50..=999 => year + 1900,
+        year => year,
+    };
+    let time = words.next()?;
+    let time = match time.split(':').collect::<Vec<_>>().as_slice() {
+        [hours, minutes] => format!("{hours:0>2}:{minutes:0>2}:00"),
+        [hours, minutes, seconds] => format!("{hours:0>2}:{minutes:0>2}:{seconds:0>2}"),
+        _ => return None,
+    };
+    let zone = match words.next().map(|zone| zone.to_ascii_uppercase()) {
+        Some(zone) if zone.len() == 5 && zone.starts_with(['+', '-']) && zone[1..].bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{}:{}", &zone[..3], &zone[3..])
+        }
+        // The obsolete zones of RFC 5322, the others say nothing about the offset
+        Some(zone) => match zone.as_str() {
+            "UT" | "GMT" | "Z" => "Z",
+            "EDT" => "-04:00",
+            "EST" | "CDT" => "-05:00",
+            "CST" | "MDT" => "-06:00",
+            "MST" | "PDT" => "-07:00",
+            "PST" => "-08:00",
+            _ => "-00:00",
+        }.to_string(),
+        None => "-00:00".to_string(),
+    };
+    Some(format!("{year:04}-{month:02}-{day:02}T{time}{zone}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_become_iso_8601() {
+        assert_eq!(iso_date("Tue, 14 May 2024 10:22:01 +0200").as_deref(), Some("2024-05-14T10:22:01+02:00"));
+        assert_eq!(iso_date("1 Jan 99 9:05 GMT").as_deref(), Some("1999-01-01T09:05:00Z"));
+        assert_eq!(iso_date("not a date"), None);
+    }
+
+    fn texts(parts: &[(Headers, &[u8])]) -> Vec<String> {
+        parts.iter().map(|(_, body)| String::from_utf8_lossy(body).trim().to_string()).collect()
+    }
+
+    #[test]
+    fn multipart_bodies_split_on_whole_delimiter_lines() {
+        let body = b"preamble\r\n--abc\r\nContent-Type: multipart/mixed; boundary=abcdef\r\n\r\n\
+            --abcdef\r\n\r\ninner one\r\n--abcdef\r\n\r\ninner two\r\n--abcdef--\r\n\
+            --abc \r\nContent-Type: text/plain\r\n\r\nouter two\r\n--abc--\r\nepilogue\r\n--abc\r\n\r\nignored\r\n";
+        let parts = split_multipart(body, "abc");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(header(&parts[0].0, "content-type"), Some("multipart/mixed; boundary=abcdef"));
+        assert_eq!(texts(&split_multipart(parts[0].1, "abcdef")), ["inner one", "inner two"]);
+        assert_eq!(texts(&parts[1..]), ["outer two"]);
+    }
+
+    fn parse(bytes: &[u8]) -> ParsedDocument {
+        let limits = Limits { max_file_size: u64::MAX, max_text: usize::MAX };
+        EmailParser.parse(Path::new("inbox.mbox"), bytes, limits).unwrap()
+    }
+
+    #[test]
+    fn mailboxes_split_into_messages() {
+        let mailbox = parse(b"\n  \nFrom alice@example.com Mon May 13 10:00:00 2024\nFrom: alice@example.com\nSubject: One\n\nHello\n\
+            From bob@example.com Mon May 13 11:00:00 2024\nFrom: bob@example.com\nSubject: Two\n\n>From here on\n>>From there\n");
+        let names = mailbox.parts.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["#message=1", "#message=2"]);
+        let second = &mailbox.parts[1].1;
+        assert_eq!(second.metadata.get("from").map(String::as_str), Some("bob@example.com"));
+        assert_eq!(second.metadata.get("mime").map(String::as_str), Some(EML));
+        assert_eq!(second.text, "Two\n\nFrom here on\n>From there\n");
+    }
+
+    #[test]
+    fn nested_multipart_bodies_keep_the_readable_parts() {
+        let message = parse(b"Subject: Report\nContent-Type: multipart/mixed; boundary=\"outer\"\n\n--outer\n\
+            Content-Type: multipart/alternative; boundary=\"outer-alt\"\n\n--outer-alt\nContent-Type: text/html\n\n<p>as html</p>\n\
+            --outer-alt\nContent-Type: text/plain\n\nas plain text\n--outer-alt--\n--outer\n\
+            Content-Type: application/pdf\nContent-Disposition: attachment\n\n%PDF-1.7\n--outer\n\
+            Content-Type: text/plain\nContent-Disposition: attachment; filename=notes.txt\n\nattached notes\n--outer--\n");
+        assert_eq!(message.text, "Report\n\nas plain text\n");
+    }
+
+    /// Message whose text is in a part `levels` multipart bodies down
+    fn nested(levels: usize) -> Vec<u8> {
+        let mut message = "Subject: Nested\n".to_string();
+        for level in 0..levels {
+            message.push_str(&format!("Content-Type: multipart/mixed; boundary=\"b{level}\"\n\n--b{level}\n"));
+        }
+        message.push_str("Content-Type: text/plain\n\ndeep down\n");
+        for level in (0..levels).rev() {
+            message.push_str(&format!("--b{level}--\n"));
+        }
+        message.into_bytes()
+    }
+
+    #[test]
+    fn bodies_nested_too_deep_are_left_out() {
+        assert_eq!(parse(&nested(MAX_DEPTH)).text, "Nested\n\ndeep down\n");
+        assert_eq!(parse(&nested(MAX_DEPTH + 1)).text, "Nested\n\n");
+        // Deep enough to overflow the stack if every level was walked down
+        assert_eq!(parse(&nested(100_000)).text, "Nested\n\n");
+    }
+
+    #[test]
+    fn transfer_encodings_and_charsets_are_decoded() {
+        let message = parse(b"Content-Type: text/plain; charset=iso-8859-1\nContent-Transfer-Encoding: quoted-printable\n\n\
+            Caf=E9 cr=E8me, a line that goes on=\n and on =3D done\n");
+        assert_eq!(message.text, "Caf\u{e9} cr\u{e8}me, a line that goes on and on = done\n");
+        let message = parse(b"Content-Type: text/plain; charset=utf-8\nContent-Transfer-Encoding: base64\n\nR3LDvMOf\nZSBhdXMgQmVybGlu\n");
+        assert_eq!(message.text, "Gr\u{fc}\u{df}e aus Berlin");
+        let message = parse(b"Content-Type: text/html; charset=windows-1252\n\n<p>\x93quoted\x94</p>");
+        assert_eq!(message.text.trim(), "\u{201c}quoted\u{201d}");
+    }
+
+    #[test]
+    fn encoded_words_of_headers_are_decoded() {
+        assert_eq!(decode_words("=?UTF-8?B?R3LDvMOfZQ==?= =?ISO-8859-1?Q?aus_K=F6ln?= !"), "Gr\u{fc}\u{df}eaus K\u{f6}ln !");
+        assert_eq!(decode_words("Re: =?utf-8*en?q?caf=C3=A9?= talk"), "Re: caf\u{e9} talk");
+        assert_eq!(decode_words("=?broken?= and 2 + 2 =? 4"), "=?broken?= and 2 + 2 =? 4");
+        let message = parse(b"From: =?UTF-8?Q?J=C3=BCrgen?= <j@example.com>\nSubject: =?UTF-8?Q?Gr=C3=BC=C3=9Fe?=\n\nHi");
+        assert_eq!(message.metadata.get("from").map(String::as_str), Some("J\u{fc}rgen <j@example.com>"));
+        assert_eq!(message.metadata.get("title").map(String::as_str), Some("Gr\u{fc}\u{df}e"));
+    }
+
+    #[test]
+    fn zones_that_are_not_ascii_are_ignored() {
+        assert_eq!(iso_date("Tue, 14 May 2024 10:22:01 +0€").as_deref(), Some("2024-05-14T10:22:01-00:00"));
+    }
+
+    #[test]
+    fn obsolete_zones_keep_their_offset() {
+        assert_eq!(iso_date("Mon, 13 May 2024 22:00:00 EST").as_deref(), Some("2024-05-13T22:00:00-05:00"));
+        assert_eq!(iso_date("Mon, 13 May 2024 22:00:00 pdt").as_deref(), Some("2024-05-13T22:00:00-07:00"));
+        assert_eq!(iso_date("Mon, 13 May 2024 22:00:00 UT").as_deref(), Some("2024-05-13T22:00:00Z"));
+        assert_eq!(iso_date("Mon, 13 May 2024 22:00:00 CEST").as_deref(), Some("2024-05-13T22:00:00-00:00"));
+        assert_eq!(iso_date("Mon, 13 May 2024 22:00:00").as_deref(), Some("2024-05-13T22:00:00-00:00"));
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{self, Read};
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// How much is read at once when decoding a stream, and how much of its beginning tells its encoding
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Decode text of an unknown character encoding into UTF-8. The byte order mark wins, then the
+/// encoding the document declares itself, then UTF-16 or UTF-8 when the bytes look like it, and the
+/// guess of a detector trained on legacy encodings (Windows-1252, Shift_JIS, ...) otherwise.
+pub fn decode(bytes: &[u8], declared: Option<&'static Encoding>) -> (String, &'static Encoding) {
+    let encoding = detect(bytes, declared);
+    let (text, _) = encoding.decode_with_bom_removal(bytes);
+    (text.into_owned(), encoding)
+}
+
+/// Like `decode`, but for text read and handed over to `sink` a chunk at a time, until it returns
+/// false. The encoding is told from the first chunk.
+pub fn decode_reader(reader: &mut dyn Read, declared: Option<&'static Encoding>, mut sink: impl FnMut(&str) -> bool) -> io::Result<&'static Encoding> {
+    let mut bytes = Vec::with_capacity(CHUNK_LEN);
+    (&mut *reader).take(CHUNK_LEN as u64).read_to_end(&mut bytes)?;
+    let encoding = detect(&bytes, declared);
+    let mut decoder = encoding.new_decoder_with_bom_removal();
+    let mut text = String::new();
+    loop {
+        let last = bytes.is_empty();
+        text.clear();
+        text.reserve(decoder.max_utf8_buffer_length(bytes.len()).unwrap_or(bytes.len() * 3 + 16));
+        let _ = decoder.decode_to_string(&bytes, &mut text, last);
+        if !sink(&text) || last {
+            return Ok(encoding);
+        }
+        bytes.clear();
+        (&mut *reader).take(CHUNK_LEN as u64).read_to_end(&mut bytes)?;
+    }
+}
+
+/// The encoding of the text that starts with `head`, see `decode`
+fn detect(head: &[u8], declared: Option<&'static Encoding>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(head) {
+        return encoding;
+    }
+    // The head may end in the middle of a character
+    let utf8 = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    };
+    declared
+        .or_else(|| utf16_without_bom(head))
+        .or_else(|| utf8.then_some(UTF_8))
+        .unwrap_or_else(|| {
+            let mut detector = EncodingDetector::new();
+            detector.feed(head, true);
+            detector.guess(None, true)
+        })
+}
+
+/// UTF-16 without a byte order mark, told by the ASCII characters whose zero high byte
+/// is on the same side of most pairs
+pub fn utf16_without_bom(bytes: &[u8]) -> Option<&'static Encoding> {
+    let pairs = bytes.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let ascii = |byte: u8| byte.is_ascii_graphic() || matches!(byte, b' ' | b'\t' | b'\n' | b'\r');
+    let little_endian = bytes.chunks_exact(2).filter(|pair| pair[1] == 0 && ascii(pair[0])).count();
+    let big_endian = bytes.chunks_exact(2).filter(|pair| pair[0] == 0 && ascii(pair[1])).count();
+    if little_endian * 2 > pairs && big_endian == 0 {
+        Some(UTF_16LE)
+    } else if big_endian * 2 > pairs && little_endian == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+    use super::*;
+
+    #[test]
+    fn the_byte_order_mark_wins_and_is_removed() {
+        assert_eq!(decode(b"\xEF\xBB\xBFcaf\xC3\xA9", Some(WINDOWS_1252)), ("café".to_string(), UTF_8));
+        assert_eq!(decode(b"\xFF\xFEh\0i\0", None), ("hi".to_string(), UTF_16LE));
+        assert_eq!(decode(b"\xFE\xFF\0h\0i", None), ("hi".to_string(), UTF_16BE));
+    }
+
+    #[test]
+    fn the_declared_encoding_comes_before_detection() {
+        assert_eq!(decode(b"caf\xC3\xA9", Some(WINDOWS_1252)), ("cafÃ©".to_string(), WINDOWS_1252));
+        assert_eq!(decode(b"caf\xC3\xA9", None), ("café".to_string(), UTF_8));
+    }
+
+    #[test]
+    fn utf16_is_told_without_a_byte_order_mark() {
+        let little_endian = "plain text".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let big_endian = "plain text".encode_utf16().flat_map(u16::to_be_bytes).collect::<Vec<_>>();
+        assert_eq!(decode(&little_endian, None), ("plain text".to_string(), UTF_16LE));
+        assert_eq!(decode(&big_endian, None), ("plain text".to_string(), UTF_16BE));
+        assert_eq!(utf16_without_bom(b"plain text"), None);
+    }
+
+    #[test]
+    fn legacy_encodings_are_guessed() {
+        let french = "Le café est très chaud, voilà ce que répète l'élève à côté de la fenêtre.";
+        let (bytes, _, _) = WINDOWS_1252.encode(french);
+        assert_eq!(decode(&bytes, None), (french.to_string(), WINDOWS_1252));
+
+        let japanese = "日本語の文章は、シフトJISで書かれることがまだよくあります。東京都の天気は晴れです。";
+        let (bytes, _, _) = SHIFT_JIS.encode(japanese);
+        assert_eq!(decode(&bytes, None), (japanese.to_string(), SHIFT_JIS));
+    }
+
+    fn decode_chunks(bytes: &[u8]) -> (Vec<String>, &'static Encoding) {
+        let mut chunks = Vec::new();
+        let encoding = decode_reader(&mut &bytes[..], None, |text| {
+            chunks.push(text.to_string());
+            true
+        }).unwrap();
+        (chunks, encoding)
+    }
+
+    #[test]
+    fn characters_split_across_chunks_are_decoded_whole() {
+        // "é" takes the last byte of the first chunk and the first byte of the second
+        let text = format!("{}é{}", "a".repeat(CHUNK_LEN - 1), "b".repeat(10));
+        let (chunks, encoding) = decode_chunks(text.as_bytes());
+        assert_eq!(encoding, UTF_8);
+        assert_eq!(chunks.concat(), text);
+        assert!(chunks.len() > 1);
+
+        // So does the surrogate pair of "😀" in UTF-16, after the byte order mark
+        let text = format!("{}😀b", "a".repeat(CHUNK_LEN / 2 - 2));
+        let utf16 = "\u{FEFF}".encode_utf16().chain(text.encode_utf16()).flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        let (chunks, encoding) = decode_chunks(&utf16);
+        assert_eq!(encoding, UTF_16LE);
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn decoding_stops_when_the_sink_has_enough() {
+        let bytes = vec![b'a'; 3 * CHUNK_LEN];
+        let mut reader = io::Cursor::new(&bytes);
+        let mut chunks = 0;
+        decode_reader(&mut reader, None, |_| {
+            chunks += 1;
+            false
+        }).unwrap();
+        assert_eq!(chunks, 1);
+        assert_eq!(reader.position(), CHUNK_LEN as u64);
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::path::Path;
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent;
+
+use crate::config::Limits;
+use crate::lang::Language;
+use super::html;
+use super::package::Package;
+use super::{DocumentParser, ParsedDocument};
+
+pub const EPUB: &str = "application/epub+zip";
+
+/// Parser for EPUB e-books. Every chapter becomes a sub-document of the book
+/// named after its entry in the table of contents.
+pub struct EpubParser;
+
+impl DocumentParser for EpubParser {
+    fn name(&self) -> &'static str {
+        "epub"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["epub"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &[EPUB]
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], limits: Limits) -> Result<ParsedDocument, ()> {
+        let mut package = Package::open(file_path, bytes, limits)?;
+        let opf_path = rootfile(&mut package).ok_or_else(|| {
+            eprintln!("ERROR: {file_path} has no package document", file_path = file_path.display());
+        })?;
+        let opf = parse_opf(&mut package, &opf_path)?;
+        let mut book = ParsedDocument::default();
+        for (key, value) in &opf.metadata {
+            match key.as_str() {
+                "lang" => {
+                    if let Some(language) = Language::from_tag(value) {
+                        book.metadata.insert("lang".to_string(), language.code().to_string());
+                    }
+                    continue;
+                }
+                "title" | "author" | "subject" => book.fields.push((key.clone(), value.clone())),
+                _ => {}
+            }
+            if key != "date" {
+                book.text.push_str(value);
+                book.text.push('\n');
+            }
+            if key != "description" {
+                book.metadata.entry(key.clone()).or_insert(value.clone());
+            }
+        }
+
+        // A broken table of contents only costs the chapters their titles
+        let titles = table_of_contents(&mut package, &opf).unwrap_or_default();
+        for (number, href) in opf.spine.iter().enumerate() {
+            let content = match package.read_to_string(href) {
+                Ok(content) => content,
+                // The chapters beyond what the book may decompress are left out like the text beyond `max_text`
+                Err(()) if package.exhausted() => {
+                    book.metadata.insert("truncated".to_string(), "true".to_string());
+                    break;
+                }
+                Err(()) => continue,
+            };
+            let mut chapter = html::extract(&content);
+            if chapter.text.trim().is_empty() {
+                continue;
+            }
+            // The <title> of a chapter is more often than not the title of the book
+            chapter.fields.retain(|(name, _)| name != "title");
+            chapter.metadata.remove("title");
+            let title = titles.get(href).cloned()
+                .or_else(|| chapter.fields.iter().find(|(name, _)| name == "heading").map(|(_, heading)| heading.clone()));
+            if let Some(title) = title {
+                chapter.fields.push(("chapter".to_string(), title.clone()));
+                chapter.metadata.insert("chapter".to_string(), title);
+            }
+            book.parts.push((format!("#chapter={}", number + 1), chapter));
+        }
+        Ok(book)
+    }
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|attribute| attribute.name.local_name == name).map(|attribute| attribute.value.as_str())
+}
+
+/// Resolve the `href` found in the part `base` into the name of a part, without its fragment
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or("");
+    let mut segments = base.split('/').collect::<Vec<_>>();
+    segments.pop();
+    let decoded = percent_decode(href);
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Path of the package document (the .opf) according to META-INF/container.xml
+fn rootfile(package: &mut Package) -> Option<String> {
+    let mut rootfile = None;
+    package.read_xml("META-INF/container.xml", |event| {
+        if let XmlEvent::StartElement { name, attributes, .. } = event {
+            if name.local_name == "rootfile" && rootfile.is_none() {
+                rootfile = attribute(&attributes, "full-path").map(String::from);
+            }
+        }
+    }).ok()?;
+    rootfile
+}
+
+struct Opf {
+    metadata: Vec<(String, String)>,
+    /// Parts of the chapters in reading order
+    spine: Vec<String>,
+    /// The EPUB 3 navigation document
+    nav: Option<String>,
+    /// The EPUB 2 NCX table of contents
+    ncx: Option<String>,
+}
+
+fn parse_opf(package: &mut Package, opf_path: &str) -> Result<Opf, ()> {
+    let mut metadata = Vec::new();
+    let mut manifest = HashMap::new();
+    let mut nav = None;
+    let mut spine = Vec::new();
+    let mut toc = None;
+    let mut in_metadata = false;
+    let mut element = String::new();
+    let mut value = String::new();
+    package.read_xml(opf_path, |event| match event {
+        XmlEvent::StartElement { name, attributes, .. } => match name.local_name.as_str() {
+            "metadata" => in_metadata = true,
+            "item" => {
+                if let (Some(id), Some(href)) = (attribute(&attributes, "id"), attribute(&attributes, "href")) {
+                    let href = resolve(opf_path, href);
+                    let properties = attribute(&attributes, "properties").unwrap_or("");
+                    if properties.split_whitespace().any(|property| property == "nav") {
+                        nav = Some(href.clone());
+                    }
+                    manifest.insert(id.to_string(), href);
+                }
+            }
+            "spine" => toc = attribute(&attributes, "toc").map(String::from),
+            "itemref" => spine.extend(attribute(&attributes, "idref").map(String::from)),
+            local_name if in_metadata => {
+                element = local_name.to_string();
+                value.clear();
+            }
+            _ => {}
+        },
+        XmlEvent::Characters(chars) if in_metadata => value.push_str(&chars),
+        XmlEvent::EndElement { name } => {
+            if name.local_name == "metadata" {
+                in_metadata = false;
+            }
+            let key = match std::mem::take(&mut element).as_str() {
+                "title" => "title",
+                "creator" => "author",
+                "language" => "lang",
+                "subject" => "subject",
+                "description" => "description",
+                "publisher" => "publisher",
+                "date" => "date",
+                _ => return,
+            };
+            let value = std::mem::take(&mut value);
+            if !value.trim().is_empty() {
+                metadata.push((key.to_string(), value.trim().to_string()));
+            }
+        }
+        _ => {}
+    })?;
+    Ok(Opf {
+        metadata,
+        spine: spine.iter().filter_map(|id| manifest.get(id).cloned()).collect(),
+        nav,
+        ncx: toc.and_then(|id| manifest.get(&id).cloned()),
+    })
+}
+
+/// Titles of the chapters by part name, from the navigation document of EPUB 3
+/// or the NCX of EPUB 2
+fn table_of_contents(package: &mut Package, opf: &Opf) -> Result<HashMap<String, String>, ()> {
+    let mut titles = HashMap::new();
+    let Some(toc) = opf.nav.as_ref().or(opf.ncx.as_ref()) else {
+        return Ok(titles);
+    };
+    let mut target = None;
+    let mut label = String::new();
+    let mut in_label = false;
+    package.read_xml(toc, |event| match event {
+        XmlEvent::StartElement { name, attributes, .. } => match name.local_name.as_str() {
+            // <a href> in the navigation document, <navLabel><text> then <content src> in the NCX
+            "a" => {
+                target = attribute(&attributes, "href").map(|href| resolve(toc, href));
+                label.clear();
+                in_label = true;
+            }
+            "navLabel" => {
+                label.clear();
+                in_label = true;
+            }
+            "content" => {
+                if let Some(src) = attribute(&attributes, "src") {
+                    let label = label.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !label.is_empty() {
+                        titles.entry(resolve(toc, src)).or_insert(label);
+                    }
+                }
+            }
+            _ => {}
+        },
+        XmlEvent::Characters(chars) | XmlEvent::Whitespace(chars) if in_label => label.push_str(&chars),
+        XmlEvent::EndElement { name } => match name.local_name.as_str() {
+            "a" => {
+                in_label = false;
+                let label = label.split_whitespace().collect::<Vec<_>>().join(" ");
+                if let Some(target) = target.take() {
+                    if !label.is_empty() {
+                        titles.entry(target).or_insert(label);
+                    }
+                }
+            }
+            "navLabel" => in_label = false,
+            _ => {}
+        },
+        _ => {}
+    })?;
+    Ok(titles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::package::tests::{zip, NO_LIMITS};
+    use super::*;
+
+    const CONTAINER: &str = r#"<container xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles>
+        <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+    </rootfiles></container>"#;
+
+    const OPF: &str = r#"<package xmlns="http://www.idpf.org/2007/opf" xmlns:dc="http://purl.org/dc/elements/1.1/" version="3.0">
+        <metadata><dc:title>Moby Dick</dc:title><dc:creator>Herman Melville</dc:creator><dc:language>en</dc:language><dc:date>1851</dc:date></metadata>
+        <manifest>
+            <item id="nav" href="nav.xhtml" properties="nav" media-type="application/xhtml+xml"/>
+            <item id="c1" href="text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+            <item id="c2" href="text/chapter2.xhtml" media-type="application/xhtml+xml"/>
+            <item id="c3" href="text/chapter3.xhtml" media-type="application/xhtml+xml"/>
+        </manifest>
+        <spine><itemref idref="c1"/><itemref idref="c2"/><itemref idref="c3"/></spine>
+    </package>"#;
+
+    const NAV: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><nav><ol>
+        <li><a href="text/chapter%201.xhtml#start">  Loomings </a></li>
+    </ol></nav></body></html>"#;
+
+    fn chapter(heading: &str, text: &str) -> String {
+        format!("<html><head><title>Moby Dick</title></head><body><h1>{heading}</h1><p>{text}</p></body></html>")
+    }
+
+    fn book(limits: Limits) -> ParsedDocument {
+        let chapters = [chapter("Chapter 1", "Call me Ishmael."), chapter("The Carpet-Bag", "I stuffed a shirt or two."), chapter("The Spouter-Inn", "Entering that gable-ended inn.")];
+        let bytes = zip(&[
+            ("mimetype", EPUB.as_bytes()),
+            ("META-INF/container.xml", CONTAINER.as_bytes()),
+            ("OEBPS/content.opf", OPF.as_bytes()),
+            ("OEBPS/nav.xhtml", NAV.as_bytes()),
+            ("OEBPS/text/chapter 1.xhtml", chapters[0].as_bytes()),
+            ("OEBPS/text/chapter2.xhtml", chapters[1].as_bytes()),
+            ("OEBPS/text/chapter3.xhtml", chapters[2].as_bytes()),
+        ]);
+        EpubParser.parse(Path::new("moby-dick.epub"), &bytes, limits).unwrap()
+    }
+
+    fn field<'a>(document: &'a ParsedDocument, name: &str) -> Vec<&'a str> {
+        document.fields.iter().filter(|(field, _)| field == name).map(|(_, value)| value.as_str()).collect()
+    }
+
+    #[test]
+    fn chapters_are_parts_named_after_the_table_of_contents() {
+        let book = book(NO_LIMITS);
+        assert_eq!(book.text, "Moby Dick\nHerman Melville\n");
+        assert_eq!(field(&book, "title"), ["Moby Dick"]);
+        assert_eq!(field(&book, "author"), ["Herman Melville"]);
+        assert_eq!(book.metadata.get("lang").map(String::as_str), Some("en"));
+        assert_eq!(book.metadata.get("date").map(String::as_str), Some("1851"));
+        assert!(!book.metadata.contains_key("truncated"));
+
+        let names = book.parts.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["#chapter=1", "#chapter=2", "#chapter=3"]);
+        let (_, first) = &book.parts[0];
+        assert!(first.text.contains("Call me Ishmael."));
+        assert_eq!(field(first, "chapter"), ["Loomings"]);
+        assert!(field(first, "title").is_empty());
+        // Chapters missing from the table of contents are named after their heading
+        let (_, second) = &book.parts[1];
+        assert_eq!(field(second, "chapter"), ["The Carpet-Bag"]);
+        assert_eq!(second.metadata.get("chapter").map(String::as_str), Some("The Carpet-Bag"));
+    }
+
+    #[test]
+    fn chapters_beyond_what_the_book_may_decompress_are_left_out() {
+        let budget = (CONTAINER.len() + OPF.len() + NAV.len() + 2 * chapter("Chapter 1", "Call me Ishmael.").len()) as u64;
+        let book = book(Limits { max_file_size: budget, max_text: usize::MAX });
+        assert_eq!(book.parts.len(), 1);
+        assert_eq!(book.metadata.get("truncated").map(String::as_str), Some("true"));
+    }
+
+    #[test]
+    fn hrefs_are_resolved_against_their_part() {
+        assert_eq!(resolve("OEBPS/content.opf", "text/ch%201.xhtml#top"), "OEBPS/text/ch 1.xhtml");
+        assert_eq!(resolve("OEBPS/text/nav.xhtml", "../images/./a.png"), "OEBPS/images/a.png");
+        assert_eq!(resolve("content.opf", "100%.xhtml"), "100%.xhtml");
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, Limits};
+use crate::sandbox::{self, TempDir};
+
+use super::encoding;
+use super::{DocumentParser, ParsedDocument};
+
+/// How much of what a failing command says on stderr ends up in the error
+const MAX_STDERR: usize = 4096;
+
+/// Parser that runs an external command and indexes its output. The command is split on spaces,
+/// with no shell involved, and `{}` stands for the path of the file. Without `{}` the content of
+/// the file is written to the standard input of the command.
+pub struct ExternalParser {
+    extensions: &'static [&'static str],
+    mime_types: &'static [&'static str],
+    arguments: Vec<String>,
+    timeout: Duration,
+    max_output: usize,
+}
+
+impl ExternalParser {
+    pub fn new(extension: &str, command: &str, config: &Config) -> Self {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        let mime_type = format!("application/x-{extension}");
+        // Parsers are configured once and live as long as the program does
+        Self {
+            extensions: Box::leak(Box::new([&*Box::leak(extension.into_boxed_str())])),
+            mime_types: Box::leak(Box::new([&*Box::leak(mime_type.into_boxed_str())])),
+            arguments: split_command(command),
+            timeout: Duration::from_secs(config.timeout),
+            max_output: config.max_output,
+        }
+    }
+}
+
+impl DocumentParser for ExternalParser {
+    fn name(&self) -> &'static str {
+        "external command"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        self.extensions
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        self.mime_types
+    }
+
+    fn overrides_sniffing(&self) -> bool {
+        true
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let (program, arguments) = self.command(file_path)?;
+        let takes_path = arguments.iter().any(|argument| argument.contains("{}"));
+        // Members of archives have no path of their own to hand over to the command
+        let temporary = (takes_path && !file_path.is_file()).then(|| temporary_copy(file_path, bytes)).transpose()?;
+        let path = temporary.as_ref().map_or(file_path, |(_, path)| path.as_path());
+        let result = self.run(program, arguments, path, (!takes_path).then_some(bytes));
+        self.document(program, file_path, result)
+    }
+
+    /// The commands that take the path of a file read it themselves, so it is not read here at all
+    fn parse_reader(&self, file_path: &Path, reader: &mut dyn Read, limits: Limits) -> Result<ParsedDocument, ()> {
+        let (program, arguments) = self.command(file_path)?;
+        if arguments.iter().any(|argument| argument.contains("{}")) && file_path.is_file() {
+            return self.document(program, file_path, self.run(program, arguments, file_path, None));
+        }
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|err| {
+            eprintln!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
+        })?;
+        self.parse(file_path, &bytes, limits)
+    }
+}
+
+impl ExternalParser {
+    fn command(&self, file_path: &Path) -> Result<(&String, &[String]), ()> {
+        self.arguments.split_first().ok_or_else(|| {
+            eprintln!("ERROR: no command is configured for {file_path}", file_path = file_path.display());
+        })
+    }
+
+    /// The document of the output of the command, recorded as `truncated` when it is cut at `max_output`
+    fn document(&self, program: &str, file_path: &Path, result: Result<(Vec<u8>, bool), String>) -> Result<ParsedDocument, ()> {
+        let (output, truncated) = result.map_err(|message| {
+            eprintln!("ERROR: {program} failed on {file_path}: {message}", file_path = file_path.display());
+        })?;
+        let (text, _) = encoding::decode(&output, None);
+        let mut document = ParsedDocument { text, ..Default::default() };
+        if truncated {
+            println!("WARNING: the output of {program} on {file_path:?} is cut at {max} bytes", max = self.max_output);
+            document.metadata.insert("truncated".to_string(), "true".to_string());
+        }
+        Ok(document)
+    }
+
+    /// The output of the command, and whether it had to be cut at `max_output`
+    fn run(&self, program: &str, arguments: &[String], path: &Path, stdin: Option<&[u8]>) -> Result<(Vec<u8>, bool), String> {
+        let deadline = Instant::now() + self.timeout;
+        let path = path.to_string_lossy();
+        let mut command = Command::new(program);
+        command
+            .args(arguments.iter().map(|argument| argument.replace("{}", &path)))
+            .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // Wrappers like shell scripts run the actual command in a process of its own, which is killed with them
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let mut child = command.spawn().map_err(|err| format!("could not start it: {err}"))?;
+        if let (Some(mut input), Some(bytes)) = (child.stdin.take(), stdin.map(<[u8]>::to_vec)) {
+            // A command that stops reading early closes the pipe, which is not an error of ours
+            thread::spawn(move || { let _ = input.write_all(&bytes); });
+        }
+        let stdout = read_in_background(child.stdout.take(), self.max_output.saturating_add(1));
+        let stderr = read_in_background(child.stderr.take(), MAX_STDERR);
+
+        let timed_out = || format!("it did not finish within {} seconds", self.timeout.as_secs());
+        let status = match sandbox::wait_or_kill(&mut child, self.timeout) {
+            Ok(Some(status)) => status,
+            Ok(None) => return Err(timed_out()),
+            Err(err) => {
+                sandbox::kill(&mut child);
+                let _ = child.wait();
+                return Err(format!("could not wait for it: {err}"));
+            }
+        };
+        // What the command left running may keep its pipes open, so they are not waited for beyond the deadline
+        let remaining = || deadline.saturating_duration_since(Instant::now());
+        if !status.success() {
+            let errors = stderr.recv_timeout(remaining()).unwrap_or_default();
+            let errors = String::from_utf8_lossy(&errors);
+            let reason = errors.lines().find(|line| !line.trim().is_empty()).unwrap_or("").trim().to_string();
+            return Err(if reason.is_empty() { format!("it exited with {status}") } else { format!("it exited with {status}: {reason}") });
+        }
+        let mut output = stdout.recv_timeout(remaining()).map_err(|_| timed_out())?;
+        let truncated = output.len() > self.max_output;
+        output.truncate(self.max_output);
+        Ok((output, truncated))
+    }
+}
+
+/// Read up to `limit` bytes of the pipe, then keep draining it so that the command is not blocked.
+/// They are sent once the pipe is closed.
+fn read_in_background<R: Read + Send + 'static>(pipe: Option<R>, limit: usize) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.by_ref().take(limit as u64).read_to_end(&mut bytes);
+            let _ = io::copy(&mut pipe, &mut io::sink());
+        }
+        let _ = sender.send(bytes);
+    });
+    receiver
+}
+
+/// Copy of the content of the file in a private temporary directory, under the name of the file
+/// so that the command can tell its format
+fn temporary_copy(file_path: &Path, bytes: &[u8]) -> Result<(TempDir, PathBuf), ()> {
+    let directory = TempDir::new().map_err(|err| {
+        eprintln!("ERROR: could not create a temporary directory for {file_path}: {err}", file_path = file_path.display());
+    })?;
+    let path = directory.path().join(file_path.file_name().unwrap_or("file".as_ref()));
+    sandbox::create_new(&path).and_then(|mut file| file.write_all(bytes)).map_err(|err| {
+        eprintln!("ERROR: could not write temporary file {path}: {err}", path = path.display());
+    })?;
+    Ok((directory, path))
+}
+
+/// Split the command on spaces, keeping together what is within single or double quotes
+fn split_command(command: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut argument = None::<String>;
+    let mut quote = None;
+    for c in command.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                argument.get_or_insert_with(String::new);
+            }
+            (Some(open), c) if c == open => quote = None,
+            (None, c) if c.is_whitespace() => arguments.extend(argument.take()),
+            (_, c) => argument.get_or_insert_with(String::new).push(c),
+        }
+    }
+    arguments.extend(argument);
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_split_on_spaces_outside_quotes() {
+        assert_eq!(split_command("pandoc  -t plain   {}"), ["pandoc", "-t", "plain", "{}"]);
+        assert_eq!(split_command("tool --title \"two words\" 'it''s' \"\""), ["tool", "--title", "two words", "its", ""]);
+        assert_eq!(split_command(""), Vec::<String>::new());
+        assert_eq!(split_command("   "), Vec::<String>::new());
+    }
+
+    #[cfg(unix)]
+    fn run(command: &str, stdin: Option<&[u8]>, max_output: usize, timeout: Duration) -> Result<(Vec<u8>, bool), String> {
+        let mut parser = ExternalParser::new("test", command, &Config::default());
+        parser.max_output = max_output;
+        parser.timeout = timeout;
+        let (program, arguments) = parser.command(Path::new("file.test")).unwrap();
+        parser.run(program, arguments, Path::new("file.test"), stdin)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_is_cut_at_max_output() {
+        let timeout = Duration::from_secs(5);
+        assert_eq!(run("cat", Some(b"abcdef"), 4, timeout), Ok((b"abcd".to_vec(), true)));
+        assert_eq!(run("cat", Some(b"abcd"), 4, timeout), Ok((b"abcd".to_vec(), false)));
+        assert_eq!(run("echo {}", None, usize::MAX, timeout), Ok((b"file.test\n".to_vec(), false)));
+    }
+
+    #[test]
+    fn members_are_copied_into_private_directories() {
+        let (directory, path) = temporary_copy(Path::new("bundle.zip!/docs/notes.rst"), b"text").unwrap();
+        assert_eq!(path, directory.path().join("notes.rst"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"text");
+        drop(directory);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failures_say_why() {
+        let failure = run("sh -c 'echo; echo \"no such format\" >&2; exit 3'", None, 100, Duration::from_secs(5)).unwrap_err();
+        assert!(failure.ends_with(": no such format"), "{failure}");
+        let failure = run("sleep 10", None, 100, Duration::from_millis(100)).unwrap_err();
+        assert!(failure.starts_with("it did not finish"), "{failure}");
+        // The shell waits for a sleep of its own, which has its pipes too
+        let start = Instant::now();
+        let failure = run("sh -c 'sleep 10; echo done'", None, 100, Duration::from_millis(100)).unwrap_err();
+        assert!(failure.starts_with("it did not finish"), "{failure}");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(run("no-such-command-for-sure", None, 100, Duration::from_secs(5)).unwrap_err().starts_with("could not start it"));
+    }
+}
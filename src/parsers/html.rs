@@ -0,0 +1,341 @@
+use std::path::Path;
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::config::Limits;
+use crate::lang::Language;
+use super::encoding;
+use super::{DocumentParser, ParsedDocument};
+
+/// Elements whose content is code or styling rather than text
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style", "template", "svg", "math"];
+/// Elements that do not separate words, so `foo<b>bar</b>` is still one word
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "dfn", "em", "font", "i", "kbd", "mark",
+    "q", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var", "wbr",
+];
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Parser that makes sense of HTML the way browsers do: unclosed and unknown tags
+/// are fine, scripts and styles are dropped, entities are decoded
+pub struct HtmlParser;
+
+impl DocumentParser for HtmlParser {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["html", "htm", "xhtml"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/html", "application/xhtml+xml"]
+    }
+
+    fn parse(&self, _file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let (html, encoding) = encoding::decode(bytes, declared_charset(bytes));
+        let mut document = extract(&html);
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        Ok(document)
+    }
+}
+
+/// The `charset` of `<meta charset>` or `<meta http-equiv="Content-Type">`, which browsers
+/// look for in the first 1024 bytes of the page
+fn declared_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = bytes[..bytes.len().min(1024)].to_ascii_lowercase();
+    let start = head.windows(8).position(|window| window == b"charset=")? + 8;
+    let label = head[start..].iter()
+        .skip_while(|b| matches!(b, b'"' | b'\''))
+        .take_while(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b':' | b'.'))
+        .copied()
+        .collect::<Vec<_>>();
+    // A page that says it is UTF-16 is not, or the declaration would not be readable as ASCII
+    Encoding::for_label(&label).map(|encoding| if encoding.is_ascii_compatible() { encoding } else { UTF_8 })
+}
+
+struct Tag<'a> {
+    name: String,
+    closing: bool,
+    /// Everything after the name up to the closing `>`
+    attributes: &'a str,
+}
+
+/// Parse the tag at the start of `html` (which starts with `<`) and return it with its length
+fn parse_tag(html: &str) -> Option<(Tag<'_>, usize)> {
+    let rest = &html[1..];
+    let (closing, rest) = match rest.strip_prefix('/') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let name_len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != ':').unwrap_or(rest.len());
+    if name_len == 0 || !rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let name = rest[..name_len].to_ascii_lowercase();
+    let mut quote = None;
+    for (i, c) in rest[name_len..].char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '>') => {
+                let end = name_len + i;
+                let tag = Tag { name, closing, attributes: &rest[name_len..end] };
+                return Some((tag, html.len() - rest.len() + end + 1));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Value of the attribute `name` in the attribute list of a tag
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let lower = attributes.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(found) = lower[from..].find(name) {
+        let start = from + found;
+        from = start + name.len();
+        let before = lower[..start].chars().next_back();
+        if before.map(|c| !c.is_whitespace()).unwrap_or(false) {
+            continue;
+        }
+        let rest = attributes[from..].trim_start();
+        let Some(rest) = rest.strip_prefix('=') else { continue };
+        let rest = rest.trim_start();
+        return Some(match rest.chars().next() {
+            Some(q @ ('"' | '\'')) => rest[1..].split(q).next().unwrap_or(""),
+            _ => rest.split(|c: char| c.is_whitespace() || c == '>').next().unwrap_or(""),
+        });
+    }
+    None
+}
+
+/// Offset of the closing tag `</name` in `html`, whatever the case of its name
+fn find_closing_tag(html: &str, name: &str) -> Option<usize> {
+    let mut from = 0;
+    while let Some(found) = html[from..].find("</") {
+        let start = from + found;
+        let candidate = html.as_bytes().get(start + 2..start + 2 + name.len());
+        if candidate.is_some_and(|candidate| candidate.eq_ignore_ascii_case(name.as_bytes())) {
+            return Some(start);
+        }
+        from = start + 2;
+    }
+    None
+}
+
+fn push_separator(text: &mut String) {
+    if !text.is_empty() && !text.ends_with(' ') {
+        text.push(' ');
+    }
+}
+
+/// Extract the readable text of an HTML document along with its title and headings
+pub fn extract(html: &str) -> ParsedDocument {
+    let mut document = ParsedDocument::default();
+    let mut title: Option<String> = None;
+    let mut heading: Option<String> = None;
+    let mut i = 0;
+    while i < html.len() {
+        let rest = &html[i..];
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = decode_entities(&rest[..end]);
+            for target in [Some(&mut document.text), title.as_mut(), heading.as_mut()].into_iter().flatten() {
+                target.push_str(&text);
+            }
+            i += end;
+            continue;
+        }
+        if rest.starts_with("<!--") {
+            i += rest.find("-->").map(|end| end + 3).unwrap_or(rest.len());
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            i += rest.find('>').map(|end| end + 1).unwrap_or(rest.len());
+            continue;
+        }
+        let Some((tag, len)) = parse_tag(rest) else {
+            // A lone `<` is just text
+            document.text.push('<');
+            i += 1;
+            continue;
+        };
+        i += len;
+        if !INLINE_ELEMENTS.contains(&tag.name.as_str()) {
+            push_separator(&mut document.text);
+            if let Some(heading) = heading.as_mut() {
+                push_separator(heading);
+            }
+        }
+        match (tag.name.as_str(), tag.closing) {
+            (name, false) if RAW_TEXT_ELEMENTS.contains(&name) && !tag.attributes.trim_end().ends_with('/') => {
+                i += find_closing_tag(&html[i..], name).unwrap_or(html.len() - i);
+            }
+            ("title", false) => title = Some(String::new()),
+            ("title", true) => {
+                if let Some(title) = title.take() {
+                    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !title.is_empty() {
+                        document.fields.push(("title".to_string(), title.clone()));
+                        document.metadata.entry("title".to_string()).or_insert(title);
+                    }
+                }
+            }
+            (name, false) if HEADINGS.contains(&name) => heading = Some(String::new()),
+            (name, true) if HEADINGS.contains(&name) => {
+                if let Some(heading) = heading.take() {
+                    let heading = heading.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !heading.is_empty() {
+                        document.fields.push(("heading".to_string(), heading));
+                    }
+                }
+            }
+            ("img", false) => {
+                if let Some(alt) = attribute(tag.attributes, "alt") {
+                    document.text.push_str(&decode_entities(alt));
+                    push_separator(&mut document.text);
+                }
+            }
+            ("html", false) => {
+                if let Some(language) = attribute(tag.attributes, "lang").and_then(Language::from_tag) {
+                    document.metadata.insert("lang".to_string(), language.code().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    document
+}
+
+fn named_entity(name: &str) -> Option<char> {
+    let c = match name {
+        "amp" => '&', "lt" => '<', "gt" => '>', "quot" => '"', "apos" => '\'',
+        "nbsp" => ' ', "ensp" => ' ', "emsp" => ' ', "thinsp" => ' ', "shy" => '\u{ad}',
+        "copy" => '©', "reg" => '®', "trade" => '™', "deg" => '°', "plusmn" => '±',
+        "times" => '×', "divide" => '÷', "middot" => '·', "bull" => '•', "hellip" => '…',
+        "ndash" => '–', "mdash" => '—', "lsquo" => '‘', "rsquo" => '’', "sbquo" => '‚',
+        "ldquo" => '“', "rdquo" => '”', "bdquo" => '„', "laquo" => '«', "raquo" => '»',
+        "lsaquo" => '‹', "rsaquo" => '›', "euro" => '€', "pound" => '£', "yen" => '¥',
+        "cent" => '¢', "curren" => '¤', "sect" => '§', "para" => '¶', "iexcl" => '¡',
+        "iquest" => '¿', "ordf" => 'ª', "ordm" => 'º', "micro" => 'µ', "dagger" => '†',
+        "Dagger" => '‡', "permil" => '‰', "prime" => '′', "frac12" => '½', "frac14" => '¼',
+        "frac34" => '¾', "sup1" => '¹', "sup2" => '²', "sup3" => '³', "larr" => '←',
+        "rarr" => '→', "uarr" => '↑', "darr" => '↓', "harr" => '↔', "ne" => '≠',
+        "le" => '≤', "ge" => '≥', "infin" => '∞', "minus" => '−',
+        "Agrave" => 'À', "Aacute" => 'Á', "Acirc" => 'Â', "Atilde" => 'Ã', "Auml" => 'Ä',
+        "Aring" => 'Å', "AElig" => 'Æ', "Ccedil" => 'Ç', "Egrave" => 'È', "Eacute" => 'É',
+        "Ecirc" => 'Ê', "Euml" => 'Ë', "Igrave" => 'Ì', "Iacute" => 'Í', "Icirc" => 'Î',
+        "Iuml" => 'Ï', "ETH" => 'Ð', "Ntilde" => 'Ñ', "Ograve" => 'Ò', "Oacute" => 'Ó',
+        "Ocirc" => 'Ô', "Otilde" => 'Õ', "Ouml" => 'Ö', "Oslash" => 'Ø', "Ugrave" => 'Ù',
+        "Uacute" => 'Ú', "Ucirc" => 'Û', "Uuml" => 'Ü', "Yacute" => 'Ý', "THORN" => 'Þ',
+        "szlig" => 'ß', "agrave" => 'à', "aacute" => 'á', "acirc" => 'â', "atilde" => 'ã',
+        "auml" => 'ä', "aring" => 'å', "aelig" => 'æ', "ccedil" => 'ç', "egrave" => 'è',
+        "eacute" => 'é', "ecirc" => 'ê', "euml" => 'ë', "igrave" => 'ì', "iacute" => 'í',
+        "icirc" => 'î', "iuml" => 'ï', "eth" => 'ð', "ntilde" => 'ñ', "ograve" => 'ò',
+        "oacute" => 'ó', "ocirc" => 'ô', "otilde" => 'õ', "ouml" => 'ö', "oslash" => 'ø',
+        "ugrave" => 'ù', "uacute" => 'ú', "ucirc" => 'û', "uuml" => 'ü', "yacute" => 'ý',
+        "thorn" => 'þ', "yuml" => 'ÿ', "OElig" => 'Œ', "oelig" => 'œ', "Scaron" => 'Š',
+        "scaron" => 'š', "Yuml" => 'Ÿ',
+        _ => return None,
+    };
+    Some(c)
+}
+
+/// Decode the character references (`&amp;`, `&#233;`, `&#xE9;`, ...) in `text`.
+/// Unknown or malformed references are kept as they are, like browsers do.
+pub fn decode_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = rest[1..].find(|c: char| !c.is_ascii_alphanumeric() && c != '#').map(|end| end + 1).unwrap_or(rest.len());
+        let reference = &rest[1..end];
+        let decoded = if let Some(number) = reference.strip_prefix('#') {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => number.parse::<u32>().ok(),
+            };
+            code.map(|code| char::from_u32(code).unwrap_or('\u{fffd}'))
+        } else {
+            named_entity(reference)
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end..];
+                if let Some(after) = rest.strip_prefix(';') {
+                    rest = after;
+                }
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> Vec<&str> {
+        text.split_whitespace().collect()
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(decode_entities("Caf&eacute; &amp; cr&#232;me &#xE9;t&#XE9; &lt;b&gt;"), "Café & crème été <b>");
+        assert_eq!(decode_entities("&nbsp;&hellip;&euro;5"), " …€5");
+    }
+
+    #[test]
+    fn unknown_and_malformed_entities_are_kept() {
+        assert_eq!(decode_entities("AT&T &bogus; & &#; &#xZZ;"), "AT&T &bogus; & &#; &#xZZ;");
+        assert_eq!(decode_entities("&#1114112;"), "\u{fffd}");
+    }
+
+    #[test]
+    fn scripts_styles_and_comments_are_skipped() {
+        let document = extract("<p>before</p><script>var x = '</p>';</script><style>p { color: red }</style><!-- hidden -->\
+            <SCRIPT type=\"module\">alert(1)</SCRIPT><svg><text>drawing</text></svg><p>after</p>");
+        assert_eq!(words(&document.text), ["before", "after"]);
+    }
+
+    #[test]
+    fn closing_tags_are_found_whatever_their_case() {
+        assert_eq!(find_closing_tag("x = a </b; </ScRiPt>", "script"), Some(11));
+        assert_eq!(find_closing_tag("é</svg", "svg"), Some(2));
+        assert_eq!(find_closing_tag("</scrip", "script"), None);
+        assert_eq!(find_closing_tag("no end", "style"), None);
+    }
+
+    #[test]
+    fn inline_tags_do_not_split_words() {
+        let document = extract("<p>foo<b>bar</b></p><div>one</div><div>two</div>");
+        assert_eq!(words(&document.text), ["foobar", "one", "two"]);
+    }
+
+    #[test]
+    fn title_headings_alt_and_lang() {
+        let document = extract("<!DOCTYPE html><html lang=\"de-AT\"><head><title> Fish &amp;\n Chips </title></head>\
+            <body><h1>Big <em>news</em></h1><img alt=\"a &quot;cat&quot;\" src=x.png> 1 < 2</body></html>");
+        assert_eq!(document.fields, [("title".to_string(), "Fish & Chips".to_string()), ("heading".to_string(), "Big news".to_string())]);
+        assert_eq!(document.metadata.get("title").map(String::as_str), Some("Fish & Chips"));
+        assert_eq!(document.metadata.get("lang").map(String::as_str), Some("de"));
+        assert_eq!(words(&document.text), ["Fish", "&", "Chips", "Big", "news", "a", "\"cat\"", "1", "<", "2"]);
+    }
+
+    #[test]
+    fn declared_charset_is_found() {
+        assert_eq!(declared_charset(b"<meta charset=\"windows-1252\">"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(declared_charset(b"<meta http-equiv=Content-Type content='text/html; charset=ISO-8859-1'>"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(declared_charset(b"<meta charset=utf-16>"), Some(UTF_8));
+        assert_eq!(declared_charset(b"<p>no declaration</p>"), None);
+    }
+}
@@ -0,0 +1,220 @@
+use std::path::Path;
+
+use crate::config::Limits;
+use super::encoding;
+use super::{DocumentParser, ParsedDocument};
+
+/// Special characters `\(xx` and `\[xx]` of troff that are worth keeping
+const SPECIAL_CHARACTERS: &[(&str, &str)] = &[
+    ("em", "—"), ("en", "–"), ("hy", "-"), ("mi", "-"), ("bu", "•"), ("aq", "'"), ("dq", "\""),
+    ("lq", "“"), ("rq", "”"), ("oq", "‘"), ("cq", "’"), ("co", "©"), ("rg", "®"), ("tm", "™"),
+    ("de", "°"), ("ti", "~"), ("ha", "^"), ("rs", "\\"), ("sl", "/"), ("ba", "|"), ("or", "|"),
+    ("lt", "<"), ("gt", ">"), ("<=", "≤"), (">=", "≥"), ("->", "→"), ("<-", "←"), ("mu", "×"),
+];
+
+/// Parser for manual pages written with the man or mdoc macros of troff, like the ones in /usr/share/man.
+/// Gzipped pages are decompressed on the way, see `archive`.
+pub struct ManParser;
+
+impl DocumentParser for ManParser {
+    fn name(&self) -> &'static str {
+        "man"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["1", "2", "3", "4", "5", "6", "7", "8", "9", "man"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/troff"]
+    }
+
+    fn parse(&self, _file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let (source, encoding) = encoding::decode(bytes, None);
+        let mut document = ParsedDocument::default();
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        let mut section = String::new();
+        // The line of the NAME section, like `ls \- list directory contents`
+        let mut name_line = None::<String>;
+        let mut page_title = None::<String>;
+        for line in source.lines() {
+            let text = match line.strip_prefix(['.', '\'']) {
+                Some(request) => {
+                    let request = request.trim_start();
+                    let (macro_name, arguments) = request.split_once(char::is_whitespace).unwrap_or((request, ""));
+                    let arguments = split_arguments(arguments);
+                    match macro_name {
+                        // .TH LS 1 "date" "source" "manual" and .Dt LS 1 of mdoc
+                        "TH" | "Dt" => {
+                            if let (Some(title), Some(number)) = (arguments.first(), arguments.get(1)) {
+                                page_title = Some(format!("{}({})", unescape(title).to_lowercase(), unescape(number)));
+                                document.metadata.insert("section".to_string(), unescape(number));
+                            }
+                            continue;
+                        }
+                        "SH" | "SS" | "Sh" | "Ss" => {
+                            let heading = unescape(&arguments.join(" "));
+                            if macro_name.eq_ignore_ascii_case("SH") {
+                                section = heading.to_uppercase();
+                            }
+                            document.fields.push(("heading".to_string(), heading.clone()));
+                            document.text.push_str(&format!("\n{heading}\n\n"));
+                            continue;
+                        }
+                        // Alternating fonts glue their arguments together: .BR ls (1) is `ls(1)`
+                        "BR" | "RB" | "BI" | "IB" | "IR" | "RI" => arguments.iter().map(|argument| unescape(argument)).collect(),
+                        // The tag of an indented paragraph, without the indentation that follows
+                        "IP" => arguments.first().map(|tag| unescape(tag)).unwrap_or_default(),
+                        "B" | "I" | "SM" | "SB" | "Nm" | "Nd" | "Fl" | "Ar" | "Cm" | "Pa" | "Xr" | "Em" | "Sy" | "Dl" => {
+                            let mut text = arguments.iter().map(|argument| unescape(argument)).collect::<Vec<_>>().join(" ");
+                            if macro_name == "Nd" {
+                                text = format!("- {text}");
+                            }
+                            text
+                        }
+                        "PP" | "LP" | "P" | "Pp" | "br" | "sp" => "\n".to_string(),
+                        _ => continue,
+                    }
+                }
+                None => unescape(line),
+            };
+            if section == "NAME" && !text.trim().is_empty() {
+                let name_line = name_line.get_or_insert_with(String::new);
+                name_line.push(' ');
+                name_line.push_str(text.trim());
+            }
+            document.text.push_str(&text);
+            document.text.push('\n');
+        }
+        let title = name_line.map(|line| line.split_whitespace().collect::<Vec<_>>().join(" ")).or(page_title);
+        if let Some(title) = title {
+            document.fields.push(("title".to_string(), title.clone()));
+            document.metadata.insert("title".to_string(), title);
+        }
+        Ok(document)
+    }
+}
+
+/// Arguments of a macro, which are separated by spaces unless they are within double quotes
+fn split_arguments(arguments: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut chars = arguments.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut argument = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        // A doubled quote is a quote
+                        '"' if chars.peek() == Some(&'"') => {
+                            chars.next();
+                            argument.push('"');
+                        }
+                        '"' => break,
+                        c => argument.push(c),
+                    }
+                }
+                result.push(argument);
+            }
+            c => {
+                let mut argument = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    argument.push(c);
+                }
+                result.push(argument);
+            }
+        }
+    }
+    result
+}
+
+/// Turn the escapes of troff into the text they stand for, dropping the changes of fonts and sizes
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            break;
+        };
+        match escape {
+            // A comment runs to the end of the line
+            '"' => break,
+            '\\' | 'e' => result.push('\\'),
+            '-' => result.push('-'),
+            ' ' | '~' | '0' => result.push(' '),
+            '(' => {
+                let name = chars.by_ref().take(2).collect::<String>();
+                result.push_str(special_character(&name));
+            }
+            '[' => {
+                let name = chars.by_ref().take_while(|c| *c != ']').collect::<String>();
+                result.push_str(special_character(&name));
+            }
+            // Fonts \fB, strings \*x and number registers \nx take a name: one character,
+            // two after `(` or any number between brackets
+            'f' | '*' | 'n' | 'F' | 'm' | 'g' | 'k' => {
+                match chars.next() {
+                    Some('(') => {
+                        chars.next();
+                        chars.next();
+                    }
+                    Some('[') => while chars.next().is_some_and(|c| c != ']') {},
+                    _ => {}
+                }
+            }
+            // The size \s+2, \s-1 or \s0
+            's' => {
+                chars.next_if(|c| matches!(c, '+' | '-'));
+                while chars.next_if(char::is_ascii_digit).is_some() {}
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+fn special_character(name: &str) -> &'static str {
+    SPECIAL_CHARACTERS.iter().find(|(special, _)| *special == name).map_or("", |(_, character)| character)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::package::tests::NO_LIMITS;
+    use super::*;
+
+    fn parse(source: &str) -> ParsedDocument {
+        ManParser.parse(Path::new("ls.1"), source.as_bytes(), NO_LIMITS).unwrap()
+    }
+
+    fn fields(document: &ParsedDocument) -> Vec<(&str, &str)> {
+        document.fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect()
+    }
+
+    #[test]
+    fn the_name_section_is_the_title() {
+        let document = parse(".\\\" Generated by hand\n.TH LS 1 \"March 2024\" \"GNU coreutils\" \"User Commands\"\n.SH NAME\nls \\- list directory contents\n.SH \"SEE ALSO\"\n.BR dir (1)\n");
+        assert_eq!(fields(&document), [("heading", "NAME"), ("heading", "SEE ALSO"), ("title", "ls - list directory contents")]);
+        assert_eq!(document.metadata.get("section").map(String::as_str), Some("1"));
+        assert!(document.text.contains("\nSEE ALSO\n\ndir(1)\n"));
+        assert!(!document.text.contains("Generated"));
+    }
+
+    #[test]
+    fn the_page_title_stands_in_for_a_missing_name_section() {
+        let document = parse(".TH PRINTF 3 2024-01-01\n.SH SYNOPSIS\n.B printf\n");
+        assert_eq!(document.metadata.get("title").map(String::as_str), Some("printf(3)"));
+        assert_eq!(document.metadata.get("section").map(String::as_str), Some("3"));
+    }
+
+    #[test]
+    fn escapes_are_turned_into_text() {
+        assert_eq!(unescape(r"\fBbold\fR and \f(CWcode\f[] \(em \[co] \e \- \*(lqquoted\*R"), "bold and code — © \\ - quoted");
+        assert_eq!(unescape(r#"\s+2big\s0 small\ space \"comment"#), "big small space ");
+        assert_eq!(split_arguments(r##"one "two ""three""" four"##), ["one", "two \"three\"", "four"]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::Path;
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag, TagEnd};
+use serde_yaml::Value;
+
+use crate::config::Limits;
+use crate::lang::Language;
+use super::{encoding, html};
+use super::{DocumentParser, ParsedDocument};
+
+/// Parser for CommonMark documents. Headings and the title and tags of the YAML front matter
+/// become fields, and the URLs of links and images are left out of the text.
+pub struct MarkdownParser {
+    /// Whether the content of fenced and indented code blocks is indexed with the text
+    pub code_blocks: bool,
+}
+
+impl Default for MarkdownParser {
+    fn default() -> Self {
+        Self { code_blocks: true }
+    }
+}
+
+impl DocumentParser for MarkdownParser {
+    fn name(&self) -> &'static str {
+        "markdown"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["md", "markdown", "mdown", "mkd"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/markdown"]
+    }
+
+    fn parse(&self, _file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let (markdown, encoding) = encoding::decode(bytes, None);
+        let mut document = self.extract(&markdown);
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        Ok(document)
+    }
+}
+
+impl MarkdownParser {
+    fn extract(&self, markdown: &str) -> ParsedDocument {
+        let options = Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES | Options::ENABLE_STRIKETHROUGH
+            | Options::ENABLE_TASKLISTS | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+        let mut document = ParsedDocument::default();
+        let mut heading = None::<String>;
+        let mut in_front_matter = false;
+        let mut in_code_block = false;
+        // Autolinks like <https://example.com> have their URL for text
+        let mut in_autolink = false;
+        for event in Parser::new_ext(markdown, options) {
+            let text = match event {
+                Event::Start(Tag::Heading { .. }) => {
+                    heading = Some(String::new());
+                    continue;
+                }
+                Event::End(TagEnd::Heading(_)) => {
+                    if let Some(heading) = heading.take().filter(|heading| !heading.trim().is_empty()) {
+                        document.fields.push(("heading".to_string(), heading.trim().to_string()));
+                    }
+                    document.text.push('\n');
+                    continue;
+                }
+                Event::Start(Tag::MetadataBlock(_)) => {
+                    in_front_matter = true;
+                    continue;
+                }
+                Event::End(TagEnd::MetadataBlock(_)) => {
+                    in_front_matter = false;
+                    continue;
+                }
+                Event::Start(Tag::CodeBlock(_)) => {
+                    in_code_block = true;
+                    continue;
+                }
+                Event::End(TagEnd::CodeBlock) => {
+                    in_code_block = false;
+                    document.text.push('\n');
+                    continue;
+                }
+                Event::Start(Tag::Link { link_type: LinkType::Autolink | LinkType::Email, .. }) => {
+                    in_autolink = true;
+                    continue;
+                }
+                Event::End(TagEnd::Link) => {
+                    in_autolink = false;
+                    continue;
+                }
+                Event::End(TagEnd::Paragraph | TagEnd::Item | TagEnd::BlockQuote(_) | TagEnd::TableCell | TagEnd::FootnoteDefinition) => {
+                    document.text.push('\n');
+                    continue;
+                }
+                Event::Text(text) if in_front_matter => {
+                    front_matter(&text, &mut document);
+                    continue;
+                }
+                Event::Text(_) if in_code_block && !self.code_blocks => continue,
+                Event::Text(_) | Event::Code(_) if in_autolink => continue,
+                Event::Text(text) | Event::Code(text) => text.into_string(),
+                Event::Html(html) | Event::InlineHtml(html) => html::extract(&html).text,
+                Event::SoftBreak | Event::HardBreak => " ".to_string(),
+                _ => continue,
+            };
+            if let Some(heading) = heading.as_mut() {
+                heading.push_str(&text);
+            }
+            document.text.push_str(&text);
+        }
+        document
+    }
+}
+
+/// Title, tags, author, date and language from the YAML front matter
+fn front_matter(yaml: &str, document: &mut ParsedDocument) {
+    let Ok(Value::Mapping(mapping)) = serde_yaml::from_str::<Value>(yaml) else {
+        return;
+    };
+    for (key, value) in mapping {
+        let (Some(key), Some(values)) = (key.as_str(), strings(value)) else {
+            continue;
+        };
+        match key.to_lowercase().as_str() {
+            "title" | "author" => {
+                let key = key.to_lowercase();
+                let value = values.join(", ");
+                document.fields.push((key.clone(), value.clone()));
+                document.metadata.insert(key, value);
+            }
+            "tags" | "keywords" | "categories" => {
+                let tags = values.iter()
+                    .flat_map(|tags| tags.split(','))
+                    .map(|tag| tag.trim().to_string())
+                    .filter(|tag| !tag.is_empty())
+                    .collect::<Vec<_>>();
+                document.fields.extend(tags.iter().map(|tag| ("tag".to_string(), tag.clone())));
+                document.metadata.insert("tags".to_string(), tags.join(", "));
+            }
+            "date" => {
+                document.metadata.insert("date".to_string(), values.join(", "));
+            }
+            "lang" | "language" => {
+                if let Some(language) = values.first().and_then(|tag| Language::from_tag(tag)) {
+                    document.metadata.insert("lang".to_string(), language.code().to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The scalars of a front matter value, which can be a single one or a list of them
+fn strings(value: Value) -> Option<Vec<String>> {
+    let scalar = |value: Value| match value {
+        Value::String(string) => Some(string),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    };
+    let values = match value {
+        Value::Sequence(sequence) => sequence.into_iter().filter_map(scalar).collect(),
+        value => vec![scalar(value)?],
+    };
+    let values = values.into_iter()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect::<Vec<_>>();
+    (!values.is_empty()).then_some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKDOWN: &str = "---
+title: Release notes
+author: [Ada, Bob]
+tags: rust, search
+date: 2024-05-13
+lang: de-DE
+---
+# Version *2.0*
+
+See [the docs](https://example.com/docs) or <https://example.com> and ![logo](logo.png).
+
+```rust
+fn main() {}
+```
+";
+
+    fn fields(document: &ParsedDocument) -> Vec<(&str, &str)> {
+        document.fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect()
+    }
+
+    #[test]
+    fn front_matter_and_headings_become_fields() {
+        let document = MarkdownParser::default().extract(MARKDOWN);
+        assert_eq!(fields(&document), [
+            ("title", "Release notes"), ("author", "Ada, Bob"), ("tag", "rust"), ("tag", "search"), ("heading", "Version 2.0"),
+        ]);
+        assert_eq!(document.metadata.get("tags").map(String::as_str), Some("rust, search"));
+        assert_eq!(document.metadata.get("date").map(String::as_str), Some("2024-05-13"));
+        assert_eq!(document.metadata.get("lang").map(String::as_str), Some("de"));
+        assert!(!document.text.contains("Release notes"));
+    }
+
+    #[test]
+    fn urls_are_left_out_of_the_text() {
+        let document = MarkdownParser::default().extract(MARKDOWN);
+        assert!(document.text.contains("See the docs or  and logo."), "{}", document.text);
+        assert!(!document.text.contains("example.com"));
+        assert!(!document.text.contains("logo.png"));
+    }
+
+    #[test]
+    fn code_blocks_can_be_left_out() {
+        assert!(MarkdownParser::default().extract(MARKDOWN).text.contains("fn main() {}"));
+        let document = MarkdownParser { code_blocks: false }.extract(MARKDOWN);
+        assert!(!document.text.contains("fn main"));
+        assert!(document.text.contains("Version 2.0"));
+    }
+}
@@ -0,0 +1,376 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Limits;
+use crate::model::{self, Fields, Metadata, Terms};
+
+mod archive;
+mod code;
+mod data;
+mod email;
+mod encoding;
+mod epub;
+mod external;
+mod html;
+mod man;
+mod markdown;
+mod odf;
+mod office;
+mod package;
+mod pdf;
+mod rtf;
+mod sniff;
+mod txt;
+mod xml;
+
+use archive::Archive;
+use sniff::Sniffed;
+
+pub use archive::extract_member;
+pub use code::CodeParser;
+pub use data::{CsvParser, StructuredDataParser};
+pub use email::EmailParser;
+pub use epub::EpubParser;
+pub use external::ExternalParser;
+pub use html::HtmlParser;
+pub use man::ManParser;
+pub use markdown::MarkdownParser;
+pub use odf::OdfParser;
+pub use office::OfficeParser;
+pub use pdf::PdfParser;
+pub use rtf::RtfParser;
+pub use txt::TxtParser;
+pub use xml::XmlParser;
+
+/// Text extracted from a file, ready to be indexed
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ParsedDocument {
+    pub text: String,
+    pub fields: Fields,
+    pub metadata: Metadata,
+    /// Sub-documents indexed on their own under `{path}{name}`, like the chapters `#chapter=N` of a book
+    /// or the members `!/member` of an archive
+    pub parts: Vec<(String, ParsedDocument)>,
+    /// Terms of the text counted as it was read, by the parsers that stream it rather than keep it in `text`
+    pub terms: Option<Terms>,
+    /// How many parts of the file could not be parsed, like the broken members of an archive
+    pub failures: usize,
+}
+
+pub trait DocumentParser: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Lowercase file extensions (without the dot) this parser understands
+    fn extensions(&self) -> &'static [&'static str];
+    fn mime_types(&self) -> &'static [&'static str];
+    /// Whether the files with the extensions of the parser go to it whatever their content looks like
+    fn overrides_sniffing(&self) -> bool {
+        false
+    }
+    /// Extract the text of the file at `file_path`, whose whole content is `bytes`. The formats that are
+    /// archives themselves decompress no more than `max_file_size` bytes of the `limits` of the file.
+    fn parse(&self, file_path: &Path, bytes: &[u8], limits: Limits) -> Result<ParsedDocument, ()>;
+    /// Extract the text of the file at `file_path` as it is read. Parsers that can do so without
+    /// the whole content in memory override it, the others read it all and `parse` it. The text
+    /// beyond `max_text` bytes of the `limits` is not needed, as it is cut off anyway.
+    fn parse_reader(&self, file_path: &Path, reader: &mut dyn Read, limits: Limits) -> Result<ParsedDocument, ()> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|err| {
+            eprintln!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
+        })?;
+        self.parse(file_path, &bytes, limits)
+    }
+}
+
+/// Set of the parsers `add_folder_to_model` picks from for every file it indexes
+pub struct ParserRegistry {
+    parsers: Vec<Box<dyn DocumentParser>>,
+}
+
+impl Default for ParserRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(XmlParser));
+        registry.register(Box::new(TxtParser));
+        registry.register(Box::new(PdfParser));
+        registry.register(Box::new(HtmlParser));
+        registry.register(Box::new(OfficeParser));
+        registry.register(Box::new(OdfParser));
+        registry.register(Box::new(EpubParser));
+        registry.register(Box::new(EmailParser));
+        registry.register(Box::new(MarkdownParser::default()));
+        for parser in CodeParser::all() {
+            registry.register(Box::new(parser));
+        }
+        registry.register(Box::new(CsvParser::default()));
+        registry.register(Box::new(StructuredDataParser));
+        registry.register(Box::new(RtfParser));
+        registry.register(Box::new(ManParser));
+        registry
+    }
+}
+
+impl ParserRegistry {
+    pub fn empty() -> Self {
+        Self { parsers: Vec::new() }
+    }
+
+    /// Parsers registered later take precedence over the earlier ones for the same extension or MIME type
+    pub fn register(&mut self, parser: Box<dyn DocumentParser>) {
+        self.parsers.push(parser);
+    }
+
+    pub fn by_extension(&self, extension: &str) -> Option<&dyn DocumentParser> {
+        let extension = extension.to_lowercase();
+        self.parsers.iter().rev()
+            .find(|parser| parser.extensions().contains(&extension.as_str()))
+            .map(|parser| parser.as_ref())
+    }
+
+    pub fn by_mime_type(&self, mime_type: &str) -> Option<&dyn DocumentParser> {
+        self.parsers.iter().rev()
+            .find(|parser| supports(parser.as_ref(), mime_type))
+            .map(|parser| parser.as_ref())
+    }
+
+    /// Pick the parser for the file together with the MIME type it is going to be parsed as.
+    /// The extension decides unless it is missing or the content clearly says otherwise.
+    pub fn find(&self, file_path: &Path, sniffed: Sniffed) -> Result<(&dyn DocumentParser, &'static str), ()> {
+        let by_extension = self.for_path(file_path);
+        let found = match (by_extension, sniffed) {
+            (Some(parser), _) if parser.overrides_sniffing() => Some((parser, parser.mime_types()[0])),
+            (Some(parser), Sniffed::Magic(mime_type)) if supports(parser, mime_type) => Some((parser, mime_type)),
+            (Some(parser), Sniffed::Text(mime_type)) if supports(parser, mime_type) => Some((parser, mime_type)),
+            (Some(parser), Sniffed::Text(_)) if handles_text(parser) => Some((parser, parser.mime_types()[0])),
+            (Some(parser), Sniffed::Binary) if !handles_text(parser) => Some((parser, parser.mime_types()[0])),
+            // The ZIP based formats are told apart by their extension when their content does not say
+            (Some(parser), Sniffed::Magic("application/zip")) if !handles_text(parser) => Some((parser, parser.mime_types()[0])),
+            (_, Sniffed::Magic(mime_type)) | (_, Sniffed::Text(mime_type)) => {
+                let parser = self.by_mime_type(mime_type);
+                if let (Some(by_extension), Some(_)) = (by_extension, parser) {
+                    println!("WARNING: {file_path} looks like {mime_type} rather than {name} as its extension says", file_path = file_path.display(), name = by_extension.name());
+                }
+                parser.map(|parser| (parser, mime_type))
+            }
+            (_, Sniffed::Binary) => None,
+        };
+        found.ok_or_else(|| {
+            match sniffed {
+                Sniffed::Magic(mime_type) | Sniffed::Text(mime_type) => println!("Skipping {file_path:?} because {mime_type} is not supported.", file_path = file_path),
+                Sniffed::Binary => println!("Skipping {file_path:?} because it is a binary file.", file_path = file_path),
+            }
+        })
+    }
+
+    /// Parse the file with the parser for its type and record that type in the `mime` metadata.
+    /// Only the beginning of the file is read to tell its type, the parser reads the rest.
+    /// The files that are too big or that no parser supports are skipped with `None`.
+    pub fn parse(&self, file_path: &Path, limits: Limits) -> Result<Option<ParsedDocument>, ()> {
+        let read_error = |err| {
+            eprintln!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
+        };
+        let mut file = File::open(file_path).map_err(read_error)?;
+        let size = file.metadata().map_err(read_error)?.len();
+        if size > limits.max_file_size {
+            println!("Skipping {file_path:?} because it is {size} bytes, more than the limit of {max} bytes.", max = limits.max_file_size);
+            return Ok(None);
+        }
+        let mut head = Vec::new();
+        file.by_ref().take(sniff::SNIFF_LEN as u64).read_to_end(&mut head).map_err(read_error)?;
+        let sniffed = sniff::sniff(&head);
+        let mut document = if self.archive(file_path, sniffed).is_some() {
+            file.read_to_end(&mut head).map_err(read_error)?;
+            match self.parse_bytes(file_path, &head, limits)? {
+                Some(document) => document,
+                None => return Ok(None),
+            }
+        } else {
+            let Ok((parser, mime_type)) = self.find(file_path, sniffed) else {
+                return Ok(None);
+            };
+            println!("Indexing {file_path:?} with {name} parser...", name = parser.name());
+            let mut document = parser.parse_reader(file_path, &mut head.as_slice().chain(file), limits)?;
+            document.metadata.entry("mime".to_string()).or_insert(mime_type.to_string());
+            document
+        };
+        if truncate(&mut document, limits.max_text) {
+            println!("WARNING: only the first {max} bytes of the text of {file_path:?} are indexed", max = limits.max_text);
+        }
+        Ok(Some(document))
+    }
+
+    /// Parse the content of a file, which may be the member of an archive. The members of
+    /// the archives end up in the parts of the document, within the `limits` of the file.
+    /// The content that no parser supports is skipped with `None`.
+    pub fn parse_bytes(&self, file_path: &Path, bytes: &[u8], limits: Limits) -> Result<Option<ParsedDocument>, ()> {
+        let sniffed = sniff::sniff(bytes);
+        if let Some(archive) = self.archive(file_path, sniffed) {
+            return archive::parse(self, file_path, bytes, archive, limits);
+        }
+        let Ok((parser, mime_type)) = self.find(file_path, sniffed) else {
+            return Ok(None);
+        };
+        println!("Indexing {file_path:?} with {name} parser...", name = parser.name());
+        let mut document = parser.parse(file_path, bytes, limits)?;
+        document.metadata.entry("mime".to_string()).or_insert(mime_type.to_string());
+        Ok(Some(document))
+    }
+
+    fn for_path(&self, file_path: &Path) -> Option<&dyn DocumentParser> {
+        file_path.extension().and_then(|extension| self.by_extension(&extension.to_string_lossy()))
+    }
+
+    /// Archives are only looked into when no parser claims the file for itself
+    fn archive(&self, file_path: &Path, sniffed: Sniffed) -> Option<Archive> {
+        let archive = match sniffed {
+            Sniffed::Magic("application/zip") => Archive::Zip,
+            Sniffed::Magic("application/x-tar") => Archive::Tar,
+            Sniffed::Magic("application/gzip") => Archive::Gzip,
+            _ => return None,
+        };
+        match self.for_path(file_path) {
+            Some(parser) if !handles_text(parser) || parser.overrides_sniffing() => None,
+            _ => Some(archive),
+        }
+    }
+}
+
+/// Cut the text of the document and of its parts, in this order, at `max_text` bytes in all,
+/// recording in the metadata of the document that it is `truncated` when any was cut
+fn truncate(document: &mut ParsedDocument, max_text: usize) -> bool {
+    let mut budget = max_text;
+    let truncated = cut_text(document, &mut budget);
+    if truncated {
+        document.metadata.insert("truncated".to_string(), "true".to_string());
+    }
+    truncated
+}
+
+/// Cut the text of the document and of its parts at what is left of the `budget`, taking what they keep out of it.
+/// The parts whose text is cut are recorded as `truncated`, and dropped when nothing of them is left.
+fn cut_text(document: &mut ParsedDocument, budget: &mut usize) -> bool {
+    let mut truncated = false;
+    if document.text.len() > *budget {
+        document.text.truncate(model::floor_char_boundary(&document.text, *budget));
+        truncated = true;
+    }
+    *budget -= document.text.len();
+    document.parts.retain_mut(|(_, part)| {
+        if !cut_text(part, budget) {
+            return true;
+        }
+        truncated = true;
+        part.metadata.insert("truncated".to_string(), "true".to_string());
+        !(part.text.is_empty() && part.fields.is_empty() && part.parts.is_empty())
+    });
+    truncated
+}
+
+fn supports(parser: &dyn DocumentParser, mime_type: &str) -> bool {
+    parser.mime_types().iter().any(|m| m.eq_ignore_ascii_case(mime_type))
+}
+
+/// Whether the parser reads some kind of text rather than a binary format
+fn handles_text(parser: &dyn DocumentParser) -> bool {
+    parser.mime_types().iter().any(|m| {
+        m.starts_with("text/") || m.starts_with("message/") || *m == email::MBOX
+            || m.ends_with("xml") || m.ends_with("json") || m.ends_with("yaml") || m.ends_with("toml")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(file_name: &str, sniffed: Sniffed) -> Option<(&'static str, &'static str)> {
+        static REGISTRY: std::sync::LazyLock<ParserRegistry> = std::sync::LazyLock::new(ParserRegistry::default);
+        REGISTRY.find(Path::new(file_name), sniffed).ok().map(|(parser, mime_type)| (parser.name(), mime_type))
+    }
+
+    #[test]
+    fn extension_decides_when_the_content_agrees() {
+        assert_eq!(found("notes.txt", Sniffed::Text("text/plain")), Some(("text", "text/plain")));
+        assert_eq!(found("paper.PDF", Sniffed::Magic("application/pdf")), Some(("pdf", "application/pdf")));
+        assert_eq!(found("report.docx", Sniffed::Magic("application/zip")), Some(("office", office::DOCX)));
+    }
+
+    #[test]
+    fn text_parsers_take_any_text_their_extension_claims() {
+        assert_eq!(found("data.csv", Sniffed::Text("text/plain")), Some(("csv", "text/csv")));
+        assert_eq!(found("page.html", Sniffed::Text("text/plain")), Some(("html", "text/html")));
+    }
+
+    #[test]
+    fn logs_are_streamed_as_plain_text_whatever_they_start_with() {
+        // Their files have no size limit, as only the parser of plain text reads them a chunk at a time
+        for mime_type in ["text/html", "application/xml", "message/rfc822", email::MBOX] {
+            assert_eq!(found("server.log", Sniffed::Text(mime_type)), Some(("text", "text/plain")), "{mime_type}");
+        }
+    }
+
+    #[test]
+    fn content_wins_when_it_clearly_says_otherwise() {
+        assert_eq!(found("notes.txt", Sniffed::Magic("application/pdf")), Some(("pdf", "application/pdf")));
+        assert_eq!(found("paper.pdf", Sniffed::Text("text/plain")), Some(("text", "text/plain")));
+        assert_eq!(found("README", Sniffed::Text("text/html")), Some(("html", "text/html")));
+    }
+
+    #[test]
+    fn binary_and_unknown_content_are_skipped() {
+        assert_eq!(found("notes.txt", Sniffed::Binary), None);
+        assert_eq!(found("picture.txt", Sniffed::Magic("image/png")), None);
+        assert_eq!(found("blob", Sniffed::Binary), None);
+    }
+
+    fn document(text: &str, parts: Vec<(&str, ParsedDocument)>) -> ParsedDocument {
+        let parts = parts.into_iter().map(|(name, part)| (name.to_string(), part)).collect();
+        ParsedDocument { text: text.to_string(), parts, ..Default::default() }
+    }
+
+    #[test]
+    fn truncation_shares_the_text_budget_across_parts() {
+        let chapter = document("cccc", vec![("#page=1", document("dddd", vec![]))]);
+        let mut book = document("aaaa", vec![("#chapter=1", document("bbbb", vec![])), ("#chapter=2", chapter)]);
+        assert!(truncate(&mut book, 10));
+        assert_eq!(book.metadata.get("truncated").map(String::as_str), Some("true"));
+        let texts = [&book.text, &book.parts[0].1.text, &book.parts[1].1.text];
+        assert_eq!(texts, ["aaaa", "bbbb", "cc"]);
+        let flagged = book.parts.iter().map(|(_, part)| part.metadata.contains_key("truncated")).collect::<Vec<_>>();
+        assert_eq!(flagged, [false, true]);
+        // The page cut down to nothing is not indexed at all
+        assert!(book.parts[1].1.parts.is_empty());
+
+        let mut short = document("aaaa", vec![("#chapter=1", document("bbbb", vec![]))]);
+        assert!(!truncate(&mut short, 8));
+        assert!(!short.metadata.contains_key("truncated"));
+    }
+
+    #[test]
+    fn truncation_keeps_whole_characters() {
+        let mut text = document("ééé", vec![]);
+        assert!(truncate(&mut text, 3));
+        assert_eq!(text.text, "é");
+    }
+
+    #[test]
+    fn archives_are_bounded_by_what_they_decompress() {
+        use std::io::{Cursor, Write};
+        use zip::write::SimpleFileOptions;
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(&[b'x'; 400]).unwrap();
+        }
+        let bytes = writer.finish().unwrap().into_inner();
+        let registry = ParserRegistry::default();
+        let members = |max_file_size| {
+            let limits = Limits { max_file_size, max_text: usize::MAX };
+            let document = registry.parse_bytes(Path::new("bundle.zip"), &bytes, limits).unwrap().unwrap();
+            document.parts.into_iter().map(|(name, _)| name).collect::<Vec<_>>()
+        };
+        assert_eq!(members(1200), ["!/a.txt", "!/b.txt", "!/c.txt"]);
+        assert_eq!(members(1000), ["!/a.txt", "!/b.txt"]);
+        assert_eq!(members(399), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,206 @@
+use std::path::Path;
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent;
+
+use crate::config::Limits;
+use crate::lang::Language;
+use super::package::Package;
+use super::{DocumentParser, ParsedDocument};
+
+pub const ODT: &str = "application/vnd.oasis.opendocument.text";
+pub const ODS: &str = "application/vnd.oasis.opendocument.spreadsheet";
+pub const ODP: &str = "application/vnd.oasis.opendocument.presentation";
+
+/// Longest run of spaces `<text:s text:c="N">` turns into
+const MAX_SPACES: usize = 1024;
+
+/// Parser for OpenDocument text documents, spreadsheets and presentations
+pub struct OdfParser;
+
+impl DocumentParser for OdfParser {
+    fn name(&self) -> &'static str {
+        "opendocument"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["odt", "ods", "odp"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &[ODT, ODS, ODP]
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], limits: Limits) -> Result<ParsedDocument, ()> {
+        let mut package = Package::open(file_path, bytes, limits)?;
+        let mut document = ParsedDocument::default();
+        parse_content(&mut package, &mut document)?;
+        if package.contains("meta.xml") {
+            parse_meta(&mut package, &mut document)?;
+        }
+        Ok(document)
+    }
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|attribute| attribute.name.local_name == name).map(|attribute| attribute.value.as_str())
+}
+
+/// Text of the paragraphs `<text:p>` and headings `<text:h>` of content.xml, which is where
+/// the cells of spreadsheets and the text boxes of presentations end up too
+fn parse_content(package: &mut Package, document: &mut ParsedDocument) -> Result<(), ()> {
+    let mut paragraph = String::new();
+    let mut depth = 0;
+    let mut heading = false;
+    let mut in_title_frame = false;
+    let mut spreadsheet = false;
+    package.read_xml("content.xml", |event| match event {
+        XmlEvent::StartElement { name, attributes, .. } => match name.local_name.as_str() {
+            "p" | "h" => {
+                depth += 1;
+                heading |= name.local_name == "h";
+            }
+            "s" => {
+                // The count comes from the file, and a run of spaces separates words no better than a few do
+                let count = attribute(&attributes, "c").and_then(|c| c.parse::<usize>().ok()).unwrap_or(1);
+                paragraph.extend(std::iter::repeat_n(' ', count.min(MAX_SPACES)));
+            }
+            "tab" | "line-break" => paragraph.push(' '),
+            "frame" => in_title_frame = attribute(&attributes, "class") == Some("title"),
+            "spreadsheet" => spreadsheet = true,
+            "table" if spreadsheet => {
+                if let Some(sheet) = attribute(&attributes, "name") {
+                    document.text.push_str(sheet);
+                    document.text.push('\n');
+                    document.fields.push(("sheet".to_string(), sheet.to_string()));
+                }
+            }
+            _ => {}
+        },
+        XmlEvent::Characters(chars) | XmlEvent::Whitespace(chars) if depth > 0 => paragraph.push_str(&chars),
+        XmlEvent::EndElement { name } => match name.local_name.as_str() {
+            "p" | "h" => {
+                depth -= 1;
+                // Paragraphs can nest (a note inside a paragraph), the outermost one takes it all
+                if depth == 0 {
+                    let line = paragraph.trim();
+                    if !line.is_empty() {
+                        document.text.push_str(line);
+                        document.text.push('\n');
+                        if heading || in_title_frame {
+                            document.fields.push(("heading".to_string(), line.to_string()));
+                        }
+                    }
+                    paragraph.clear();
+                    heading = false;
+                }
+            }
+            "frame" => in_title_frame = false,
+            _ => {}
+        },
+        _ => {}
+    })
+}
+
+/// Title, author, dates and language from meta.xml
+fn parse_meta(package: &mut Package, document: &mut ParsedDocument) -> Result<(), ()> {
+    let mut element = String::new();
+    let mut value = String::new();
+    let mut properties = Vec::new();
+    package.read_xml("meta.xml", |event| match event {
+        XmlEvent::StartElement { name, .. } => {
+            element = name.local_name;
+            value.clear();
+        }
+        XmlEvent::Characters(chars) => value.push_str(&chars),
+        XmlEvent::EndElement { .. } => {
+            let value = std::mem::take(&mut value);
+            if !value.trim().is_empty() {
+                properties.push((std::mem::take(&mut element), value.trim().to_string()));
+            }
+        }
+        _ => {}
+    })?;
+    for (element, value) in properties {
+        let (key, searchable) = match element.as_str() {
+            "title" => ("title", true),
+            "initial-creator" | "creator" => ("author", true),
+            "subject" => ("subject", true),
+            "keyword" => ("keywords", true),
+            "creation-date" => ("created", false),
+            "date" => ("modified", false),
+            "language" => {
+                if let Some(language) = Language::from_tag(&value) {
+                    document.metadata.insert("lang".to_string(), language.code().to_string());
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if searchable {
+            document.fields.push((key.to_string(), value.clone()));
+        }
+        // The initial creator is the author, the creator is whoever saved it last
+        if key == "author" && element == "creator" && document.metadata.contains_key(key) {
+            continue;
+        }
+        document.metadata.insert(key.to_string(), value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::package::tests::{zip, NO_LIMITS};
+    use super::*;
+
+    const NS: &str = r#"xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:meta="urn:oasis:names:tc:opendocument:xmlns:meta:1.0" xmlns:dc="http://purl.org/dc/elements/1.1/""#;
+
+    fn parse(file_name: &str, parts: &[(&str, String)]) -> ParsedDocument {
+        let parts = parts.iter().map(|(name, content)| (*name, content.as_bytes())).collect::<Vec<_>>();
+        OdfParser.parse(Path::new(file_name), &zip(&parts), NO_LIMITS).unwrap()
+    }
+
+    #[test]
+    fn text_documents_keep_headings_paragraphs_and_meta() {
+        let document = parse("letter.odt", &[
+            ("mimetype", ODT.to_string()),
+            ("content.xml", format!(r#"<office:document-content {NS}><office:body><office:text>
+                <text:h text:outline-level="1">Dear <text:span>reader</text:span></text:h>
+                <text:p>one<text:s/>two<text:tab/>three<text:line-break/>four</text:p>
+                <text:p>Note<text:note><text:note-body><text:p>inside</text:p></text:note-body></text:note></text:p>
+            </office:text></office:body></office:document-content>"#)),
+            ("meta.xml", format!(r#"<office:document-meta {NS}><office:meta>
+                <dc:title>A letter</dc:title><meta:initial-creator>Ada</meta:initial-creator><dc:creator>Bob</dc:creator>
+                <dc:language>fr-FR</dc:language><meta:creation-date>2024-01-02T03:04:05</meta:creation-date>
+            </office:meta></office:document-meta>"#)),
+        ]);
+        assert_eq!(document.text, "Dear reader\none two three four\nNoteinside\n");
+        let fields = document.fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<Vec<_>>();
+        assert_eq!(fields, [("heading", "Dear reader"), ("title", "A letter"), ("author", "Ada"), ("author", "Bob")]);
+        assert_eq!(document.metadata.get("author").map(String::as_str), Some("Ada"));
+        assert_eq!(document.metadata.get("lang").map(String::as_str), Some("fr"));
+        assert_eq!(document.metadata.get("created").map(String::as_str), Some("2024-01-02T03:04:05"));
+    }
+
+    #[test]
+    fn runs_of_spaces_are_capped() {
+        let document = parse("spaces.odt", &[
+            ("content.xml", format!(r#"<office:document-content {NS}><office:body><office:text>
+                <text:p>a<text:s text:c="3"/>b<text:s text:c="100000000"/>c</text:p>
+            </office:text></office:body></office:document-content>"#)),
+        ]);
+        assert_eq!(document.text, format!("a   b{}c\n", " ".repeat(MAX_SPACES)));
+    }
+
+    #[test]
+    fn spreadsheets_name_their_sheets() {
+        let document = parse("budget.ods", &[
+            ("content.xml", format!(r#"<office:document-content {NS}><office:body><office:spreadsheet>
+                <table:table table:name="Income"><table:table-row><table:table-cell><text:p>Salary</text:p></table:table-cell></table:table-row></table:table>
+                <table:table table:name="Costs"><table:table-row><table:table-cell><text:p>Rent</text:p></table:table-cell></table:table-row></table:table>
+            </office:spreadsheet></office:body></office:document-content>"#)),
+        ]);
+        assert_eq!(document.text, "Income\nSalary\nCosts\nRent\n");
+        assert_eq!(document.fields, [("sheet".to_string(), "Income".to_string()), ("sheet".to_string(), "Costs".to_string())]);
+    }
+}
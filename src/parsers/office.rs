@@ -0,0 +1,283 @@
+use std::path::Path;
+use xml::reader::XmlEvent;
+
+use crate::config::Limits;
+use super::package::Package;
+use super::{DocumentParser, ParsedDocument};
+
+pub const DOCX: &str = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+pub const XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+pub const PPTX: &str = "application/vnd.openxmlformats-officedocument.presentationml.presentation";
+
+/// Parser for Office Open XML documents: Word, Excel and PowerPoint files
+pub struct OfficeParser;
+
+impl DocumentParser for OfficeParser {
+    fn name(&self) -> &'static str {
+        "office"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["docx", "xlsx", "pptx"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &[DOCX, XLSX, PPTX]
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], limits: Limits) -> Result<ParsedDocument, ()> {
+        let mut package = Package::open(file_path, bytes, limits)?;
+        let mut document = ParsedDocument::default();
+        if package.contains("word/document.xml") {
+            parse_word(&mut package, &mut document)?;
+        } else if package.contains("xl/workbook.xml") {
+            parse_excel(&mut package, &mut document)?;
+        } else if package.contains("ppt/presentation.xml") {
+            parse_powerpoint(&mut package, &mut document)?;
+        } else {
+            eprintln!("ERROR: {file_path} is not an Office Open XML document", file_path = file_path.display());
+            return Err(());
+        }
+        if package.contains("docProps/core.xml") {
+            parse_core_properties(&mut package, &mut document)?;
+        }
+        Ok(document)
+    }
+}
+
+fn attribute<'a>(attributes: &'a [xml::attribute::OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes.iter().find(|attribute| attribute.name.local_name == name).map(|attribute| attribute.value.as_str())
+}
+
+/// Text of the `<t>` runs of a part, one line per paragraph `<p>`.
+/// Returns the paragraphs that are headings (or slide titles) according to `is_heading`,
+/// which gets to see every start element of the paragraph.
+fn parse_paragraphs(package: &mut Package, name: &str, text: &mut String, mut is_heading: impl FnMut(&str, &[xml::attribute::OwnedAttribute]) -> bool) -> Result<Vec<String>, ()> {
+    let mut headings = Vec::new();
+    let mut paragraph = String::new();
+    let mut heading = false;
+    let mut in_text = false;
+    package.read_xml(name, |event| match event {
+        XmlEvent::StartElement { name, attributes, .. } => {
+            match name.local_name.as_str() {
+                "t" => in_text = true,
+                "tab" | "br" | "cr" => paragraph.push(' '),
+                _ => {}
+            }
+            heading |= is_heading(&name.local_name, &attributes);
+        }
+        XmlEvent::Characters(chars) | XmlEvent::Whitespace(chars) if in_text => paragraph.push_str(&chars),
+        XmlEvent::EndElement { name } => match name.local_name.as_str() {
+            "t" => in_text = false,
+            "p" => {
+                let line = paragraph.trim();
+                if !line.is_empty() {
+                    text.push_str(line);
+                    text.push('\n');
+                    if heading {
+                        headings.push(line.to_string());
+                    }
+                }
+                paragraph.clear();
+                heading = false;
+            }
+            _ => {}
+        },
+        _ => {}
+    })?;
+    Ok(headings)
+}
+
+fn parse_word(package: &mut Package, document: &mut ParsedDocument) -> Result<(), ()> {
+    let mut parts = vec!["word/document.xml".to_string()];
+    parts.extend(package.numbered_parts("word/header", ".xml"));
+    parts.extend(package.numbered_parts("word/footer", ".xml"));
+    parts.extend(["word/footnotes.xml", "word/endnotes.xml"].into_iter().filter(|name| package.contains(name)).map(String::from));
+    for part in parts {
+        let headings = parse_paragraphs(package, &part, &mut document.text, |name, attributes| {
+            name == "pStyle" && attribute(attributes, "val")
+                .map(|style| style.starts_with("Heading") || style == "Title")
+                .unwrap_or(false)
+        })?;
+        document.fields.extend(headings.into_iter().map(|heading| ("heading".to_string(), heading)));
+    }
+    Ok(())
+}
+
+fn parse_excel(package: &mut Package, document: &mut ParsedDocument) -> Result<(), ()> {
+    package.read_xml("xl/workbook.xml", |event| {
+        if let XmlEvent::StartElement { name, attributes, .. } = event {
+            if name.local_name == "sheet" {
+                if let Some(sheet) = attribute(&attributes, "name") {
+                    document.text.push_str(sheet);
+                    document.text.push('\n');
+                    document.fields.push(("sheet".to_string(), sheet.to_string()));
+                }
+            }
+        }
+    })?;
+    // Cells share their strings through sharedStrings.xml, apart from the rare inline ones
+    let mut parts = Vec::new();
+    if package.contains("xl/sharedStrings.xml") {
+        parts.push("xl/sharedStrings.xml".to_string());
+    }
+    parts.extend(package.numbered_parts("xl/worksheets/sheet", ".xml"));
+    for part in parts {
+        let mut cell = String::new();
+        let mut in_text = false;
+        package.read_xml(&part, |event| match event {
+            XmlEvent::StartElement { name, .. } if name.local_name == "t" => in_text = true,
+            XmlEvent::Characters(chars) | XmlEvent::Whitespace(chars) if in_text => cell.push_str(&chars),
+            XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                "t" => in_text = false,
+                "si" | "is" => {
+                    let value = cell.trim();
+                    if !value.is_empty() {
+                        document.text.push_str(value);
+                        document.text.push('\n');
+                    }
+                    cell.clear();
+                }
+                _ => {}
+            },
+            _ => {}
+        })?;
+    }
+    Ok(())
+}
+
+fn parse_powerpoint(package: &mut Package, document: &mut ParsedDocument) -> Result<(), ()> {
+    for part in package.numbered_parts("ppt/slides/slide", ".xml") {
+        let headings = parse_paragraphs(package, &part, &mut document.text, title_placeholder())?;
+        document.fields.extend(headings.into_iter().map(|heading| ("heading".to_string(), heading)));
+    }
+    for part in package.numbered_parts("ppt/notesSlides/notesSlide", ".xml") {
+        parse_paragraphs(package, &part, &mut document.text, |_, _| false)?;
+    }
+    Ok(())
+}
+
+/// Recognizes the paragraphs of the shapes that hold the title of a slide
+fn title_placeholder() -> impl FnMut(&str, &[xml::attribute::OwnedAttribute]) -> bool {
+    let mut in_title = false;
+    move |name, attributes| {
+        match name {
+            "sp" => in_title = false,
+            "ph" => in_title = matches!(attribute(attributes, "type"), Some("title" | "ctrTitle")),
+            _ => {}
+        }
+        in_title
+    }
+}
+
+/// Title, author and dates from the Dublin Core properties of the package
+fn parse_core_properties(package: &mut Package, document: &mut ParsedDocument) -> Result<(), ()> {
+    let mut element = String::new();
+    let mut value = String::new();
+    let mut properties = Vec::new();
+    package.read_xml("docProps/core.xml", |event| match event {
+        XmlEvent::StartElement { name, .. } => {
+            element = name.local_name;
+            value.clear();
+        }
+        XmlEvent::Characters(chars) => value.push_str(&chars),
+        XmlEvent::EndElement { .. } => {
+            let value = std::mem::take(&mut value);
+            if !value.trim().is_empty() {
+                properties.push((std::mem::take(&mut element), value.trim().to_string()));
+            }
+        }
+        _ => {}
+    })?;
+    for (element, value) in properties {
+        let (key, searchable) = match element.as_str() {
+            "title" => ("title", true),
+            "creator" => ("author", true),
+            "subject" => ("subject", true),
+            "keywords" => ("keywords", true),
+            "created" => ("created", false),
+            "modified" => ("modified", false),
+            _ => continue,
+        };
+        if searchable {
+            document.fields.push((key.to_string(), value.clone()));
+        }
+        document.metadata.insert(key.to_string(), value);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::package::tests::{zip, NO_LIMITS};
+    use super::*;
+
+    const W: &str = r#"xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main""#;
+    const A: &str = r#"xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main" xmlns:p="http://schemas.openxmlformats.org/presentationml/2006/main""#;
+
+    fn parse(file_name: &str, parts: &[(&str, String)]) -> ParsedDocument {
+        let parts = parts.iter().map(|(name, content)| (*name, content.as_bytes())).collect::<Vec<_>>();
+        OfficeParser.parse(Path::new(file_name), &zip(&parts), NO_LIMITS).unwrap()
+    }
+
+    fn fields(document: &ParsedDocument) -> Vec<(&str, &str)> {
+        document.fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect()
+    }
+
+    #[test]
+    fn word_documents_keep_paragraphs_headings_and_properties() {
+        let document = parse("report.docx", &[
+            ("word/document.xml", format!(r#"<w:document {W}><w:body>
+                <w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>Introduction</w:t></w:r></w:p>
+                <w:p><w:r><w:t xml:space="preserve">Hello </w:t></w:r><w:r><w:t>world</w:t><w:tab/><w:t>again</w:t></w:r></w:p>
+                <w:p></w:p>
+            </w:body></w:document>"#)),
+            ("word/footer1.xml", format!(r#"<w:ftr {W}><w:p><w:r><w:t>Page footer</w:t></w:r></w:p></w:ftr>"#)),
+            ("docProps/core.xml", r#"<cp:coreProperties xmlns:cp="http://schemas.openxmlformats.org/package/2006/metadata/core-properties"
+                xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:dcterms="http://purl.org/dc/terms/">
+                <dc:title>Quarterly Report</dc:title><dc:creator>Ada Lovelace</dc:creator><dcterms:created>2024-05-13T10:22:01Z</dcterms:created>
+            </cp:coreProperties>"#.to_string()),
+        ]);
+        assert_eq!(document.text, "Introduction\nHello world again\nPage footer\n");
+        assert_eq!(fields(&document), [("heading", "Introduction"), ("title", "Quarterly Report"), ("author", "Ada Lovelace")]);
+        assert_eq!(document.metadata.get("title").map(String::as_str), Some("Quarterly Report"));
+        assert_eq!(document.metadata.get("created").map(String::as_str), Some("2024-05-13T10:22:01Z"));
+    }
+
+    #[test]
+    fn excel_workbooks_keep_their_sheets_in_order() {
+        let ns = r#"xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main""#;
+        let sheet = |text: &str| format!(r#"<worksheet {ns}><sheetData><row><c t="inlineStr"><is><t>{text}</t></is></c></row></sheetData></worksheet>"#);
+        let document = parse("budget.xlsx", &[
+            ("xl/workbook.xml", format!(r#"<workbook {ns}><sheets><sheet name="Budget"/><sheet name="Costs"/></sheets></workbook>"#)),
+            ("xl/worksheets/sheet10.xml", sheet("tenth")),
+            ("xl/sharedStrings.xml", format!(r#"<sst {ns}><si><t>Rent</t></si><si><r><t>Fo</t></r><r><t>od</t></r></si></sst>"#)),
+            ("xl/worksheets/sheet2.xml", sheet("second")),
+        ]);
+        assert_eq!(document.text, "Budget\nCosts\nRent\nFood\nsecond\ntenth\n");
+        assert_eq!(fields(&document), [("sheet", "Budget"), ("sheet", "Costs")]);
+    }
+
+    #[test]
+    fn powerpoint_slides_come_in_order_with_their_titles() {
+        let slide = |title: &str, body: &str| format!(r#"<p:sld {A}><p:cSld><p:spTree>
+            <p:sp><p:nvSpPr><p:nvPr><p:ph type="title"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>{title}</a:t></a:r></a:p></p:txBody></p:sp>
+            <p:sp><p:nvSpPr><p:nvPr><p:ph idx="1"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>{body}</a:t></a:r></a:p></p:txBody></p:sp>
+        </p:spTree></p:cSld></p:sld>"#);
+        let document = parse("talk.pptx", &[
+            ("ppt/presentation.xml", format!("<p:presentation {A}/>")),
+            ("ppt/slides/slide10.xml", slide("Questions", "Thanks")),
+            ("ppt/slides/slide1.xml", slide("Welcome", "Agenda")),
+            ("ppt/slides/slide2.xml", slide("Results", "Growth")),
+            ("ppt/notesSlides/notesSlide1.xml", format!("<p:notes {A}><a:p><a:r><a:t>Speak slowly</a:t></a:r></a:p></p:notes>")),
+        ]);
+        assert_eq!(document.text, "Welcome\nAgenda\nResults\nGrowth\nQuestions\nThanks\nSpeak slowly\n");
+        assert_eq!(fields(&document), [("heading", "Welcome"), ("heading", "Results"), ("heading", "Questions")]);
+    }
+
+    #[test]
+    fn other_packages_are_rejected() {
+        let bytes = zip(&[("mimetype", b"application/epub+zip")]);
+        assert!(OfficeParser.parse(Path::new("book.docx"), &bytes, NO_LIMITS).is_err());
+    }
+}
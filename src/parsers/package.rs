@@ -0,0 +1,120 @@
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use xml::common::{TextPosition, Position};
+use xml::reader::{EventReader, XmlEvent};
+use zip::ZipArchive;
+
+use crate::config::Limits;
+
+/// ZIP based document format (Office Open XML, OpenDocument, EPUB) made of XML parts.
+/// Like the members of archives, its parts decompress to `max_file_size` bytes at most in all.
+pub struct Package<'a> {
+    path: PathBuf,
+    archive: ZipArchive<Cursor<&'a [u8]>>,
+    max_file_size: u64,
+    /// Bytes left to decompress
+    budget: u64,
+}
+
+impl<'a> Package<'a> {
+    pub fn open(file_path: &Path, bytes: &'a [u8], limits: Limits) -> Result<Self, ()> {
+        let archive = ZipArchive::new(Cursor::new(bytes)).map_err(|err| {
+            eprintln!("ERROR: could not read ZIP archive {file_path}: {err}", file_path = file_path.display());
+        })?;
+        Ok(Self { path: file_path.to_path_buf(), archive, max_file_size: limits.max_file_size, budget: limits.max_file_size })
+    }
+
+    /// Whether the parts read so far used up the bytes the package may decompress
+    pub fn exhausted(&self) -> bool {
+        self.budget == 0
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.archive.index_for_name(name).is_some()
+    }
+
+    /// Names of the parts `{prefix}N{suffix}` ordered by their number N, like the slides of a presentation
+    pub fn numbered_parts(&self, prefix: &str, suffix: &str) -> Vec<String> {
+        let mut parts = self.archive.file_names()
+            .filter_map(|name| {
+                let number = name.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                if number.is_empty() {
+                    return Some((0, name.to_string()));
+                }
+                number.parse::<usize>().ok().map(|number| (number, name.to_string()))
+            })
+            .collect::<Vec<_>>();
+        parts.sort();
+        parts.into_iter().map(|(_, name)| name).collect()
+    }
+
+    /// Decompress the part `name` out of the bytes left to decompress. A part that does not fit
+    /// fails and uses them up, so that every part read after it fails too.
+    pub fn read(&mut self, name: &str) -> Result<Vec<u8>, ()> {
+        let part = self.archive.by_name(name).map_err(|err| {
+            eprintln!("ERROR: could not read {name} in {file_path}: {err}", file_path = self.path.display());
+        })?;
+        let available = self.budget;
+        let mut bytes = Vec::new();
+        let result = part.take(available.saturating_add(1)).read_to_end(&mut bytes);
+        self.budget = available.saturating_sub(bytes.len() as u64);
+        result.map_err(|err| {
+            eprintln!("ERROR: could not read {name} in {file_path}: {err}", file_path = self.path.display());
+        })?;
+        if bytes.len() as u64 > available {
+            eprintln!("ERROR: could not read {name} in {file_path}: it decompresses to more than the limit of {max} bytes", file_path = self.path.display(), max = self.max_file_size);
+            return Err(());
+        }
+        Ok(bytes)
+    }
+
+    pub fn read_to_string(&mut self, name: &str) -> Result<String, ()> {
+        let bytes = self.read(name)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Feed every event of the XML part `name` to `visit`
+    pub fn read_xml(&mut self, name: &str, mut visit: impl FnMut(XmlEvent)) -> Result<(), ()> {
+        let bytes = self.read(name)?;
+        for event in EventReader::new(bytes.as_slice()) {
+            let event = event.map_err(|err| {
+                let TextPosition {row, column} = err.position();
+                let msg = err.msg();
+                eprintln!("{file_path}!/{name}:{row}:{column}: ERROR: {msg}", file_path = self.path.display());
+            })?;
+            visit(event);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use super::*;
+
+    pub const NO_LIMITS: Limits = Limits { max_file_size: u64::MAX, max_text: usize::MAX };
+
+    /// ZIP archive of the parts, in this order
+    pub fn zip(parts: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in parts {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn parts_are_bounded_by_what_they_decompress() {
+        let bytes = zip(&[("a.xml", &[b' '; 400]), ("b.xml", &[b' '; 400]), ("c.xml", b"")]);
+        let limits = Limits { max_file_size: 600, max_text: usize::MAX };
+        let mut package = Package::open(Path::new("bomb.docx"), &bytes, limits).unwrap();
+        assert_eq!(package.read("a.xml").map(|bytes| bytes.len()), Ok(400));
+        assert!(!package.exhausted());
+        assert_eq!(package.read("b.xml"), Err(()));
+        assert!(package.exhausted());
+        assert_eq!(package.read("c.xml"), Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,275 @@
+use std::io::Read;
+use std::os::raw::c_long;
+use std::path::Path;
+use poppler::Document;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::config::Limits;
+use super::{DocumentParser, ParsedDocument};
+
+/// Parser for PDF files. Every page becomes a sub-document `#page=N` so that the results
+/// point at the page the words are on.
+pub struct PdfParser;
+
+impl DocumentParser for PdfParser {
+    fn name(&self) -> &'static str {
+        "pdf"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["pdf"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/pdf"]
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], limits: Limits) -> Result<ParsedDocument, ()> {
+        let pdf = Document::from_data(bytes, None).map_err(|err| {
+            eprintln!("ERROR: could not read file {file_path}: {err}",
+                      file_path = file_path.display());
+        })?;
+        Ok(extract_within(&pdf, file_path, limits.max_text))
+    }
+
+    fn parse_reader(&self, file_path: &Path, reader: &mut dyn Read, limits: Limits) -> Result<ParsedDocument, ()> {
+        // Poppler reads the parts of a file on disk it needs by itself instead of the whole file from memory
+        let Some(uri) = file_uri(file_path) else {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).map_err(|err| {
+                eprintln!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
+            })?;
+            return self.parse(file_path, &bytes, limits);
+        };
+        let pdf = Document::from_file(&uri, None).map_err(|err| {
+            eprintln!("ERROR: could not read file {file_path}: {err}",
+                      file_path = file_path.display());
+        })?;
+        Ok(extract_within(&pdf, file_path, limits.max_text))
+    }
+}
+
+/// Like `extract`, warning about the text that is left out
+fn extract_within(pdf: &Document, file_path: &Path, max_text: usize) -> ParsedDocument {
+    let document = extract(pdf, max_text);
+    if document.metadata.contains_key("truncated") {
+        println!("WARNING: only the first {max_text} bytes of the text of {file_path:?} are indexed");
+    }
+    document
+}
+
+/// URI of the file for poppler, if it is a file on disk
+fn file_uri(file_path: &Path) -> Option<String> {
+    let path = file_path.canonicalize().ok().filter(|path| path.is_file())?;
+    let mut uri = "file://".to_string();
+    for byte in path.to_str()?.bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    Some(uri)
+}
+
+/// The properties of the PDF and the text of its pages, which are no longer extracted once
+/// there is `max_text` bytes of text, as what is beyond is cut off anyway
+fn extract(pdf: &Document, max_text: usize) -> ParsedDocument {
+    let mut document = ParsedDocument::default();
+    let info = [
+        ("title", pdf.title().map(|title| title.to_string())),
+        ("author", pdf.author().map(|author| author.to_string())),
+        ("subject", pdf.subject().map(|subject| subject.to_string())),
+        ("keywords", pdf.keywords().map(|keywords| keywords.to_string())),
+        ("created", iso_date(pdf.creation_date())),
+        ("modified", iso_date(pdf.modification_date())),
+    ];
+    let xmp = pdf.metadata();
+    for (key, value) in properties(info, xmp.as_deref()) {
+        if !matches!(key, "created" | "modified") {
+            document.text.push_str(&value);
+            document.text.push('\n');
+            document.fields.push((key.to_string(), value.clone()));
+        }
+        document.metadata.insert(key.to_string(), value);
+    }
+
+    let mut text_len = document.text.len();
+    let n = pdf.n_pages();
+    for i in 0..n {
+        if text_len >= max_text {
+            document.metadata.insert("truncated".to_string(), "true".to_string());
+            break;
+        }
+        let Some(page) = pdf.page(i) else {
+            continue;
+        };
+        let Some(content) = page.text().filter(|content| !content.trim().is_empty()) else {
+            continue;
+        };
+        // Pages are numbered from 1 like in the `#page=N` of the URL opening a PDF on a page
+        let number = (i + 1).to_string();
+        text_len += content.len();
+        let mut part = ParsedDocument { text: content.to_string(), ..Default::default() };
+        part.metadata.insert("page".to_string(), number.clone());
+        if let Some(label) = page.label().filter(|label| label.as_str() != number) {
+            part.metadata.insert("page_label".to_string(), label.to_string());
+        }
+        document.parts.push((format!("#page={number}"), part));
+    }
+    document
+}
+
+/// The properties of the document information dictionary, filled in with what the XMP packet
+/// says about the ones it leaves out
+fn properties(info: [(&'static str, Option<String>); 6], xmp: Option<&str>) -> Vec<(&'static str, String)> {
+    let mut properties = info.into_iter()
+        .filter_map(|(key, value)| Some((key, value?.trim().to_string())))
+        .filter(|(_, value)| !value.is_empty())
+        .collect::<Vec<_>>();
+    for (key, value) in xmp.map(parse_xmp).unwrap_or_default() {
+        if !properties.iter().any(|(known, _)| *known == key) {
+            properties.push((key, value));
+        }
+    }
+    properties
+}
+
+/// Title, author, subject, keywords and dates from the XMP metadata packet
+fn parse_xmp(xmp: &str) -> Vec<(&'static str, String)> {
+    fn key(local_name: &str) -> Option<&'static str> {
+        match local_name {
+            "title" => Some("title"),
+            "creator" => Some("author"),
+            "description" => Some("subject"),
+            "subject" | "Keywords" => Some("keywords"),
+            "CreateDate" => Some("created"),
+            "ModifyDate" => Some("modified"),
+            _ => None,
+        }
+    }
+    let mut properties: Vec<(&'static str, String)> = Vec::new();
+    let mut property = None;
+    let mut values = Vec::new();
+    let mut value = String::new();
+    for event in EventReader::new(xmp.as_bytes()) {
+        let Ok(event) = event else {
+            break;
+        };
+        match event {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                // Simple properties may be written as attributes of <rdf:Description>
+                for attribute in &attributes {
+                    if let Some(key) = key(&attribute.name.local_name) {
+                        properties.push((key, attribute.value.trim().to_string()));
+                    }
+                }
+                if property.is_none() {
+                    property = key(&name.local_name);
+                    values.clear();
+                }
+                value.clear();
+            }
+            XmlEvent::Characters(chars) if property.is_some() => value.push_str(&chars),
+            // The values of a property are either its text or the <rdf:li> items of its array
+            XmlEvent::EndElement { name } => {
+                let text = std::mem::take(&mut value);
+                if !text.trim().is_empty() {
+                    values.push(text.trim().to_string());
+                }
+                if let Some(key) = property.filter(|property| key(&name.local_name) == Some(*property)) {
+                    properties.push((key, values.join(", ")));
+                    property = None;
+                }
+            }
+            _ => {}
+        }
+    }
+    properties.retain(|(_, value)| !value.is_empty());
+    properties
+}
+
+/// Turn seconds since the Unix epoch into `2024-05-13T10:22:01Z`, or `None` for the `-1` of a missing date.
+/// The dates before 1970 are the negative ones.
+fn iso_date(timestamp: c_long) -> Option<String> {
+    if timestamp == -1 {
+        return None;
+    }
+    let (days, seconds) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+    // Civil date from the count of days, after Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + c_long::from(month <= 2);
+    Some(format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z", seconds / 3600, seconds % 3600 / 60, seconds % 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XMP_ELEMENTS: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+        <rdf:Description xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+            <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Annual Report</rdf:li></rdf:Alt></dc:title>
+            <dc:creator><rdf:Seq><rdf:li>Ada Lovelace</rdf:li><rdf:li>Charles Babbage</rdf:li></rdf:Seq></dc:creator>
+            <dc:subject><rdf:Bag><rdf:li>finance</rdf:li><rdf:li>2024</rdf:li></rdf:Bag></dc:subject>
+            <xmp:CreateDate>2024-05-13T10:22:01Z</xmp:CreateDate>
+        </rdf:Description>
+    </rdf:RDF></x:xmpmeta>"#;
+
+    const XMP_ATTRIBUTES: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+        <rdf:Description xmlns:pdf="http://ns.adobe.com/pdf/1.3/" xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+            pdf:Keywords=" finance, 2024 " xmp:ModifyDate="2024-06-01T08:00:00Z" pdf:Producer="LaTeX"/>
+    </rdf:RDF></x:xmpmeta>"#;
+
+    #[test]
+    fn xmp_properties_come_from_elements() {
+        assert_eq!(parse_xmp(XMP_ELEMENTS), [
+            ("title", "Annual Report".to_string()),
+            ("author", "Ada Lovelace, Charles Babbage".to_string()),
+            ("keywords", "finance, 2024".to_string()),
+            ("created", "2024-05-13T10:22:01Z".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn xmp_properties_come_from_attributes() {
+        assert_eq!(parse_xmp(XMP_ATTRIBUTES), [("keywords", "finance, 2024".to_string()), ("modified", "2024-06-01T08:00:00Z".to_string())]);
+        assert_eq!(parse_xmp("not xml at all"), []);
+    }
+
+    #[test]
+    fn info_dictionary_wins_over_xmp() {
+        let info = [
+            ("title", Some(" Report 2024 ".to_string())),
+            ("author", Some("  ".to_string())),
+            ("subject", None),
+            ("keywords", None),
+            ("created", None),
+            ("modified", Some("2024-07-01T00:00:00Z".to_string())),
+        ];
+        assert_eq!(properties(info.clone(), Some(XMP_ELEMENTS)), [
+            ("title", "Report 2024".to_string()),
+            ("modified", "2024-07-01T00:00:00Z".to_string()),
+            ("author", "Ada Lovelace, Charles Babbage".to_string()),
+            ("keywords", "finance, 2024".to_string()),
+            ("created", "2024-05-13T10:22:01Z".to_string()),
+        ]);
+        assert_eq!(properties(info, None).len(), 2);
+    }
+
+    #[test]
+    fn dates_become_iso_8601() {
+        assert_eq!(iso_date(-1), None);
+        assert_eq!(iso_date(0).as_deref(), Some("1970-01-01T00:00:00Z"));
+        assert_eq!(iso_date(1709208000).as_deref(), Some("2024-02-29T12:00:00Z"));
+        assert_eq!(iso_date(951825599).as_deref(), Some("2000-02-29T11:59:59Z"));
+        assert_eq!(iso_date(-2).as_deref(), Some("1969-12-31T23:59:58Z"));
+        assert_eq!(iso_date(-14182940).as_deref(), Some("1969-07-20T20:17:40Z"));
+    }
+}
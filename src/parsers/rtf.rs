@@ -0,0 +1,266 @@
+use std::path::Path;
+use encoding_rs::{Encoding, WINDOWS_1252};
+
+use crate::config::Limits;
+use super::{DocumentParser, ParsedDocument};
+
+/// Destinations whose content is not text: tables of fonts and styles, pictures, field instructions...
+const SKIPPED_DESTINATIONS: &[&str] = &[
+    "fonttbl", "colortbl", "stylesheet", "listtable", "listoverridetable", "rsidtbl", "revtbl", "filetbl",
+    "generator", "xmlnstbl", "latentstyles", "themedata", "colorschememapping", "datastore", "pict",
+    "object", "fldinst", "bkmkstart", "bkmkend",
+];
+/// Destinations of the \info group that are document properties
+const PROPERTIES: &[&str] = &["title", "author", "subject", "keywords"];
+
+/// Parser for Rich Text Format documents
+pub struct RtfParser;
+
+impl DocumentParser for RtfParser {
+    fn name(&self) -> &'static str {
+        "rtf"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["rtf"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/rtf", "text/rtf"]
+    }
+
+    fn parse(&self, _file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let mut rtf = Rtf::default();
+        rtf.read(bytes);
+        let mut document = ParsedDocument { text: rtf.text, ..Default::default() };
+        for (key, value) in rtf.properties {
+            let value = value.trim().to_string();
+            if !value.is_empty() {
+                document.fields.push((key.to_string(), value.clone()));
+                document.metadata.insert(key.to_string(), value);
+            }
+        }
+        Ok(document)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Group {
+    /// Whether the text of the group is thrown away
+    skip: bool,
+    /// Whether the group is in the \info group of the document properties
+    info: bool,
+    /// The document property the text of the group is the value of
+    property: Option<&'static str>,
+    /// How many characters stand in for every \uN for readers that do not know Unicode
+    fallback_len: usize,
+}
+
+impl Default for Group {
+    fn default() -> Self {
+        Self { skip: false, info: false, property: None, fallback_len: 1 }
+    }
+}
+
+struct Rtf {
+    text: String,
+    properties: Vec<(&'static str, String)>,
+    group: Group,
+    groups: Vec<Group>,
+    codepage: &'static Encoding,
+    /// Bytes of \'hh escapes waiting to be decoded together, as a character may take several
+    pending: Vec<u8>,
+    /// Fallback characters left to skip after a \uN
+    skip_fallback: usize,
+}
+
+impl Default for Rtf {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            properties: Vec::new(),
+            group: Group::default(),
+            groups: Vec::new(),
+            codepage: WINDOWS_1252,
+            pending: Vec::new(),
+            skip_fallback: 0,
+        }
+    }
+}
+
+impl Rtf {
+    fn read(&mut self, bytes: &[u8]) {
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'{' => {
+                    self.flush();
+                    self.groups.push(self.group);
+                    i += 1;
+                }
+                b'}' => {
+                    self.flush();
+                    self.group = self.groups.pop().unwrap_or_default();
+                    i += 1;
+                }
+                b'\\' => i = self.control(bytes, i + 1),
+                b'\r' | b'\n' => i += 1,
+                byte => {
+                    self.byte(byte);
+                    i += 1;
+                }
+            }
+        }
+        self.flush();
+    }
+
+    /// Handle the control word or symbol after the backslash at `i - 1`, returning where it ends
+    fn control(&mut self, bytes: &[u8], i: usize) -> usize {
+        let Some(&first) = bytes.get(i) else {
+            return i;
+        };
+        if !first.is_ascii_alphabetic() {
+            match first {
+                b'\'' => {
+                    let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+                    if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                        self.byte(byte);
+                    }
+                    return (i + 3).min(bytes.len());
+                }
+                // An ignorable destination that is not known is skipped
+                b'*' => self.group.skip = true,
+                b'~' => self.char('\u{a0}'),
+                b'_' => self.char('-'),
+                b'\r' | b'\n' => self.char('\n'),
+                b'-' => {}
+                symbol => self.char(symbol as char),
+            }
+            return i + 1;
+        }
+        let word_end = bytes[i..].iter().position(|b| !b.is_ascii_alphabetic()).map_or(bytes.len(), |len| i + len);
+        let word = std::str::from_utf8(&bytes[i..word_end]).unwrap_or("");
+        let digits = bytes[word_end..].iter().enumerate()
+            .take_while(|(j, b)| b.is_ascii_digit() || (*j == 0 && **b == b'-'))
+            .count();
+        let parameter = std::str::from_utf8(&bytes[word_end..word_end + digits]).ok().and_then(|digits| digits.parse::<i32>().ok());
+        let mut end = word_end + digits;
+        // A space delimiting the control word is part of it
+        if bytes.get(end) == Some(&b' ') {
+            end += 1;
+        }
+        self.word(word, parameter);
+        end
+    }
+
+    fn word(&mut self, word: &str, parameter: Option<i32>) {
+        match word {
+            "par" | "line" | "sect" | "page" | "row" => self.char('\n'),
+            "tab" | "cell" => self.char('\t'),
+            "emdash" => self.char('—'),
+            "endash" => self.char('–'),
+            "bullet" => self.char('•'),
+            "lquote" => self.char('‘'),
+            "rquote" => self.char('’'),
+            "ldblquote" => self.char('“'),
+            "rdblquote" => self.char('”'),
+            "u" => {
+                if let Some(code) = parameter {
+                    // Code points above 32767 are written as negative numbers
+                    let code = if code < 0 { code + 65536 } else { code };
+                    self.char(char::from_u32(code as u32).unwrap_or('\u{fffd}'));
+                    self.skip_fallback = self.group.fallback_len;
+                }
+            }
+            "uc" => self.group.fallback_len = parameter.unwrap_or(1).max(0) as usize,
+            "ansicpg" => {
+                let label = match parameter {
+                    Some(932) => "shift_jis".to_string(),
+                    Some(936) => "gbk".to_string(),
+                    Some(949) => "euc-kr".to_string(),
+                    Some(950) => "big5".to_string(),
+                    Some(10000) => "macintosh".to_string(),
+                    Some(codepage) => format!("windows-{codepage}"),
+                    None => return,
+                };
+                self.codepage = Encoding::for_label(label.as_bytes()).unwrap_or(WINDOWS_1252);
+            }
+            "mac" => self.codepage = Encoding::for_label(b"macintosh").unwrap_or(WINDOWS_1252),
+            "info" => {
+                self.group.skip = true;
+                self.group.info = true;
+            }
+            _ if SKIPPED_DESTINATIONS.contains(&word) => self.group.skip = true,
+            _ => {
+                if let Some(key) = PROPERTIES.iter().find(|name| **name == word).filter(|_| self.group.info) {
+                    self.group.property = Some(key);
+                    self.properties.push((key, String::new()));
+                }
+            }
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        if self.skip_fallback > 0 {
+            self.skip_fallback -= 1;
+            return;
+        }
+        self.pending.push(byte);
+    }
+
+    fn char(&mut self, c: char) {
+        self.flush();
+        self.push(c.encode_utf8(&mut [0; 4]));
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let (text, _) = self.codepage.decode_without_bom_handling(&pending);
+        self.push(&text);
+    }
+
+    fn push(&mut self, text: &str) {
+        match (self.group.property, self.properties.last_mut()) {
+            (Some(_), Some((_, value))) => value.push_str(text),
+            _ if self.group.skip => {}
+            _ => self.text.push_str(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::package::tests::NO_LIMITS;
+    use super::*;
+
+    fn parse(rtf: &str) -> ParsedDocument {
+        RtfParser.parse(Path::new("letter.rtf"), rtf.as_bytes(), NO_LIMITS).unwrap()
+    }
+
+    #[test]
+    fn hex_escapes_are_decoded_in_the_codepage() {
+        let document = parse(r"{\rtf1\ansi\ansicpg1252{\fonttbl{\f0 Times New Roman;}}{\*\generator Writer;}caf\'e9\par cr\'e8me}");
+        assert_eq!(document.text, "café\ncrème");
+        // A character of Shift_JIS takes two escapes
+        let document = parse(r"{\rtf1\ansi\ansicpg932 \'82\'a0\'82\'a2}");
+        assert_eq!(document.text, "あい");
+    }
+
+    #[test]
+    fn unicode_escapes_skip_their_fallback() {
+        let document = parse(r"{\rtf1 \u8364?x{\uc2\u8364 EUy}\u-28?z\u233\'65!}");
+        assert_eq!(document.text, "€x€y\u{ffe4}zé!");
+    }
+
+    #[test]
+    fn info_properties_are_fields_out_of_the_text() {
+        let document = parse(r"{\rtf1{\info{\title Quarterly \'e9tat}{\author Ada}{\operator Bob}{\creatim\yr2024}}Body\tab text}");
+        assert_eq!(document.text, "Body\ttext");
+        let fields = document.fields.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect::<Vec<_>>();
+        assert_eq!(fields, [("title", "Quarterly état"), ("author", "Ada")]);
+        assert_eq!(document.metadata.get("title").map(String::as_str), Some("Quarterly état"));
+    }
+}
@@ -0,0 +1,207 @@
+use super::{encoding, epub, odf, office};
+
+/// How much of the beginning of a file is looked at to guess its type
+pub const SNIFF_LEN: usize = 8192;
+
+/// Type of a file as told by its content rather than its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sniffed {
+    /// The file starts with the signature of a known format
+    Magic(&'static str),
+    /// The file looks like text, with a best guess of its MIME type
+    Text(&'static str),
+    /// The file is neither text nor any format we know the signature of
+    Binary,
+}
+
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"PK\x05\x06", "application/zip"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (257, b"ustar", "application/x-tar"),
+    (0, b"{\\rtf", "application/rtf"),
+    (0, b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (0, b"Rar!\x1a\x07", "application/vnd.rar"),
+    (0, b"BZh", "application/x-bzip2"),
+    (0, b"\xfd7zXZ\x00", "application/x-xz"),
+    (0, b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1", "application/x-ole-storage"),
+    (0, b"SQLite format 3\x00", "application/vnd.sqlite3"),
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"BM", "image/bmp"),
+    (0, b"II*\x00", "image/tiff"),
+    (0, b"MM\x00*", "image/tiff"),
+    (0, b"\x00\x00\x01\x00", "image/x-icon"),
+    (0, b"RIFF", "application/x-riff"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"fLaC", "audio/flac"),
+    (0, b"ID3", "audio/mpeg"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+    (0, b"\x7fELF", "application/x-executable"),
+    (0, b"MZ", "application/x-msdownload"),
+    (0, b"\xca\xfe\xba\xbe", "application/java-vm"),
+    (0, b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (0, b"\x00asm", "application/wasm"),
+];
+
+fn looks_like_text(head: &[u8]) -> bool {
+    // UTF-16 is full of zero bytes but its byte order mark or the pattern of its zeros gives it away
+    if head.starts_with(b"\xff\xfe") || head.starts_with(b"\xfe\xff") || encoding::utf16_without_bom(head).is_some() {
+        return true;
+    }
+    if head.contains(&0) {
+        return false;
+    }
+    let control = head.iter()
+        .filter(|b| (**b < 0x20 && !matches!(**b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || **b == 0x7f)
+        .count();
+    control * 20 < head.len().max(1)
+}
+
+/// Whether the lowercase beginning of a text is the header of an email message
+fn looks_like_email(head: &[u8]) -> bool {
+    const HEADERS: &[&[u8]] = &[b"return-path:", b"received:", b"delivered-to:", b"from:", b"to:", b"date:", b"subject:", b"message-id:", b"mime-version:"];
+    head.split(|b| *b == b'\n')
+        .take_while(|line| line.iter().any(|b| !b.is_ascii_whitespace()))
+        .filter(|line| HEADERS.iter().any(|header| line.starts_with(header)))
+        .count() >= 2
+}
+
+fn guess_text_type(head: &[u8]) -> &'static str {
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let start = head.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(head.len());
+    let head = head[start..].iter().map(|b| b.to_ascii_lowercase()).collect::<Vec<_>>();
+    // A mailbox starts with the `From ` line of its first message, followed by its header
+    let after_first_line = head.iter().position(|b| *b == b'\n').map(|end| &head[end + 1..]).unwrap_or(&[]);
+    if head.starts_with(b"from ") && looks_like_email(after_first_line) {
+        "application/mbox"
+    } else if looks_like_email(&head) {
+        "message/rfc822"
+    } else if head.starts_with(b"<?xml") {
+        "application/xml"
+    } else if head.starts_with(b"<!doctype html") || head.starts_with(b"<html") {
+        "text/html"
+    } else {
+        "text/plain"
+    }
+}
+
+/// Tell the document formats that are ZIP archives under the hood apart by the names
+/// of the parts near the beginning of the archive
+fn zip_content_type(head: &[u8]) -> &'static str {
+    // OpenDocument and EPUB start with an uncompressed `mimetype` entry holding their MIME type
+    if head.len() >= 30 && &head[30..head.len().min(38)] == b"mimetype" {
+        let size = u32::from_le_bytes([head[18], head[19], head[20], head[21]]) as usize;
+        let start = 30 + 8 + u16::from_le_bytes([head[28], head[29]]) as usize;
+        if let Some(mime_type) = head.get(start..start + size) {
+            let known = [odf::ODT, odf::ODS, odf::ODP, epub::EPUB];
+            if let Some(mime_type) = known.into_iter().find(|known| known.as_bytes() == mime_type) {
+                return mime_type;
+            }
+        }
+    }
+    let contains = |needle: &[u8]| head.windows(needle.len()).any(|window| window == needle);
+    if contains(b"[Content_Types].xml") {
+        if contains(b"word/") {
+            return office::DOCX;
+        } else if contains(b"xl/") {
+            return office::XLSX;
+        } else if contains(b"ppt/") {
+            return office::PPTX;
+        }
+    }
+    "application/zip"
+}
+
+fn sniff_bytes(head: &[u8]) -> Sniffed {
+    let text = looks_like_text(head);
+    for (offset, signature, mime_type) in SIGNATURES {
+        // Signatures this short also start plenty of text files ("BMW", "MZ-80", ...)
+        if signature.len() < 4 && text {
+            continue;
+        }
+        if head.len() >= offset + signature.len() && &head[*offset..offset + signature.len()] == *signature {
+            if *mime_type == "application/zip" {
+                return Sniffed::Magic(zip_content_type(head));
+            }
+            return Sniffed::Magic(mime_type);
+        }
+    }
+    if text {
+        Sniffed::Text(guess_text_type(head))
+    } else {
+        Sniffed::Binary
+    }
+}
+
+/// Guess the type of a file from the beginning of its content
+pub fn sniff(bytes: &[u8]) -> Sniffed {
+    sniff_bytes(&bytes[..bytes.len().min(SNIFF_LEN)])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+    use super::*;
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            writer.start_file(*name, SimpleFileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn magic_bytes_win_over_text() {
+        assert_eq!(sniff(b"%PDF-1.7\n%\xe2\xe3\xcf\xd3\n"), Sniffed::Magic("application/pdf"));
+        assert_eq!(sniff(b"{\\rtf1\\ansi Hello}"), Sniffed::Magic("application/rtf"));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"), Sniffed::Magic("image/png"));
+        assert_eq!(sniff(b"\x1f\x8b\x08\x00\x00\x00\x00\x00"), Sniffed::Magic("application/gzip"));
+        let mut tar = vec![0; 512];
+        tar[..9].copy_from_slice(b"notes.txt");
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar), Sniffed::Magic("application/x-tar"));
+    }
+
+    #[test]
+    fn short_signatures_do_not_claim_text() {
+        assert_eq!(sniff(b"BMW and Audi are car makers."), Sniffed::Text("text/plain"));
+        assert_eq!(sniff(b"MZ-80 was a home computer."), Sniffed::Text("text/plain"));
+        assert_eq!(sniff(b"BM\x00\x00\x36\x00\x00\x00"), Sniffed::Magic("image/bmp"));
+    }
+
+    #[test]
+    fn zip_based_formats_are_told_apart() {
+        assert_eq!(sniff(&zip(&[("mimetype", odf::ODT.as_bytes()), ("content.xml", b"<x/>")])), Sniffed::Magic(odf::ODT));
+        assert_eq!(sniff(&zip(&[("mimetype", epub::EPUB.as_bytes())])), Sniffed::Magic(epub::EPUB));
+        assert_eq!(sniff(&zip(&[("[Content_Types].xml", b"<x/>"), ("word/document.xml", b"<x/>")])), Sniffed::Magic(office::DOCX));
+        assert_eq!(sniff(&zip(&[("[Content_Types].xml", b"<x/>"), ("xl/workbook.xml", b"<x/>")])), Sniffed::Magic(office::XLSX));
+        assert_eq!(sniff(&zip(&[("notes.txt", b"hello")])), Sniffed::Magic("application/zip"));
+    }
+
+    #[test]
+    fn text_types_are_guessed_from_the_beginning() {
+        assert_eq!(sniff(b"\xef\xbb\xbf  <!DOCTYPE html><html></html>"), Sniffed::Text("text/html"));
+        assert_eq!(sniff(b"<?xml version=\"1.0\"?><a/>"), Sniffed::Text("application/xml"));
+        assert_eq!(sniff(b"From: alice@example.com\nSubject: Hi\n\nHello"), Sniffed::Text("message/rfc822"));
+        assert_eq!(sniff(b"From alice@example.com Mon Jan  1 00:00:00 2024\nFrom: alice@example.com\nDate: Mon, 1 Jan 2024\n\nHi"), Sniffed::Text("application/mbox"));
+        assert_eq!(sniff(b"From here on, it is just text.\nTo be continued."), Sniffed::Text("text/plain"));
+    }
+
+    #[test]
+    fn binary_and_utf16() {
+        assert_eq!(sniff(b"\x00\x01\x02\x03binary\x00stuff"), Sniffed::Binary);
+        let utf16 = "hello world".encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<_>>();
+        assert_eq!(sniff(&[b"\xff\xfe".as_slice(), &utf16].concat()), Sniffed::Text("text/plain"));
+        assert_eq!(sniff(b""), Sniffed::Text("text/plain"));
+    }
+}
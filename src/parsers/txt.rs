@@ -0,0 +1,99 @@
+use std::io::Read;
+use std::path::Path;
+
+use crate::config::Limits;
+use crate::lang::Language;
+use crate::model::TermCounter;
+
+use super::encoding;
+use super::{DocumentParser, ParsedDocument};
+
+/// Parser for plain text, which it streams so that huge logs are indexed without being held in memory
+pub struct TxtParser;
+
+impl DocumentParser for TxtParser {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "log"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["text/plain"]
+    }
+
+    fn parse(&self, _file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let (text, encoding) = encoding::decode(bytes, None);
+        let mut document = ParsedDocument { text, ..Default::default() };
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        Ok(document)
+    }
+
+    fn parse_reader(&self, file_path: &Path, reader: &mut dyn Read, limits: Limits) -> Result<ParsedDocument, ()> {
+        let mut counter = TermCounter::new(None, Language::default()).with_limit(limits.max_text);
+        let encoding = encoding::decode_reader(reader, None, |text| {
+            counter.push(text);
+            !counter.truncated()
+        }).map_err(|err| {
+            eprintln!("ERROR: could not read file {file_path}: {err}", file_path = file_path.display());
+        })?;
+        let truncated = counter.truncated();
+        let (terms, language) = counter.finish();
+        let mut document = ParsedDocument { terms: Some(terms), ..Default::default() };
+        document.metadata.insert("encoding".to_string(), encoding.name().to_string());
+        document.metadata.insert("lang".to_string(), language.code().to_string());
+        if truncated {
+            document.metadata.insert("truncated".to_string(), "true".to_string());
+        }
+        Ok(document)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::lexer::EXACT_PREFIX;
+    use super::super::package::tests::NO_LIMITS;
+    use super::*;
+
+    fn parse_reader(bytes: &[u8], max_text: usize) -> (ParsedDocument, u64) {
+        let mut reader = Cursor::new(bytes);
+        let limits = Limits { max_file_size: u64::MAX, max_text };
+        let document = TxtParser.parse_reader(Path::new("server.log"), &mut reader, limits).unwrap();
+        (document, reader.position())
+    }
+
+    #[test]
+    fn words_split_across_chunks_are_counted_whole() {
+        // "boundary" starts 4 bytes before the end of the first chunk the text is read in
+        let mut text = "log line ".repeat(64 * 1024 / 9 + 1);
+        text.truncate(64 * 1024 - 5);
+        text.push(' ');
+        text.push_str("boundary and more\n");
+        let (document, _) = parse_reader(text.as_bytes(), usize::MAX);
+        let terms = document.terms.unwrap();
+        assert!(terms.tf.contains_key(&format!("{EXACT_PREFIX}boundary")));
+        assert!(!terms.tf.contains_key(&format!("{EXACT_PREFIX}boun")));
+        assert!(!terms.tf.contains_key(&format!("{EXACT_PREFIX}dary")));
+        assert_eq!(document.metadata.get("encoding").map(String::as_str), Some("UTF-8"));
+        assert!(!document.metadata.contains_key("truncated"));
+    }
+
+    #[test]
+    fn reading_stops_at_max_text() {
+        let text = "word ".repeat(100_000);
+        let (document, read) = parse_reader(text.as_bytes(), 20);
+        assert_eq!(document.metadata.get("truncated").map(String::as_str), Some("true"));
+        assert_eq!(document.terms.unwrap().tf.get(&format!("{EXACT_PREFIX}word")), Some(&4));
+        assert!(read < text.len() as u64);
+    }
+
+    #[test]
+    fn text_in_memory_is_decoded() {
+        let document = TxtParser.parse(Path::new("notes.txt"), b"caf\xE9 cr\xE8me br\xFBl\xE9e, c'est d\xE9j\xE0 l'\xE9t\xE9", NO_LIMITS).unwrap();
+        assert_eq!(document.text, "café crème brûlée, c'est déjà l'été");
+        assert_eq!(document.metadata.get("encoding").map(String::as_str), Some("windows-1252"));
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::Path;
+use xml::common::{TextPosition, Position};
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::config::Limits;
+use super::{DocumentParser, ParsedDocument};
+
+pub struct XmlParser;
+
+impl DocumentParser for XmlParser {
+    fn name(&self) -> &'static str {
+        "xml"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["xml"]
+    }
+
+    fn mime_types(&self) -> &'static [&'static str] {
+        &["application/xml", "text/xml"]
+    }
+
+    fn parse(&self, file_path: &Path, bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+        let er = EventReader::new(bytes);
+        let mut content = String::new();
+        for event in er.into_iter() {
+            let event = event.map_err(|err| {
+                let TextPosition {row, column} = err.position();
+                let msg = err.msg();
+                eprintln!("{file_path}:{row}:{column}: ERROR: {msg}", file_path = file_path.display(), row = row, column = column, msg = msg);
+            })?;
+            if let XmlEvent::Characters(text) = event {
+                content.push_str(&text);
+                content.push_str(" ");
+            }
+        }
+        Ok(ParsedDocument { text: content, ..Default::default() })
+    }
+}
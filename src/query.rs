@@ -0,0 +1,215 @@
+use crate::lang::Language;
+use crate::lexer::{self, Lexer, EXACT_PREFIX};
+use crate::model::Metadata;
+
+/// How the value of a filter is compared with the value of the metadata, ignoring case
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterMatch {
+    Equals,
+    Contains,
+    /// For dates, so `date:2024-05` picks all of May 2024
+    StartsWith,
+}
+
+/// Metadata keys that can restrict the search with `key:value` words in the query, and whether they
+/// only restrict messages. In the other documents those words search the field of that name, like
+/// a `date` column of a CSV file or a `subject` key of front matter.
+const FILTER_KEYS: &[(&str, FilterMatch, bool)] = &[
+    ("lang", FilterMatch::Equals, false),
+    ("from", FilterMatch::Contains, true),
+    ("to", FilterMatch::Contains, true),
+    ("subject", FilterMatch::Contains, true),
+    ("date", FilterMatch::StartsWith, true),
+];
+
+/// Whether the document is an email message, which the filters on headers apply to
+pub fn is_message(metadata: &Metadata) -> bool {
+    metadata.get("mime").is_some_and(|mime| mime.starts_with("message/"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub key: String,
+    pub value: String,
+    pub how: FilterMatch,
+    pub messages_only: bool,
+}
+
+impl Filter {
+    pub fn applies_to(&self, message: bool) -> bool {
+        message || !self.messages_only
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let Some(value) = metadata.get(&self.key) else {
+            return false;
+        };
+        let value = value.to_lowercase();
+        let expected = self.value.to_lowercase();
+        match self.how {
+            FilterMatch::Equals => value == expected,
+            FilterMatch::Contains => value.contains(&expected),
+            FilterMatch::StartsWith => value.starts_with(&expected),
+        }
+    }
+}
+
+/// Search query split into the free text that gets lexed, the `=words`
+/// that only match their exact form, the `field:words` that only match inside
+/// that field and the metadata filters
+pub struct Query {
+    text: Vec<char>,
+    exact: Vec<char>,
+    fields: Vec<(String, Vec<char>)>,
+    pub filters: Vec<Filter>,
+}
+
+impl Query {
+    pub fn parse(query: &[char]) -> Self {
+        let mut text = Vec::new();
+        let mut exact = Vec::new();
+        let mut fields = Vec::new();
+        let mut filters = Vec::new();
+        for word in query.split(|c| c.is_whitespace()).filter(|word| !word.is_empty()) {
+            let clause = word.iter().position(|c| *c == ':').and_then(|colon| {
+                let name = word[..colon].iter().collect::<String>().to_lowercase();
+                let value = &word[colon + 1..];
+                let is_name = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'));
+                if is_name && !value.is_empty() {
+                    Some((name, value))
+                } else {
+                    None
+                }
+            });
+            let filter = clause.as_ref().and_then(|(key, _)| FILTER_KEYS.iter().find(|(name, _, _)| name == key));
+            match (clause, filter) {
+                (Some((key, value)), Some((_, how, messages_only))) => {
+                    if *messages_only {
+                        fields.push((key.clone(), value.to_vec()));
+                    }
+                    filters.push(Filter { key, value: value.iter().collect(), how: *how, messages_only: *messages_only });
+                }
+                (Some((name, value)), None) => fields.push((name, value.to_vec())),
+                (None, _) if word[0] == EXACT_PREFIX => {
+                    exact.extend_from_slice(&word[1..]);
+                    exact.push(' ');
+                }
+                (None, _) => {
+                    text.extend_from_slice(word);
+                    text.push(' ');
+                }
+            }
+        }
+        Self { text, exact, fields, filters }
+    }
+
+    /// Terms of the query normalized the same way as the documents in `language`. In messages
+    /// the words of the filters on headers are filters only, not fields.
+    pub fn terms(&self, language: Language, message: bool) -> Vec<String> {
+        let mut terms = Lexer::new(&self.text, language).terms()
+            .chain(lexer::exact_terms(&self.exact, language))
+            .collect::<Vec<_>>();
+        for (name, value) in self.fields.iter().filter(|(name, _)| !(message && self.filters_messages_on(name))) {
+            terms.extend(lexer::field_terms(name, value, language));
+        }
+        terms
+    }
+
+    /// Whether the query does nothing but filter the documents of this kind, in which case all
+    /// the ones that pass the filters match rather than the ones that contain the terms
+    pub fn only_filters(&self, message: bool) -> bool {
+        self.text.is_empty() && self.exact.is_empty()
+            && self.fields.iter().all(|(name, _)| message && self.filters_messages_on(name))
+            && self.filters.iter().any(|filter| filter.applies_to(message))
+    }
+
+    fn filters_messages_on(&self, key: &str) -> bool {
+        self.filters.iter().any(|filter| filter.messages_only && filter.key == key)
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        let message = is_message(metadata);
+        self.filters.iter()
+            .filter(|filter| filter.applies_to(message))
+            .all(|filter| filter.matches(metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Query {
+        Query::parse(&query.chars().collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn exact_words_match_their_lowercase_form_only() {
+        let query = parse("=Running =the dogs");
+        assert_eq!(query.terms(Language::English, false), ["dog", "=running", "=the"]);
+    }
+
+    #[test]
+    fn field_words_are_stemmed_within_their_field() {
+        let query = parse("Title:Running heading:the");
+        assert_eq!(query.terms(Language::English, false), ["title:run"]);
+        assert!(query.filters.is_empty());
+    }
+
+    #[test]
+    fn colons_without_a_field_or_a_value_are_free_text() {
+        let query = parse(":colon trailing:");
+        assert_eq!(query.terms(Language::English, false), [":", "colon", "trail", ":"]);
+    }
+
+    #[test]
+    fn the_first_colon_ends_the_field_of_a_clause() {
+        let query = parse("a:b:c");
+        assert_eq!(query.terms(Language::English, false), ["a:b", "a::", "a:c"]);
+    }
+
+    #[test]
+    fn filter_keys_restrict_the_metadata() {
+        let query = parse("lang:DE report");
+        assert_eq!(query.filters, [Filter { key: "lang".to_string(), value: "DE".to_string(), how: FilterMatch::Equals, messages_only: false }]);
+        assert_eq!(query.terms(Language::English, false), ["report"]);
+        let metadata = |lang: &str| Metadata::from([("lang".to_string(), lang.to_string())]);
+        assert!(query.matches(&metadata("de")));
+        assert!(!query.matches(&metadata("en")));
+        assert!(!query.matches(&Metadata::new()));
+    }
+
+    #[test]
+    fn header_filters_only_restrict_messages() {
+        let query = parse("subject:Invoice");
+        let message = Metadata::from([("mime".to_string(), "message/rfc822".to_string()), ("subject".to_string(), "Your invoice".to_string())]);
+        let other = Metadata::from([("mime".to_string(), "message/rfc822".to_string()), ("subject".to_string(), "Lunch".to_string())]);
+        let csv = Metadata::from([("mime".to_string(), "text/csv".to_string())]);
+        assert!(query.matches(&message));
+        assert!(!query.matches(&other));
+        assert!(query.matches(&csv));
+        assert!(query.terms(Language::English, true).is_empty());
+        assert_eq!(query.terms(Language::English, false), ["subject:invoic"]);
+        assert!(query.only_filters(true));
+        assert!(!query.only_filters(false));
+    }
+
+    #[test]
+    fn only_filters_needs_a_filter_and_nothing_else() {
+        assert!(parse("lang:de").only_filters(false));
+        assert!(!parse("").only_filters(false));
+        assert!(!parse("the").only_filters(false));
+        assert!(!parse("lang:de =the").only_filters(false));
+        assert!(!parse("lang:de title:report").only_filters(true));
+    }
+
+    #[test]
+    fn filters_compare_as_their_key_says() {
+        let filter = |key: &str, value: &str, how| Filter { key: key.to_string(), value: value.to_string(), how, messages_only: true };
+        let metadata = Metadata::from([("date".to_string(), "2024-05-17".to_string()), ("from".to_string(), "Alice <alice@example.com>".to_string())]);
+        assert!(filter("date", "2024-05", FilterMatch::StartsWith).matches(&metadata));
+        assert!(!filter("date", "05", FilterMatch::StartsWith).matches(&metadata));
+        assert!(filter("from", "ALICE@", FilterMatch::Contains).matches(&metadata));
+        assert!(!filter("from", "alice", FilterMatch::Equals).matches(&metadata));
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::parsers::{ParsedDocument, ParserRegistry};
+
+/// How the parsing of a file went wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    /// The parser found something wrong with the file and said so
+    Rejected,
+    /// The parser panicked, hung or took its process down with it
+    Crashed,
+}
+
+/// How many parsers that did not finish in time may be left running in threads. Beyond that, files
+/// are parsed in child processes, which unlike threads are killed once they take too long.
+const MAX_STUCK_THREADS: usize = 4;
+
+/// How to run the program itself to parse a single file, see the `parse` subcommand
+pub struct Isolation {
+    pub program: PathBuf,
+    /// What comes before the path of the file to parse and the path of the output
+    pub arguments: Vec<String>,
+}
+
+/// Runs the parsers so that a file that makes one hang, panic or crash only costs that file.
+/// Files that keep doing so are quarantined until they change.
+pub struct Sandbox {
+    parsers: Arc<ParserRegistry>,
+    /// Where the limits of the files come from
+    config: Config,
+    timeout: Duration,
+    isolation: Isolation,
+    /// Whether every file is parsed in a child process of its own rather than in a thread
+    isolate: bool,
+    /// Threads that are parsing a file, which are the ones left behind when no file is being parsed
+    threads: Arc<AtomicUsize>,
+    quarantine: Quarantine,
+}
+
+impl Sandbox {
+    pub fn new(parsers: ParserRegistry, config: Config, isolation: Isolation, isolate: bool, quarantine: Quarantine) -> Self {
+        Self {
+            parsers: Arc::new(parsers),
+            timeout: Duration::from_secs(config.parse_timeout),
+            config,
+            isolation,
+            isolate,
+            threads: Arc::new(AtomicUsize::new(0)),
+            quarantine,
+        }
+    }
+
+    /// Parse the file like the parsers do, skipping it with `None` when they do. A file in
+    /// quarantine is not parsed at all and skipped too, as its failures were counted when they happened.
+    pub fn parse(&mut self, file_path: &Path, last_modified: SystemTime) -> Result<Option<ParsedDocument>, ()> {
+        if let Some(failures) = self.quarantine.failures(file_path, last_modified) {
+            println!("Skipping {file_path:?} because it is quarantined after parsing it crashed {crashes} times and failed {rejections} times.",
+                     file_path = file_path, crashes = failures.crashes, rejections = failures.rejections);
+            return Ok(None);
+        }
+        let stuck = self.threads.load(Ordering::SeqCst);
+        let result = if self.isolate {
+            self.parse_in_child(file_path)
+        } else if stuck >= MAX_STUCK_THREADS {
+            println!("WARNING: parsing {file_path:?} in a process of its own as {stuck} parsers that did not finish in time are still running.");
+            self.parse_in_child(file_path)
+        } else {
+            self.parse_in_thread(file_path)
+        };
+        match result {
+            Ok(document) => {
+                self.quarantine.forget(file_path);
+                Ok(document)
+            }
+            Err(failure) => {
+                self.quarantine.record(file_path, last_modified, failure);
+                Err(())
+            }
+        }
+    }
+
+    fn parse_in_thread(&self, file_path: &Path) -> Result<Option<ParsedDocument>, Failure> {
+        let (sender, receiver) = mpsc::channel();
+        let parsers = Arc::clone(&self.parsers);
+        let path = file_path.to_path_buf();
+        let limits = self.config.limits(file_path);
+        // A thread cannot be stopped, so one whose parser hangs is left behind rather than waited for
+        let threads = Arc::clone(&self.threads);
+        threads.fetch_add(1, Ordering::SeqCst);
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| parsers.parse(&path, limits)));
+            threads.fetch_sub(1, Ordering::SeqCst);
+            let _ = sender.send(result);
+        });
+        match receiver.recv_timeout(self.timeout) {
+            Ok(Ok(Ok(document))) => Ok(document),
+            Ok(Ok(Err(()))) => Err(Failure::Rejected),
+            Ok(Err(_)) | Err(RecvTimeoutError::Disconnected) => {
+                eprintln!("ERROR: the parser panicked on {file_path}", file_path = file_path.display());
+                Err(Failure::Crashed)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                eprintln!("ERROR: parsing {file_path} did not finish within {timeout} seconds", file_path = file_path.display(), timeout = self.timeout.as_secs());
+                Err(Failure::Crashed)
+            }
+        }
+    }
+
+    fn parse_in_child(&self, file_path: &Path) -> Result<Option<ParsedDocument>, Failure> {
+        let isolation = &self.isolation;
+        // The output is out of the reach of other users, who could otherwise forge it or have it overwrite their files
+        let directory = TempDir::new().map_err(|err| {
+            eprintln!("ERROR: could not create a temporary directory to parse {file_path}: {err}", file_path = file_path.display());
+            Failure::Rejected
+        })?;
+        let output_path = directory.path().join("document.json");
+        let mut child = Command::new(&isolation.program)
+            .args(&isolation.arguments)
+            .arg(file_path)
+            .arg(&output_path)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|err| {
+                eprintln!("ERROR: could not start a process to parse {file_path}: {err}", file_path = file_path.display());
+                Failure::Rejected
+            })?;
+        let result = match wait_or_kill(&mut child, self.timeout) {
+            Ok(Some(status)) if status.success() => read_document(&output_path).map_err(|()| Failure::Rejected),
+            // What `entry` exits with when the parser returns an error
+            Ok(Some(status)) if status.code() == Some(1) => Err(Failure::Rejected),
+            Ok(Some(status)) => {
+                eprintln!("ERROR: the process parsing {file_path} crashed: {status}", file_path = file_path.display());
+                Err(Failure::Crashed)
+            }
+            Ok(None) => {
+                eprintln!("ERROR: parsing {file_path} did not finish within {timeout} seconds", file_path = file_path.display(), timeout = self.timeout.as_secs());
+                Err(Failure::Crashed)
+            }
+            Err(err) => {
+                eprintln!("ERROR: could not wait for the process parsing {file_path}: {err}", file_path = file_path.display());
+                Err(Failure::Rejected)
+            }
+        };
+        result
+    }
+}
+
+fn read_document(path: &Path) -> Result<Option<ParsedDocument>, ()> {
+    let file = File::open(path).map_err(|err| {
+        eprintln!("ERROR: could not open parsed document {path}: {err}", path = path.display());
+    })?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|err| {
+        eprintln!("ERROR: could not read parsed document {path}: {err}", path = path.display());
+    })
+}
+
+/// Write the document parsed out of a file into a new file, `None` standing for a file that is skipped
+pub fn write_document(document: &Option<ParsedDocument>, path: &Path) -> Result<(), ()> {
+    let file = create_new(path).map_err(|err| {
+        eprintln!("ERROR: could not create file {path}: {err}", path = path.display());
+    })?;
+    serde_json::to_writer(BufWriter::new(file), document).map_err(|err| {
+        eprintln!("ERROR: could not write parsed document {path}: {err}", path = path.display());
+    })
+}
+
+/// Directory of its own in the temporary directory of the system, which only the user can get into.
+/// It is removed with everything in it once dropped.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    #[cfg(unix)]
+    pub fn new() -> io::Result<Self> {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+        let mut template = std::env::temp_dir().join("local_search_engine-XXXXXX").into_os_string().into_vec();
+        template.push(0);
+        // SAFETY: mkdtemp replaces the XXXXXX at the end of the nul terminated template in place
+        if unsafe { libc::mkdtemp(template.as_mut_ptr().cast()) }.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        template.pop();
+        Ok(Self { path: PathBuf::from(OsString::from_vec(template)) })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let mut attempts = 0;
+        loop {
+            let nanos = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+            let count = COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = std::env::temp_dir().join(format!("local_search_engine-{}-{nanos:x}-{count}", std::process::id()));
+            // Unlike opening a file, creating a directory never reuses what is already there
+            match fs::create_dir(&path) {
+                Ok(()) => return Ok(Self { path }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempts < 100 => attempts += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Create a file that does not exist yet, which only the user can read. A link planted
+/// where the file goes makes it fail rather than write where the link points.
+pub fn create_new(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+/// Wait for the child to exit, killing it when it takes longer than `timeout`. `None` means it was killed.
+pub fn wait_or_kill(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    let mut pause = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            kill(child);
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(pause);
+        pause = (pause * 2).min(Duration::from_millis(10));
+    }
+}
+
+/// Kill the child, and all the processes it started when it leads a process group of its own
+pub fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        let pid = child.id() as libc::pid_t;
+        // SAFETY: the child is not waited for yet, so its pid and the group it leads are still its own
+        unsafe {
+            if libc::getpgid(pid) == pid {
+                libc::killpg(pid, libc::SIGKILL);
+            }
+        }
+    }
+    let _ = child.kill();
+}
+
+/// Keep the process from using more than `bytes` of memory, so that a parser that goes wild
+/// fails to allocate and takes down its own process only
+#[cfg(unix)]
+pub fn limit_memory(bytes: u64) {
+    let limit = libc::rlimit { rlim_cur: bytes as libc::rlim_t, rlim_max: bytes as libc::rlim_t };
+    // SAFETY: setrlimit only reads the limit it is given
+    if unsafe { libc::setrlimit(libc::RLIMIT_AS, &limit) } != 0 {
+        println!("WARNING: could not limit the memory of the parser: {err}", err = io::Error::last_os_error());
+    }
+}
+
+#[cfg(not(unix))]
+pub fn limit_memory(_bytes: u64) {
+    println!("WARNING: the memory of parsers cannot be limited on this platform");
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Failures {
+    /// How many times parsing the file crashed
+    #[serde(alias = "count")]
+    crashes: u32,
+    /// How many times a parser rejected the file
+    #[serde(default)]
+    rejections: u32,
+    /// The version of the file that failed, as a new version gets another chance
+    last_modified: SystemTime,
+}
+
+/// Files that failed to parse and how many times, kept in `.local_search_engine.quarantine.json`.
+/// A crash costs a timeout or a process each time and may take down the whole program with it,
+/// so it takes fewer of them than of the errors a parser reports for a file to be quarantined.
+pub struct Quarantine {
+    path: PathBuf,
+    max_failures: u32,
+    max_rejections: u32,
+    files: BTreeMap<PathBuf, Failures>,
+}
+
+impl Quarantine {
+    pub fn load(path: &Path, config: &Config) -> Self {
+        let files = match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|err| {
+                println!("WARNING: starting over with quarantine file {path} as it could not be parsed: {err}", path = path.display());
+                BTreeMap::new()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                println!("WARNING: starting over with quarantine file {path} as it could not be opened: {err}", path = path.display());
+                BTreeMap::new()
+            }
+        };
+        Self { path: path.to_path_buf(), max_failures: config.max_failures, max_rejections: config.max_rejections, files }
+    }
+
+    /// How many times the file failed, if that is enough for it not to be parsed again
+    fn failures(&self, file_path: &Path, last_modified: SystemTime) -> Option<&Failures> {
+        self.files.get(file_path)
+            .filter(|failures| failures.last_modified == last_modified && self.quarantines(failures))
+    }
+
+    fn quarantines(&self, failures: &Failures) -> bool {
+        failures.crashes >= self.max_failures || failures.rejections >= self.max_rejections
+    }
+
+    fn record(&mut self, file_path: &Path, last_modified: SystemTime, failure: Failure) {
+        let failures = self.files.entry(file_path.to_path_buf()).or_insert(Failures { crashes: 0, rejections: 0, last_modified });
+        if failures.last_modified != last_modified {
+            *failures = Failures { crashes: 0, rejections: 0, last_modified };
+        }
+        match failure {
+            Failure::Crashed => failures.crashes += 1,
+            Failure::Rejected => failures.rejections += 1,
+        }
+        let failures = &self.files[file_path];
+        if self.quarantines(failures) {
+            println!("WARNING: {file_path:?} is quarantined until it changes, after {crashes} crashes and {rejections} failures.",
+                     file_path = file_path, crashes = failures.crashes, rejections = failures.rejections);
+        }
+        // Saved right away, as the next crash may be the one of the whole program
+        let _ = self.save();
+    }
+
+    fn forget(&mut self, file_path: &Path) {
+        if self.files.remove(file_path).is_some() {
+            let _ = self.save();
+        }
+    }
+
+    fn save(&self) -> Result<(), ()> {
+        let file = File::create(&self.path).map_err(|err| {
+            eprintln!("ERROR: could not create quarantine file {path}: {err}", path = self.path.display());
+        })?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &self.files).map_err(|err| {
+            eprintln!("ERROR: could not write quarantine file {path}: {err}", path = self.path.display());
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+    use crate::config::Limits;
+    use crate::parsers::DocumentParser;
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("local_search_engine-test-{}-{name}", std::process::id()))
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn counts(quarantine: &Quarantine, file_path: &Path) -> Option<(u32, u32)> {
+        quarantine.files.get(file_path).map(|failures| (failures.crashes, failures.rejections))
+    }
+
+    #[test]
+    fn crashes_quarantine_a_file_until_it_changes() {
+        let path = temp_path("crashes.quarantine.json");
+        let file = Path::new("broken.pdf");
+        let mut quarantine = Quarantine::load(&path, &Config::default());
+        quarantine.record(file, at(1), Failure::Crashed);
+        quarantine.record(file, at(1), Failure::Crashed);
+        assert!(quarantine.failures(file, at(1)).is_none());
+        quarantine.record(file, at(1), Failure::Crashed);
+        assert_eq!(quarantine.failures(file, at(1)).map(|failures| failures.crashes), Some(3));
+        // A new version of the file gets another chance, and its failures are counted from scratch
+        assert!(quarantine.failures(file, at(2)).is_none());
+        quarantine.record(file, at(2), Failure::Crashed);
+        assert_eq!(counts(&quarantine, file), Some((1, 0)));
+
+        let reloaded = Quarantine::load(&path, &Config::default());
+        assert_eq!(counts(&reloaded, file), Some((1, 0)));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn rejections_take_a_threshold_of_their_own() {
+        let path = temp_path("rejections.quarantine.json");
+        let file = Path::new("broken.docx");
+        let mut quarantine = Quarantine::load(&path, &Config::default());
+        for _ in 0..4 {
+            quarantine.record(file, at(1), Failure::Rejected);
+        }
+        quarantine.record(file, at(1), Failure::Crashed);
+        assert!(quarantine.failures(file, at(1)).is_none());
+        quarantine.record(file, at(1), Failure::Rejected);
+        assert_eq!(quarantine.failures(file, at(1)).map(|failures| (failures.crashes, failures.rejections)), Some((1, 5)));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn quarantine_files_that_count_crashes_still_load() {
+        let path = temp_path("old.quarantine.json");
+        fs::write(&path, r#"{"old.pdf": {"count": 3, "last_modified": {"secs_since_epoch": 1, "nanos_since_epoch": 0}}}"#).unwrap();
+        let quarantine = Quarantine::load(&path, &Config::default());
+        assert_eq!(counts(&quarantine, Path::new("old.pdf")), Some((3, 0)));
+        assert!(quarantine.failures(Path::new("old.pdf"), at(1)).is_some());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn files_that_parse_are_forgotten() {
+        let path = temp_path("forget.quarantine.json");
+        let file = Path::new("fixed.html");
+        let mut quarantine = Quarantine::load(&path, &Config::default());
+        quarantine.record(file, at(1), Failure::Rejected);
+        quarantine.forget(file);
+        assert_eq!(counts(&quarantine, file), None);
+        assert_eq!(counts(&Quarantine::load(&path, &Config::default()), file), None);
+        let _ = fs::remove_file(path);
+    }
+
+    /// Parser of the files with the extension of its name, which goes wrong the way the name says
+    #[derive(Clone, Copy)]
+    enum Faulty {
+        Panics,
+        Hangs,
+        Rejects,
+    }
+
+    impl DocumentParser for Faulty {
+        fn name(&self) -> &'static str {
+            match self {
+                Faulty::Panics => "panics",
+                Faulty::Hangs => "hangs",
+                Faulty::Rejects => "rejects",
+            }
+        }
+
+        fn extensions(&self) -> &'static [&'static str] {
+            match self {
+                Faulty::Panics => &["panics"],
+                Faulty::Hangs => &["hangs"],
+                Faulty::Rejects => &["rejects"],
+            }
+        }
+
+        fn mime_types(&self) -> &'static [&'static str] {
+            &["text/plain"]
+        }
+
+        fn overrides_sniffing(&self) -> bool {
+            true
+        }
+
+        fn parse(&self, _file_path: &Path, _bytes: &[u8], _limits: Limits) -> Result<ParsedDocument, ()> {
+            match self {
+                Faulty::Panics => panic!("the parser gave up"),
+                Faulty::Hangs => {
+                    thread::sleep(Duration::from_secs(10));
+                    Ok(ParsedDocument::default())
+                }
+                Faulty::Rejects => Err(()),
+            }
+        }
+    }
+
+    fn sandbox(name: &str) -> Sandbox {
+        let mut parsers = ParserRegistry::empty();
+        for parser in [Faulty::Panics, Faulty::Hangs, Faulty::Rejects] {
+            parsers.register(Box::new(parser));
+        }
+        let quarantine = Quarantine::load(&temp_path(name), &Config::default());
+        // What stands in for the program parsing a file in a child process rejects every file
+        let isolation = Isolation { program: PathBuf::from("false"), arguments: Vec::new() };
+        let mut sandbox = Sandbox::new(parsers, Config::default(), isolation, false, quarantine);
+        sandbox.timeout = Duration::from_millis(200);
+        sandbox
+    }
+
+    fn parse_fails(sandbox: &mut Sandbox, file_name: &str) -> Option<(u32, u32)> {
+        let file = temp_path(file_name);
+        fs::write(&file, "some text").unwrap();
+        assert!(sandbox.parse(&file, at(1)).is_err());
+        let counts = counts(&sandbox.quarantine, &file);
+        let _ = fs::remove_file(file);
+        counts
+    }
+
+    #[test]
+    fn temporary_files_are_private() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("document.json");
+        write_document(&None, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "null");
+        assert!(write_document(&None, &path).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{symlink, PermissionsExt};
+            assert_eq!(fs::metadata(directory.path()).unwrap().permissions().mode() & 0o777, 0o700);
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+            let target = directory.path().join("target");
+            symlink(&target, directory.path().join("link")).unwrap();
+            assert!(create_new(&directory.path().join("link")).is_err());
+            assert!(!target.exists());
+        }
+        let kept = directory.path().to_path_buf();
+        drop(directory);
+        assert!(!kept.exists());
+    }
+
+    #[test]
+    fn panics_timeouts_and_rejections_are_told_apart() {
+        let mut sandbox = sandbox("sandbox.quarantine.json");
+        assert_eq!(parse_fails(&mut sandbox, "file.panics"), Some((1, 0)));
+        assert_eq!(parse_fails(&mut sandbox, "file.hangs"), Some((1, 0)));
+        assert_eq!(parse_fails(&mut sandbox, "file.rejects"), Some((0, 1)));
+        let _ = fs::remove_file(temp_path("sandbox.quarantine.json"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_are_parsed_in_child_processes_once_too_many_threads_are_stuck() {
+        let mut sandbox = sandbox("stuck.quarantine.json");
+        for i in 0..MAX_STUCK_THREADS {
+            assert_eq!(parse_fails(&mut sandbox, &format!("stuck-{i}.hangs")), Some((1, 0)));
+        }
+        assert_eq!(sandbox.threads.load(Ordering::SeqCst), MAX_STUCK_THREADS);
+        // The parser would panic in a thread, but the child process rejects the file
+        assert_eq!(parse_fails(&mut sandbox, "stuck.panics"), Some((0, 1)));
+        let _ = fs::remove_file(temp_path("stuck.quarantine.json"));
+    }
+}
@@ -1,8 +1,11 @@
 use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
-use std::{io, str};
+use std::{fs, io, str};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use super::config::Config;
 use super::model::*;
+use super::parsers;
 
 fn serve_404(request: Request) -> io::Result<()> {
     request.respond(Response::from_string("404").with_status_code(StatusCode(404)))
@@ -42,7 +45,16 @@ fn serve_api_search(model: Arc<Mutex<Box<dyn Model + Send>>>, mut request: Reque
             return serve_500(request);
         }
     };
-    let json = match serde_json::to_string(&result.iter().take(20).collect::<Vec<_>>()) {
+    // The title of the document, when it has one, is there to be shown instead of its path
+    let mut hits = Vec::new();
+    for (path, rank) in result.iter().take(20) {
+        let title = match model.document_metadata(path) {
+            Ok(mut metadata) => metadata.remove("title"),
+            Err(()) => return serve_500(request),
+        };
+        hits.push((path, rank, title));
+    }
+    let json = match serde_json::to_string(&hits) {
         Ok(json) => json,
         Err(err) => {
             eprintln!("ERROR: could not serialize search result as JSON: {err}", err = err);
@@ -54,6 +66,68 @@ fn serve_api_search(model: Arc<Mutex<Box<dyn Model + Send>>>, mut request: Reque
     return request.respond(response)
 }
 
+/// Serve the file of an indexed document, whose path comes percent-encoded after `/files/`.
+/// The members of archives are extracted from them, only the indexed documents are served.
+fn serve_file(model: Arc<Mutex<Box<dyn Model + Send>>>, config: &Config, request: Request) -> io::Result<()> {
+    let url = request.url();
+    let encoded = url.split_once('?').map_or(url, |(path, _)| path).trim_start_matches("/files/");
+    let Some(path) = percent_decode(encoded) else {
+        return serve_400(request, "could not decode the path of the file")
+    };
+    let metadata = match model.lock().unwrap().document_metadata(Path::new(&path)) {
+        Ok(metadata) => metadata,
+        Err(()) => return serve_500(request),
+    };
+    if metadata.is_empty() {
+        return serve_404(request)
+    }
+    let (file_path, member) = match path.split_once("!/") {
+        Some((file_path, member)) => (file_path, Some(member)),
+        None => (path.as_str(), None),
+    };
+    let bytes = match fs::read(file_path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("ERROR: could not read file {file_path}: {err}");
+            return serve_404(request)
+        }
+    };
+    let bytes = match member {
+        Some(member) => match parsers::extract_member(&bytes, member, config.limits(Path::new(file_path)).max_file_size) {
+            Some(bytes) => bytes,
+            None => {
+                eprintln!("ERROR: could not extract {member} from archive {file_path}");
+                return serve_404(request)
+            }
+        },
+        None => bytes,
+    };
+    let content_type = metadata.get("mime").map_or("application/octet-stream", String::as_str);
+    let content_type_header = Header::from_bytes("Content-Type", content_type).unwrap_or_else(|()| {
+        Header::from_bytes("Content-Type", "application/octet-stream").expect("header is fine")
+    });
+    // The indexed HTML and SVG files are not to run scripts with the access of the search page
+    let sandbox_header = Header::from_bytes("Content-Security-Policy", "sandbox").expect("header is fine");
+    request.respond(Response::from_data(bytes).with_header(content_type_header).with_header(sandbox_header))
+}
+
+/// Decode the `%XX` escapes of a URL path, `None` when they are broken or do not make UTF-8
+fn percent_decode(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
 fn serve_api_stats(model: Arc<Mutex<Box<dyn Model + Send>>>, request: Request) -> io::Result<()> {
     use serde::Serialize;
     #[derive(Serialize)]
@@ -142,7 +216,7 @@ fn serve_api_stats(model: Arc<Mutex<Box<dyn Model + Send>>>, request: Request) -
     request.respond(Response::from_string(json).with_header(content_type_header))
 }
 
-fn serve_request(model: Arc<Mutex<Box<dyn Model + Send>>>, request: Request) -> io::Result<()> {
+fn serve_request(model: Arc<Mutex<Box<dyn Model + Send>>>, config: &Config, request: Request) -> io::Result<()> {
     println!("INFO: Received request! method: {:?}, url: {:?}", request.method(), request.url());
     match (request.method(), request.url()) {
         (Method::Post, "/api/search") => {
@@ -157,19 +231,22 @@ fn serve_request(model: Arc<Mutex<Box<dyn Model + Send>>>, request: Request) ->
         (Method::Get, "/") | (Method::Get, "/index.html") => {
             return serve_bytes(request, include_bytes!("index.html"), "text/html; charset=utf-8")
         }
+        (Method::Get, url) if url.starts_with("/files/") => {
+            return serve_file(model, config, request)
+        }
         _ => {
             return serve_404(request)
         }
     }
 } 
 
-pub fn start(address: &str, model: Arc<Mutex<Box<dyn Model + Send>>>) -> Result<(), ()> {
+pub fn start(address: &str, model: Arc<Mutex<Box<dyn Model + Send>>>, config: Config) -> Result<(), ()> {
     let server = Server::http(&address).map_err(|err| {
         eprintln!("ERROR: could not start HTTP server at {address}: {err}", address = address, err = err);
     })?;
     println!("INFO: HTTP server is running at http://{address}/", address = address);
     for request in server.incoming_requests() {
-        serve_request(Arc::clone(&model), request).map_err(|err| {
+        serve_request(Arc::clone(&model), &config, request).map_err(|err| {
             eprintln!("ERROR: could not serve the response: {err}");
         }).ok();
     }
@@ -1,3 +1,10 @@
 // Have a look at build.rs
 // include!(concat!(env!("OUT_DIR"), "/lang_include.rs"));
-pub mod english_stemmer;
\ No newline at end of file
+pub mod english_stemmer;
+pub mod german_stemmer;
+pub mod french_stemmer;
+pub mod spanish_stemmer;
+pub mod italian_stemmer;
+pub mod portuguese_stemmer;
+pub mod dutch_stemmer;
+pub mod russian_stemmer;
@@ -1,6 +1,11 @@
 pub mod algorithms;
 mod among;
 mod snowball_env;
+mod stemmer;
 
 pub use crate::snowball::among::Among;
 pub use crate::snowball::snowball_env::SnowballEnv;
+pub use crate::snowball::stemmer::Stemmer;
+
+#[cfg(test)]
+mod tests;
@@ -25,10 +25,31 @@ impl<'a> SnowballEnv<'a> {
         }
     }
 
+    /// Env that stems `value` inside `buffer`, so stemming word after word
+    /// with the same buffer does not allocate once it has grown large enough
+    pub fn create_in(mut buffer: String, value: &str) -> SnowballEnv<'static> {
+        buffer.clear();
+        buffer.push_str(value);
+        let len = buffer.len();
+        SnowballEnv {
+            current: Cow::from(buffer),
+            cursor: 0,
+            limit: len as i32,
+            limit_backward: 0,
+            bra: 0,
+            ket: len as i32,
+        }
+    }
+
     pub fn get_current(self) -> Cow<'a, str> {
         self.current
     }
 
+    /// Give back the buffer holding the current string for the next `create_in`
+    pub fn into_buffer(self) -> String {
+        self.current.into_owned()
+    }
+
     pub fn set_current(&mut self, current: &'a str) {
         self.current = Cow::from(current);
     }
@@ -39,14 +60,7 @@ impl<'a> SnowballEnv<'a> {
 
     fn replace_s(&mut self, bra: i32, ket: i32, s: &str) -> i32 {
         let adjustment = s.len() as i32 - (ket - bra);
-        let mut result = String::with_capacity(self.current.len());
-        {
-            let (lhs, _) = self.current.split_at(bra as usize);
-            let (_, rhs) = self.current.split_at(ket as usize);
-            result.push_str(lhs);
-            result.push_str(s);
-            result.push_str(rhs);
-        }
+        self.current.to_mut().replace_range(bra as usize..ket as usize, s);
         // ... not very nice...
         let new_lim = self.limit + adjustment;
         self.limit = new_lim;
@@ -56,7 +70,6 @@ impl<'a> SnowballEnv<'a> {
         } else if self.cursor > bra {
             self.cursor = bra
         }
-        self.current = Cow::from(result);
         adjustment
     }
 
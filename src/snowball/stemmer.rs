@@ -0,0 +1,75 @@
+use std::borrow::Cow;
+use std::mem;
+use crate::snowball::SnowballEnv;
+use crate::snowball::algorithms::*;
+
+/// Snowball algorithms available in this crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stemmer {
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Portuguese,
+    Dutch,
+    Russian,
+}
+
+impl Stemmer {
+    pub const ALL: &'static [Stemmer] = &[
+        Stemmer::English,
+        Stemmer::German,
+        Stemmer::French,
+        Stemmer::Spanish,
+        Stemmer::Italian,
+        Stemmer::Portuguese,
+        Stemmer::Dutch,
+        Stemmer::Russian,
+    ];
+
+    /// ISO 639-1 code of the language the algorithm is written for
+    pub fn code(&self) -> &'static str {
+        match self {
+            Stemmer::English => "en",
+            Stemmer::German => "de",
+            Stemmer::French => "fr",
+            Stemmer::Spanish => "es",
+            Stemmer::Italian => "it",
+            Stemmer::Portuguese => "pt",
+            Stemmer::Dutch => "nl",
+            Stemmer::Russian => "ru",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|stemmer| stemmer.code().eq_ignore_ascii_case(code))
+    }
+
+    pub fn algorithm(&self) -> fn(&mut SnowballEnv) -> bool {
+        match self {
+            Stemmer::English => english_stemmer::stem,
+            Stemmer::German => german_stemmer::stem,
+            Stemmer::French => french_stemmer::stem,
+            Stemmer::Spanish => spanish_stemmer::stem,
+            Stemmer::Italian => italian_stemmer::stem,
+            Stemmer::Portuguese => portuguese_stemmer::stem,
+            Stemmer::Dutch => dutch_stemmer::stem,
+            Stemmer::Russian => russian_stemmer::stem,
+        }
+    }
+
+    /// Stem a lowercase word
+    pub fn stem<'a>(&self, word: &'a str) -> Cow<'a, str> {
+        let mut env = SnowballEnv::create(word);
+        self.algorithm()(&mut env);
+        env.get_current()
+    }
+
+    /// Stem a lowercase word into `buffer`, reusing its allocation
+    pub fn stem_into(&self, word: &str, buffer: &mut String) {
+        let mut env = SnowballEnv::create_in(mem::take(buffer), word);
+        self.algorithm()(&mut env);
+        *buffer = env.into_buffer();
+    }
+}
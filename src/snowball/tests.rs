@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::snowball::{SnowballEnv, Stemmer};
+
+fn fixtures_dir(stemmer: Stemmer) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/snowball").join(stemmer.code())
+}
+
+fn assert_conforms(stemmer: Stemmer) {
+    let dir = fixtures_dir(stemmer);
+    let read = |name: &str| {
+        let path = dir.join(name);
+        fs::read_to_string(&path).unwrap_or_else(|err| panic!("could not read {path}: {err}", path = path.display()))
+    };
+    let voc = read("voc.txt");
+    let output = read("output.txt");
+    assert_eq!(voc.lines().count(), output.lines().count(), "{stemmer:?}: voc.txt and output.txt are not aligned");
+    let mut buffer = String::new();
+    let mismatches = voc.lines().zip(output.lines())
+        .filter_map(|(word, expected)| {
+            let actual = stemmer.stem(word);
+            stemmer.stem_into(word, &mut buffer);
+            if actual != expected {
+                Some(format!("{word} -> {actual} (expected {expected})"))
+            } else if buffer != expected {
+                Some(format!("{word} -> {buffer} with a reused buffer (expected {expected})"))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    assert!(mismatches.is_empty(), "{stemmer:?}: {n} mismatches:\n{list}", n = mismatches.len(), list = mismatches.join("\n"));
+}
+
+#[test]
+fn every_stemmer_has_fixtures() {
+    // Dutch waits for its vocabulary, see dutch_conforms
+    for stemmer in Stemmer::ALL.iter().filter(|stemmer| **stemmer != Stemmer::Dutch) {
+        assert!(fixtures_dir(*stemmer).join("voc.txt").exists(), "{stemmer:?} has no vocabulary in tests/fixtures/snowball");
+    }
+}
+
+#[test]
+fn english_conforms() {
+    assert_conforms(Stemmer::English);
+}
+
+#[test]
+fn german_conforms() {
+    assert_conforms(Stemmer::German);
+}
+
+#[test]
+fn french_conforms() {
+    assert_conforms(Stemmer::French);
+}
+
+#[test]
+fn spanish_conforms() {
+    assert_conforms(Stemmer::Spanish);
+}
+
+#[test]
+fn italian_conforms() {
+    assert_conforms(Stemmer::Italian);
+}
+
+#[test]
+fn portuguese_conforms() {
+    assert_conforms(Stemmer::Portuguese);
+}
+
+#[test]
+#[ignore = "tests/fixtures/snowball/nl needs voc.txt and output.txt from dutch/ of snowball-data"]
+fn dutch_conforms() {
+    assert_conforms(Stemmer::Dutch);
+}
+
+#[test]
+fn russian_conforms() {
+    assert_conforms(Stemmer::Russian);
+}
+
+#[test]
+fn env_eq_s_moves_cursor_past_match() {
+    let mut env = SnowballEnv::create("straße");
+    assert!(env.eq_s("stra"));
+    assert_eq!(env.cursor, 4);
+    assert!(!env.eq_s("ss"));
+    assert!(env.eq_s("ß"));
+    assert_eq!(env.cursor, 6);
+}
+
+#[test]
+fn env_eq_s_b_moves_cursor_before_match() {
+    let mut env = SnowballEnv::create("running");
+    env.cursor = env.limit;
+    assert!(env.eq_s_b("ing"));
+    assert_eq!(env.cursor, 4);
+    assert!(!env.eq_s_b("ing"));
+}
+
+#[test]
+fn env_slice_from_replaces_between_bra_and_ket() {
+    let mut env = SnowballEnv::create("ponies");
+    env.bra = 3;
+    env.ket = 6;
+    env.cursor = 6;
+    assert!(env.slice_from("y"));
+    assert_eq!(env.limit, 4);
+    assert_eq!(env.cursor, 4);
+    assert_eq!(env.get_current(), "pony");
+}
+
+#[test]
+fn env_insert_shifts_bra_and_ket() {
+    let mut env = SnowballEnv::create("abc");
+    env.bra = 1;
+    env.ket = 3;
+    env.insert(1, 1, "xy");
+    assert_eq!((env.bra, env.ket), (3, 5));
+    assert_eq!(env.get_current(), "axybc");
+}
+
+#[test]
+fn env_hop_counts_chars_not_bytes() {
+    let mut env = SnowballEnv::create("жук");
+    assert!(env.hop(2));
+    assert_eq!(env.cursor, 4);
+    assert!(!env.hop(2));
+    assert_eq!(env.cursor, 4);
+    assert!(env.hop_back(1));
+    assert_eq!(env.cursor, 2);
+}